@@ -0,0 +1,387 @@
+// ████████ ██   ██  █████  ██       █████  ███    ███ ██    ██ ███████ 
+//    ██    ██   ██ ██   ██ ██      ██   ██ ████  ████ ██    ██ ██      
+//    ██    ███████ ███████ ██      ███████ ██ ████ ██ ██    ██ ███████ 
+//    ██    ██   ██ ██   ██ ██      ██   ██ ██  ██  ██ ██    ██      ██ 
+//    ██    ██   ██ ██   ██ ███████ ██   ██ ██      ██  ██████  ███████                                                                             
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+
+// Benchmark runner behind ThalamusNodeStats
+// Each test gets warmup runs, then timed repetitions summarised as median, p95 and stddev.
+// Fixtures and settings come from /opt/thalamus/benchmark.json when it exists; the fixture
+// id is stored with the results so only runs on the same fixtures are compared.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use serde::{Serialize, Deserialize};
+use sha2::{Sha256, Digest};
+
+use crate::{LlamaModel, WhisperModel};
+
+pub const BENCH_CONFIG_PATH: &str = "/opt/thalamus/benchmark.json";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct BenchConfig {
+    /// Untimed runs before measuring; a failed warmup skips the test
+    pub warmup: u32,
+    pub repetitions: u32,
+    /// Whisper sizes to run, smallest first; the first failure stops larger ones
+    pub whisper_models: Vec<WhisperModel>,
+    pub llama_models: Vec<LlamaModel>,
+    pub fixtures: Fixtures,
+    pub timeouts: Timeouts,
+}
+impl Default for BenchConfig {
+    fn default() -> BenchConfig {
+        BenchConfig {
+            warmup: 1,
+            repetitions: 3,
+            whisper_models: WhisperModel::ALL.to_vec(),
+            llama_models: vec![LlamaModel::Llama7B],
+            fixtures: Fixtures::default(),
+            timeouts: Timeouts::default(),
+        }
+    }
+}
+impl BenchConfig {
+    /// Reads benchmark.json, falling back to the defaults
+    pub fn load() -> BenchConfig {
+        match std::fs::read_to_string(BENCH_CONFIG_PATH) {
+            Ok(data) => match serde_json::from_str(data.as_str()) {
+                Ok(config) => config,
+                Err(e) => {
+                    log::error!("Unable to parse {}, using defaults: {}", BENCH_CONFIG_PATH, e);
+                    BenchConfig::default()
+                }
+            },
+            Err(_) => BenchConfig::default(),
+        }
+    }
+}
+
+/// Inputs every node is benchmarked with
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Fixtures {
+    /// Bump whenever a fixture changes
+    pub version: u32,
+    pub wav: String,
+    pub jpg: String,
+    pub prompt: String,
+    pub tts_text: String,
+    pub tts_voice: String,
+}
+impl Default for Fixtures {
+    fn default() -> Fixtures {
+        Fixtures {
+            version: 1,
+            wav: format!("/opt/thalamus/test.wav"),
+            jpg: format!("/opt/thalamus/test.jpg"),
+            prompt: format!("Tell me about Abraham Lincoln."),
+            tts_text: format!("hello, my name is sam."),
+            tts_voice: format!("coqui-tts:en_ljspeech"),
+        }
+    }
+}
+impl Fixtures {
+    /// Version plus a hash of the fixture contents, e.g. "v1-3fa2c09e1b7d"
+    pub fn id(&self) -> String {
+        let mut hasher = Sha256::new();
+        for path in [&self.wav, &self.jpg] {
+            match std::fs::read(path) {
+                Ok(data) => hasher.update(&data),
+                Err(e) => log::warn!("Unable to read benchmark fixture {}: {}", path, e),
+            }
+        }
+        for text in [&self.prompt, &self.tts_text, &self.tts_voice] {
+            hasher.update(text.as_bytes());
+        }
+        let hash = format!("{:x}", hasher.finalize());
+        return format!("v{}-{}", self.version, &hash[..12]);
+    }
+}
+
+/// Per-test timeouts in seconds, sized to how long each service really takes
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Timeouts {
+    pub tts: u64,
+    pub srgan: u64,
+    pub yolo: u64,
+    /// Keyed by model, e.g. "tiny"
+    pub whisper: BTreeMap<String, u64>,
+    /// Keyed by model, e.g. "7B"
+    pub llama: BTreeMap<String, u64>,
+}
+impl Default for Timeouts {
+    fn default() -> Timeouts {
+        let mut whisper = BTreeMap::new();
+        whisper.insert(WhisperModel::Tiny.to_string(), 120);
+        whisper.insert(WhisperModel::Base.to_string(), 180);
+        whisper.insert(WhisperModel::Medium.to_string(), 600);
+        whisper.insert(WhisperModel::Large.to_string(), 1200);
+        let mut llama = BTreeMap::new();
+        llama.insert(LlamaModel::Llama7B.to_string(), 300);
+        llama.insert(LlamaModel::Llama13B.to_string(), 600);
+        llama.insert(LlamaModel::Llama30B.to_string(), 1200);
+        llama.insert(LlamaModel::Llama65B.to_string(), 2400);
+        Timeouts {
+            tts: 60,
+            srgan: 300,
+            yolo: 120,
+            whisper: whisper,
+            llama: llama,
+        }
+    }
+}
+impl Timeouts {
+    pub fn tts(&self) -> Duration {
+        Duration::from_secs(self.tts)
+    }
+
+    pub fn srgan(&self) -> Duration {
+        Duration::from_secs(self.srgan)
+    }
+
+    pub fn yolo(&self) -> Duration {
+        Duration::from_secs(self.yolo)
+    }
+
+    pub fn whisper(&self, model: WhisperModel) -> Duration {
+        self.lookup(&self.whisper, model.as_str())
+    }
+
+    /// Visual wav rendering runs whisper and then ffmpeg
+    pub fn vwav(&self, model: WhisperModel) -> Duration {
+        self.whisper(model) * 2
+    }
+
+    pub fn llama(&self, model: LlamaModel) -> Duration {
+        self.lookup(&self.llama, model.as_str())
+    }
+
+    fn lookup(&self, timeouts: &BTreeMap<String, u64>, model: &str) -> Duration {
+        match timeouts.get(model) {
+            Some(secs) => Duration::from_secs(*secs),
+            None => crate::client::DEFAULT_TIMEOUT,
+        }
+    }
+}
+
+/// Summary of one test's timed runs, in milliseconds
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Measurement {
+    pub runs: u32,
+    pub failures: u32,
+    pub median: i64,
+    pub p95: i64,
+    pub mean: f64,
+    pub stddev: f64,
+}
+impl Measurement {
+    /// None when no run succeeded
+    pub fn from_samples(mut samples: Vec<i64>, failures: u32) -> Option<Measurement> {
+        if samples.is_empty() {
+            return None;
+        }
+        samples.sort();
+        let n = samples.len();
+        let median = if n % 2 == 0 { (samples[n / 2 - 1] + samples[n / 2]) / 2 } else { samples[n / 2] };
+        // Nearest-rank percentile
+        let p95 = samples[((n as f64 * 0.95).ceil() as usize).max(1) - 1];
+        let mean = samples.iter().sum::<i64>() as f64 / n as f64;
+        let variance = samples.iter().map(|s| (*s as f64 - mean).powi(2)).sum::<f64>() / n as f64;
+        Some(Measurement {
+            runs: n as u32 + failures,
+            failures: failures,
+            median: median,
+            p95: p95,
+            mean: mean,
+            stddev: variance.sqrt(),
+        })
+    }
+}
+
+/// Runs one test with warmup and repetitions, checking `cancelled` before each run; a
+/// cancelled test returns None
+pub fn measure<F>(pid: &str, label: &str, config: &BenchConfig, cancelled: &dyn Fn() -> bool, test: F) -> Option<Measurement>
+where
+    F: Fn() -> Result<Option<i64>, std::sync::mpsc::RecvTimeoutError>,
+{
+    let run = || match test() {
+        Ok(time_elapsed) => time_elapsed,
+        Err(e) => {
+            log::error!("{}: {} test timed out: {:?}", pid, label, e);
+            None
+        }
+    };
+
+    for i in 0..config.warmup {
+        if cancelled() {
+            log::warn!("{}: {} cancelled during warmup", pid, label);
+            return None;
+        }
+        if run().is_none() {
+            log::warn!("{}: {} warmup {} failed, skipping test", pid, label, i + 1);
+            return None;
+        }
+    }
+
+    let mut samples = Vec::new();
+    let mut failures = 0;
+    for _ in 0..config.repetitions.max(1) {
+        if cancelled() {
+            log::warn!("{}: {} cancelled after {} runs", pid, label, samples.len() as u32 + failures);
+            return None;
+        }
+        match run() {
+            Some(time_elapsed) => samples.push(time_elapsed),
+            None => failures += 1,
+        }
+    }
+
+    let measurement = Measurement::from_samples(samples, failures);
+    log::info!("{}: {} test complete: {:?}", pid, label, measurement);
+    return measurement;
+}
+
+/// One benchmark run of one test, as a point in a trend
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TrendPoint {
+    pub ran_at: i64,
+    pub version: String,
+    pub median: i64,
+    pub p95: i64,
+}
+
+/// How a test's median moved across benchmark runs, oldest point first
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Trend {
+    pub points: Vec<TrendPoint>,
+    /// Percent change of the latest median against the first, negative is faster
+    pub change: Option<f64>,
+}
+
+/// Reply of /api/nodex/{pid}/stats
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StatsReply {
+    pub pid: String,
+    pub stats: crate::ThalamusNodeStats,
+    pub history: Vec<crate::storage::BenchmarkRecord>,
+    /// Per test, e.g. "whisper:base", over runs on the current fixtures
+    pub trend: BTreeMap<String, Trend>,
+}
+
+/// Builds per-test trends from history (newest first, as the store returns it),
+/// leaving out runs on other fixtures than the newest
+pub fn trends(history: &[crate::storage::BenchmarkRecord]) -> BTreeMap<String, Trend> {
+    let mut trends: BTreeMap<String, Trend> = BTreeMap::new();
+    let fixtures = match history.first() {
+        Some(latest) => latest.stats.fixtures.clone(),
+        None => return trends,
+    };
+
+    for record in history.iter().rev().filter(|record| record.stats.fixtures == fixtures) {
+        for (key, measurement) in &record.stats.benchmarks {
+            trends.entry(key.clone()).or_insert(Trend { points: Vec::new(), change: None }).points.push(TrendPoint {
+                ran_at: record.ran_at,
+                version: record.version.clone(),
+                median: measurement.median,
+                p95: measurement.p95,
+            });
+        }
+    }
+
+    for trend in trends.values_mut() {
+        match (trend.points.first(), trend.points.last()) {
+            (Some(first), Some(last)) if trend.points.len() > 1 && first.median > 0 => {
+                trend.change = Some((last.median - first.median) as f64 / first.median as f64 * 100.0);
+            },
+            _ => {}
+        }
+    }
+    return trends;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn odd_sample_count() {
+        let m = Measurement::from_samples(vec![30, 10, 20], 0).unwrap();
+        assert_eq!(m.runs, 3);
+        assert_eq!(m.failures, 0);
+        assert_eq!(m.median, 20);
+        assert_eq!(m.p95, 30);
+        assert!(close(m.mean, 20.0));
+        assert!(close(m.stddev, (200.0f64 / 3.0).sqrt()));
+    }
+
+    #[test]
+    fn even_sample_count() {
+        let m = Measurement::from_samples(vec![40, 10, 30, 20], 1).unwrap();
+        assert_eq!(m.runs, 5);
+        assert_eq!(m.failures, 1);
+        assert_eq!(m.median, 25);
+        assert_eq!(m.p95, 40);
+        assert!(close(m.mean, 25.0));
+        assert!(close(m.stddev, 125.0f64.sqrt()));
+
+        // Integer milliseconds, so an odd midpoint rounds down
+        assert_eq!(Measurement::from_samples(vec![10, 11], 0).unwrap().median, 10);
+    }
+
+    #[test]
+    fn single_sample() {
+        let m = Measurement::from_samples(vec![42], 2).unwrap();
+        assert_eq!(m.runs, 3);
+        assert_eq!(m.failures, 2);
+        assert_eq!(m.median, 42);
+        assert_eq!(m.p95, 42);
+        assert!(close(m.mean, 42.0));
+        assert!(close(m.stddev, 0.0));
+    }
+
+    #[test]
+    fn all_failures() {
+        assert_eq!(Measurement::from_samples(Vec::new(), 3), None);
+        assert_eq!(Measurement::from_samples(Vec::new(), 0), None);
+    }
+
+    #[test]
+    fn p95_is_nearest_rank() {
+        let samples: Vec<i64> = (1..=20).collect();
+        assert_eq!(Measurement::from_samples(samples, 0).unwrap().p95, 19);
+        let samples: Vec<i64> = (1..=100).rev().collect();
+        assert_eq!(Measurement::from_samples(samples, 0).unwrap().p95, 95);
+    }
+
+    #[test]
+    fn measure_stops_when_cancelled() {
+        let config = BenchConfig::default();
+        let runs = std::cell::Cell::new(0);
+        let test = || { runs.set(runs.get() + 1); Ok(Some(10)) };
+
+        assert!(measure("pid", "test", &config, &|| false, test).is_some());
+        assert_eq!(runs.get(), config.warmup + config.repetitions);
+
+        // Cancelled before the first run
+        runs.set(0);
+        assert!(measure("pid", "test", &config, &|| true, test).is_none());
+        assert_eq!(runs.get(), 0);
+
+        // Cancelled between repetitions
+        runs.set(0);
+        assert!(measure("pid", "test", &config, &|| runs.get() >= 2, test).is_none());
+        assert_eq!(runs.get(), 2);
+    }
+}
@@ -0,0 +1,187 @@
+// ████████ ██   ██  █████  ██       █████  ███    ███ ██    ██ ███████ 
+//    ██    ██   ██ ██   ██ ██      ██   ██ ████  ████ ██    ██ ██      
+//    ██    ███████ ███████ ██      ███████ ██ ████ ██ ██    ██ ███████ 
+//    ██    ██   ██ ██   ██ ██      ██   ██ ██  ██  ██ ██    ██      ██ 
+//    ██    ██   ██ ██   ██ ███████ ██   ██ ██      ██  ██████  ███████                                                                             
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+
+// Command-line client for the mesh
+// thalamus transcribe file.wav --model base, thalamus chat "prompt", thalamus nodes, ...
+// Calls go through ThalamusClient failover, or to one node when --node is given.
+
+use std::path::PathBuf;
+
+use clap::Subcommand;
+
+use crate::client::Input;
+use crate::{ClientError, LlamaModel, ThalamusClient, ThalamusNode, WhisperFormat, WhisperModel};
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Transcribe an audio file with whisper
+    Transcribe {
+        file: PathBuf,
+        #[arg(long, default_value = "base")]
+        model: WhisperModel,
+        #[arg(long)]
+        language: Option<String>,
+        /// txt, srt or vtt
+        #[arg(long, default_value = "txt")]
+        format: WhisperFormat,
+    },
+    /// Complete a prompt with llama
+    Chat {
+        prompt: String,
+        #[arg(long, default_value = "7B")]
+        model: LlamaModel,
+    },
+    /// Speak text, writing a wav file
+    Say {
+        text: String,
+        #[arg(long, default_value = "coqui-tts:en_ljspeech")]
+        voice: String,
+        #[arg(short, long, default_value = "say.wav")]
+        output: PathBuf,
+    },
+    /// Upscale an image with SRGAN
+    Upscale {
+        image: PathBuf,
+        /// Defaults to SRGAN_<image name>
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Run YOLOv7 object detection on an image
+    Detect {
+        image: PathBuf,
+    },
+    /// List known nodes
+    Nodes,
+}
+
+/// Runs a CLI command, returning the process exit code
+pub async fn run(command: Command, args: crate::Args) -> i32 {
+    match execute(command, args).await {
+        Ok(_) => 0,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            1
+        }
+    }
+}
+
+async fn execute(command: Command, args: crate::Args) -> Result<(), ClientError> {
+    match crate::storage::init(args.storage.as_str()) {
+        Ok(_) => {},
+        Err(e) => return Err(ClientError::Io(std::io::Error::new(std::io::ErrorKind::Other, format!("{}", e)))),
+    }
+    let client = mesh(args.node.as_deref()).await?;
+
+    match command {
+        Command::Transcribe { file, model, language, format } => {
+            let reply = match (language, format) {
+                // Plain transcripts get mesh failover, the rest go to the best node directly
+                (None, WhisperFormat::Txt) => client.async_transcribe(file, model).await?,
+                (language, format) => {
+                    let node = best_whisper(&client, model)?;
+                    let mut builder = node.whisper(model).format(format);
+                    match language {
+                        Some(language) => builder = builder.language(language.as_str()),
+                        None => {}
+                    }
+                    builder.async_transcribe(file).await?
+                }
+            };
+            println!("{}", reply.text);
+        },
+        Command::Chat { prompt, model } => {
+            println!("{}", client.async_chat(prompt, model).await?);
+        },
+        Command::Say { text, voice, output } => {
+            let wav = client.async_say(text, voice.as_str()).await?;
+            std::fs::write(&output, wav)?;
+            println!("{}", output.display());
+        },
+        Command::Upscale { image, output } => {
+            let output = match output {
+                Some(output) => output,
+                None => {
+                    let input = Input::from(image.clone());
+                    let name = match image.file_name() {
+                        Some(name) => name.to_string_lossy().to_string(),
+                        None => format!("image.{}", input.extension()),
+                    };
+                    PathBuf::from(format!("SRGAN_{}", name))
+                }
+            };
+            let upscaled = client.async_upscale(image).await?;
+            std::fs::write(&output, upscaled)?;
+            println!("{}", output.display());
+        },
+        Command::Detect { image } => {
+            println!("{}", client.async_detect(image).await?.text);
+        },
+        Command::Nodes => {
+            println!("{:<20} {:<24} {:<10} {:<8} {:<8} {}", "PID", "ADDRESS", "VERSION", "ONLINE", "ROUTE", "CAPABILITIES");
+            for node in &client.nodes {
+                let route = if node.p2p_only { if node.relayed { "relay" } else { "p2p" } } else { "http" };
+                let capabilities = match &node.capablities {
+                    Some(capabilities) => capabilities.iter().map(|c| c.tag.clone()).collect::<Vec<String>>().join(","),
+                    None => format!("-"),
+                };
+                println!("{:<20} {:<24} {:<10} {:<8} {:<8} {}", node.pid, format!("{}:{}", node.ip_address, node.port), node.version, node.is_online, route, capabilities);
+            }
+        },
+    }
+    Ok(())
+}
+
+// The saved mesh, or just the forced node
+async fn mesh(node: Option<&str>) -> Result<ThalamusClient, ClientError> {
+    let saved = crate::storage::store().load()?;
+
+    match node {
+        Some(node) => {
+            let mut forced = match saved.as_ref().and_then(|client| client.nodes.iter().find(|n| n.pid == node).cloned()) {
+                Some(known) => known,
+                None => {
+                    let (host, port) = match node.rsplit_once(':') {
+                        Some((host, port)) => match port.parse::<u16>() {
+                            Ok(port) => (host.to_string(), port),
+                            Err(_) => return Err(ClientError::Decode(format!("bad --node port: {}", port))),
+                        },
+                        None => (node.to_string(), 8050),
+                    };
+                    let version = crate::async_fetch_version(host.as_str(), port).await?;
+                    ThalamusNode::new(version.pid, version.version, host, port)
+                }
+            };
+            // Forced means forced: skip the online and capability filters
+            forced.is_online = true;
+            forced.capablities = None;
+            let mut client = ThalamusClient::new();
+            client.nodes.push(forced);
+            return Ok(client);
+        },
+        None => {
+            let mut client = match saved {
+                Some(client) => client,
+                None => return Err(ClientError::NotCapable(format!("no known nodes, pass --node host:port"))),
+            };
+            // The swarm isn't running in the CLI, so only http nodes are reachable
+            client.nodes.retain(|node| !node.p2p_only);
+            return Ok(client);
+        }
+    }
+}
+
+fn best_whisper(client: &ThalamusClient, model: WhisperModel) -> Result<ThalamusNode, ClientError> {
+    let capability = format!("whisper:{}", model);
+    let nodes = client.candidates(capability.as_str(), |node| node.stats.median(capability.as_str()));
+    match nodes.into_iter().next() {
+        Some(node) => Ok(node),
+        None => Err(ClientError::NotCapable(format!("no available node provides {}", capability))),
+    }
+}
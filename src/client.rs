@@ -0,0 +1,172 @@
+// ████████ ██   ██  █████  ██       █████  ███    ███ ██    ██ ███████ 
+//    ██    ██   ██ ██   ██ ██      ██   ██ ████  ████ ██    ██ ██      
+//    ██    ███████ ███████ ██      ███████ ██ ████ ██ ██    ██ ███████ 
+//    ██    ██   ██ ██   ██ ██      ██   ██ ██  ██  ██ ██    ██      ██ 
+//    ██    ██   ██ ██   ██ ███████ ██   ██ ██      ██  ██████  ███████                                                                             
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+
+// Async client for ThalamusNode
+// Every call takes its own timeout. Cancel a call by dropping its future (e.g. from tokio::select!).
+
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::p2p::infer::InferRequest;
+use crate::thalamus::services::llama::LlamaModel;
+use crate::thalamus::services::whisper::WhisperModel;
+use crate::{STTReply, ThalamusNode};
+
+pub mod builder;
+pub mod error;
+pub mod input;
+pub mod mesh;
+pub use builder::{LlamaBuilder, TtsBuilder, WhisperBuilder, DEFAULT_TIMEOUT};
+pub use error::{ClientError, Retry};
+pub use input::Input;
+pub use mesh::MeshPolicy;
+
+use error::check;
+
+// One pooled client per node, keyed by pid, shared by every clone of that node
+static HTTP_CLIENTS: OnceLock<Mutex<HashMap<String, reqwest::Client>>> = OnceLock::new();
+
+/// Returns the pooled http client for a node, building it on first use
+pub fn http_client(node: &ThalamusNode) -> Result<reqwest::Client, ClientError> {
+    let mut clients = HTTP_CLIENTS.get_or_init(|| Mutex::new(HashMap::new())).lock().unwrap();
+    match clients.get(&node.pid) {
+        Some(client) => return Ok(client.clone()),
+        None => {}
+    }
+
+    let client = reqwest::Client::builder()
+        .connect_timeout(Duration::from_secs(10))
+        .pool_idle_timeout(Duration::from_secs(90))
+        .build()?;
+    clients.insert(node.pid.clone(), client.clone());
+    std::mem::drop(clients);
+    return Ok(client);
+}
+
+// Blocking twins of the pooled clients; reqwest::blocking clients run their own runtime
+static BLOCKING_HTTP_CLIENTS: OnceLock<Mutex<HashMap<String, reqwest::blocking::Client>>> = OnceLock::new();
+
+/// Returns the pooled blocking http client for a node, building it on first use.
+/// Set each request's timeout on the request; the client only bounds connecting.
+pub fn blocking_http_client(node: &ThalamusNode) -> Result<reqwest::blocking::Client, ClientError> {
+    let mut clients = BLOCKING_HTTP_CLIENTS.get_or_init(|| Mutex::new(HashMap::new())).lock().unwrap();
+    match clients.get(&node.pid) {
+        Some(client) => return Ok(client.clone()),
+        None => {}
+    }
+
+    let client = reqwest::blocking::Client::builder()
+        .connect_timeout(Duration::from_secs(10))
+        .pool_idle_timeout(Duration::from_secs(90))
+        .timeout(None)
+        .build()?;
+    clients.insert(node.pid.clone(), client.clone());
+    std::mem::drop(clients);
+    return Ok(client);
+}
+
+async fn p2p_infer(peer_id: String, request: InferRequest, timeout: Duration) -> Result<crate::p2p::infer::InferResponse, ClientError> {
+    match tokio::time::timeout(timeout, crate::p2p::async_infer(peer_id.as_str(), request)).await {
+        Ok(response) => response,
+        Err(_) => Err(ClientError::Timeout),
+    }
+}
+
+impl ThalamusNode {
+
+    fn url(&self, path: &str) -> String {
+        format!("http://{}:{}{}", self.ip_address.clone(), self.port.clone(), path)
+    }
+
+    pub fn whisper(&self, model: WhisperModel) -> WhisperBuilder {
+        WhisperBuilder::new(self, model)
+    }
+
+    pub fn llama(&self, model: LlamaModel) -> LlamaBuilder {
+        LlamaBuilder::new(self, model)
+    }
+
+    /// voice is an opentts voice (e.g. coqui-tts:en_ljspeech) or opensamfoundation
+    pub fn tts(&self, voice: &str) -> TtsBuilder {
+        TtsBuilder::new(self, voice)
+    }
+
+    pub async fn async_yolov7<I: Into<Input>>(&self, image: I, timeout: Duration) -> Result<STTReply, ClientError> {
+        let image = image.into();
+        if let Some(peer_id) = self.via_p2p() {
+            let request = InferRequest::Yolov7 { image: image.async_into_vec().await? };
+            return p2p_infer(peer_id, request, timeout).await?.into_json();
+        }
+
+        let file_name = format!("image.{}", image.extension());
+        let form = reqwest::multipart::Form::new().part("image_file", image.into_async_part(file_name).await?);
+
+        let span = crate::trace::client(format!("POST /api/services/image/yolo/v7"), self.pid.as_str());
+        let response = http_client(self)?.post(self.url("/api/services/image/yolo/v7"))
+        .timeout(timeout)
+        .multipart(form)
+        .header("traceparent", span.traceparent())
+        .send().await?;
+
+        return Ok(check(response).await?.json().await?);
+    }
+
+    pub async fn async_srgan<I: Into<Input>>(&self, image: I, timeout: Duration) -> Result<Vec<u8>, ClientError> {
+        let image = image.into();
+
+        // The server stores uploads under this name, so keep it unique
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as i64;
+
+        let new_file_name = format!("{}.{}", timestamp, image.extension());
+
+        if let Some(peer_id) = self.via_p2p() {
+            let request = InferRequest::Srgan { filename: new_file_name, image: image.async_into_vec().await? };
+            return p2p_infer(peer_id, request, timeout).await?.into_bytes();
+        }
+
+        let form = reqwest::multipart::Form::new().text("filename", new_file_name.clone()).part("input_file", image.into_async_part(new_file_name).await?);
+
+        let span = crate::trace::client(format!("POST /api/services/image/srgan"), self.pid.as_str());
+        let response = http_client(self)?.post(self.url("/api/services/image/srgan"))
+        .timeout(timeout)
+        .multipart(form)
+        .header("traceparent", span.traceparent())
+        .send().await?;
+
+        let bytes = check(response).await?.bytes().await?;
+
+        return Ok(bytes.to_vec());
+    }
+
+    pub async fn async_hardware(&self, timeout: Duration) -> Result<crate::thalamus::hardware::Hardware, ClientError> {
+        if let Some(peer_id) = self.via_p2p() {
+            return p2p_infer(peer_id, InferRequest::Hardware, timeout).await?.into_json();
+        }
+
+        let span = crate::trace::client(format!("GET /api/thalamus/hardware"), self.pid.as_str());
+        let response = http_client(self)?.get(self.url("/api/thalamus/hardware"))
+        .timeout(timeout)
+        .header("traceparent", span.traceparent())
+        .send().await?;
+
+        return Ok(check(response).await?.json().await?);
+    }
+
+    pub async fn async_nodex(&self, timeout: Duration) -> Result<Vec<ThalamusNode>, ClientError> {
+        let span = crate::trace::client(format!("GET /api/nodex"), self.pid.as_str());
+        let response = http_client(self)?.get(self.url("/api/nodex"))
+        .timeout(timeout)
+        .header("traceparent", span.traceparent())
+        .send().await?;
+
+        return Ok(check(response).await?.json().await?);
+    }
+}
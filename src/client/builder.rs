@@ -0,0 +1,303 @@
+// ████████ ██   ██  █████  ██       █████  ███    ███ ██    ██ ███████ 
+//    ██    ██   ██ ██   ██ ██      ██   ██ ████  ████ ██    ██ ██      
+//    ██    ███████ ███████ ██      ███████ ██ ████ ██ ██    ██ ███████ 
+//    ██    ██   ██ ██   ██ ██      ██   ██ ██  ██  ██ ██    ██      ██ 
+//    ██    ██   ██ ██   ██ ███████ ██   ██ ██      ██  ██████  ███████                                                                             
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+
+// One request builder per service: node.whisper(WhisperModel::Base).language("en").timeout(..).transcribe(path)
+// Each builder has a blocking and an async_ terminal.
+
+use std::time::Duration;
+
+use super::error::{check, check_blocking};
+use super::{ClientError, Input};
+use crate::p2p::infer::InferRequest;
+use crate::thalamus::services::llama::LlamaModel;
+use crate::thalamus::services::whisper::{STTReply, WhisperFormat, WhisperModel};
+use crate::ThalamusNode;
+
+// Used when a call doesn't set its own timeout
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(600);
+
+#[derive(Debug, Clone)]
+pub struct WhisperBuilder<'a> {
+    node: &'a ThalamusNode,
+    model: WhisperModel,
+    language: Option<String>,
+    format: WhisperFormat,
+    timeout: Duration,
+}
+impl<'a> WhisperBuilder<'a> {
+    pub fn new(node: &'a ThalamusNode, model: WhisperModel) -> WhisperBuilder<'a> {
+        WhisperBuilder {
+            node: node,
+            model: model,
+            language: None,
+            format: WhisperFormat::Txt,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Spoken language (e.g. en, de, auto), defaults to en
+    pub fn language(mut self, language: &str) -> WhisperBuilder<'a> {
+        self.language = Some(language.to_string());
+        self
+    }
+
+    /// Transcript format, ignored by vwav
+    pub fn format(mut self, format: WhisperFormat) -> WhisperBuilder<'a> {
+        self.format = format;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> WhisperBuilder<'a> {
+        self.timeout = timeout;
+        self
+    }
+
+    fn form(&self) -> reqwest::blocking::multipart::Form {
+        let mut form = reqwest::blocking::multipart::Form::new().text("method", self.model.as_str());
+        if let Some(language) = &self.language {
+            form = form.text("language", language.clone());
+        }
+        form
+    }
+
+    fn async_form(&self) -> reqwest::multipart::Form {
+        let mut form = reqwest::multipart::Form::new().text("method", self.model.as_str());
+        if let Some(language) = &self.language {
+            form = form.text("language", language.clone());
+        }
+        form
+    }
+
+    /// Speech to text (blocking)
+    pub fn transcribe<I: Into<Input>>(&self, speech: I) -> Result<STTReply, ClientError> {
+        let speech = speech.into();
+        if let Some(peer_id) = self.node.via_p2p() {
+            let request = InferRequest::Whisper { model: self.model, language: self.language.clone(), format: self.format, speech: speech.into_vec()? };
+            return crate::p2p::infer(peer_id.as_str(), request, self.timeout)?.into_json();
+        }
+
+        let file_name = format!("speech.{}", speech.extension());
+        let form = self.form().text("format", self.format.as_str()).part("speech", speech.into_part(file_name)?);
+
+        let span = crate::trace::client(format!("POST /api/services/whisper"), self.node.pid.as_str());
+        let response = super::blocking_http_client(self.node)?.post(format!("http://{}:{}/api/services/whisper", self.node.ip_address.clone(), self.node.port.clone()))
+        .timeout(self.timeout)
+        .multipart(form)
+        .header("traceparent", span.traceparent())
+        .send()?;
+
+        return Ok(check_blocking(response)?.json()?);
+    }
+
+    /// Speech to text
+    pub async fn async_transcribe<I: Into<Input>>(&self, speech: I) -> Result<STTReply, ClientError> {
+        let speech = speech.into();
+        if let Some(peer_id) = self.node.via_p2p() {
+            let request = InferRequest::Whisper { model: self.model, language: self.language.clone(), format: self.format, speech: speech.async_into_vec().await? };
+            return super::p2p_infer(peer_id, request, self.timeout).await?.into_json();
+        }
+
+        let file_name = format!("speech.{}", speech.extension());
+        let form = self.async_form().text("format", self.format.as_str()).part("speech", speech.into_async_part(file_name).await?);
+
+        let span = crate::trace::client(format!("POST /api/services/whisper"), self.node.pid.as_str());
+        let response = super::http_client(self.node)?.post(self.node.url("/api/services/whisper"))
+        .timeout(self.timeout)
+        .multipart(form)
+        .header("traceparent", span.traceparent())
+        .send().await?;
+
+        return Ok(check(response).await?.json().await?);
+    }
+
+    /// Subtitled video of the speech, as mp4 bytes (blocking)
+    pub fn vwav<I: Into<Input>>(&self, speech: I) -> Result<Vec<u8>, ClientError> {
+        let speech = speech.into();
+        if let Some(peer_id) = self.node.via_p2p() {
+            let request = InferRequest::WhisperVwav { model: self.model, language: self.language.clone(), speech: speech.into_vec()? };
+            return crate::p2p::infer(peer_id.as_str(), request, self.timeout)?.into_bytes();
+        }
+
+        let url = format!("http://{}:{}/api/services/whisper/vwav", self.node.ip_address.clone(), self.node.port.clone());
+
+        log::info!("Fetching VWAV from {}", url);
+
+        let file_name = format!("speech.{}", speech.extension());
+        let form = self.form().part("speech", speech.into_part(file_name)?);
+
+        let span = crate::trace::client(format!("POST /api/services/whisper/vwav"), self.node.pid.as_str());
+        let response = super::blocking_http_client(self.node)?.post(url)
+        .timeout(self.timeout)
+        .multipart(form)
+        .header("traceparent", span.traceparent())
+        .send()?;
+
+        let bytes = check_blocking(response)?.bytes()?;
+
+        return Ok(bytes.to_vec());
+    }
+
+    /// Subtitled video of the speech, as mp4 bytes
+    pub async fn async_vwav<I: Into<Input>>(&self, speech: I) -> Result<Vec<u8>, ClientError> {
+        let speech = speech.into();
+        if let Some(peer_id) = self.node.via_p2p() {
+            let request = InferRequest::WhisperVwav { model: self.model, language: self.language.clone(), speech: speech.async_into_vec().await? };
+            return super::p2p_infer(peer_id, request, self.timeout).await?.into_bytes();
+        }
+
+        let url = self.node.url("/api/services/whisper/vwav");
+
+        log::info!("Fetching VWAV from {}", url);
+
+        let file_name = format!("speech.{}", speech.extension());
+        let form = self.async_form().part("speech", speech.into_async_part(file_name).await?);
+
+        let span = crate::trace::client(format!("POST /api/services/whisper/vwav"), self.node.pid.as_str());
+        let response = super::http_client(self.node)?.post(url)
+        .timeout(self.timeout)
+        .multipart(form)
+        .header("traceparent", span.traceparent())
+        .send().await?;
+
+        let bytes = check(response).await?.bytes().await?;
+
+        return Ok(bytes.to_vec());
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LlamaBuilder<'a> {
+    node: &'a ThalamusNode,
+    model: LlamaModel,
+    timeout: Duration,
+}
+impl<'a> LlamaBuilder<'a> {
+    pub fn new(node: &'a ThalamusNode, model: LlamaModel) -> LlamaBuilder<'a> {
+        LlamaBuilder {
+            node: node,
+            model: model,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> LlamaBuilder<'a> {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Completes a prompt (blocking)
+    pub fn prompt(&self, prompt: String) -> Result<String, ClientError> {
+        if let Some(peer_id) = self.node.via_p2p() {
+            return crate::p2p::infer(peer_id.as_str(), InferRequest::Llama { model: self.model, prompt: prompt }, self.timeout)?.into_text();
+        }
+
+        let params = [("model", self.model.as_str()), ("prompt", prompt.as_str())];
+
+        let span = crate::trace::client(format!("POST /api/services/llama"), self.node.pid.as_str());
+        let response = super::blocking_http_client(self.node)?.post(format!("http://{}:{}/api/services/llama", self.node.ip_address.clone(), self.node.port.clone()))
+        .timeout(self.timeout)
+        .form(&params)
+        .header("traceparent", span.traceparent())
+        .send()?;
+
+        return Ok(check_blocking(response)?.text()?);
+    }
+
+    /// Completes a prompt
+    pub async fn async_prompt(&self, prompt: String) -> Result<String, ClientError> {
+        if let Some(peer_id) = self.node.via_p2p() {
+            let request = InferRequest::Llama { model: self.model, prompt: prompt };
+            return super::p2p_infer(peer_id, request, self.timeout).await?.into_text();
+        }
+
+        let params = [("model", self.model.as_str()), ("prompt", prompt.as_str())];
+
+        let span = crate::trace::client(format!("POST /api/services/llama"), self.node.pid.as_str());
+        let response = super::http_client(self.node)?.post(self.node.url("/api/services/llama"))
+        .timeout(self.timeout)
+        .form(&params)
+        .header("traceparent", span.traceparent())
+        .send().await?;
+
+        return Ok(check(response).await?.text().await?);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TtsBuilder<'a> {
+    node: &'a ThalamusNode,
+    voice: String,
+    fallback: String,
+    timeout: Duration,
+}
+impl<'a> TtsBuilder<'a> {
+    pub fn new(node: &'a ThalamusNode, voice: &str) -> TtsBuilder<'a> {
+        TtsBuilder {
+            node: node,
+            voice: voice.to_string(),
+            fallback: format!("opensamfoundation"),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Voice used when the primary voice fails, defaults to opensamfoundation
+    pub fn fallback(mut self, voice: &str) -> TtsBuilder<'a> {
+        self.fallback = voice.to_string();
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> TtsBuilder<'a> {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Speaks the text, returning wav bytes (blocking)
+    pub fn say(&self, text: String) -> Result<Vec<u8>, ClientError> {
+        if let Some(peer_id) = self.node.via_p2p() {
+            let request = InferRequest::Tts { text: text, primary: self.voice.clone(), fallback: self.fallback.clone() };
+            return crate::p2p::infer(peer_id.as_str(), request, self.timeout)?.into_bytes();
+        }
+
+        // The tts route reads its fields from the query string
+        let params = [("text", text.as_str()), ("primary", self.voice.as_str()), ("fallback", self.fallback.as_str())];
+
+        let span = crate::trace::client(format!("POST /api/services/tts"), self.node.pid.as_str());
+        let response = super::blocking_http_client(self.node)?.post(format!("http://{}:{}/api/services/tts", self.node.ip_address.clone(), self.node.port.clone()))
+        .timeout(self.timeout)
+        .query(&params)
+        .header("traceparent", span.traceparent())
+        .send()?;
+
+        let bytes = check_blocking(response)?.bytes()?;
+
+        return Ok(bytes.to_vec());
+    }
+
+    /// Speaks the text, returning wav bytes
+    pub async fn async_say(&self, text: String) -> Result<Vec<u8>, ClientError> {
+        if let Some(peer_id) = self.node.via_p2p() {
+            let request = InferRequest::Tts { text: text, primary: self.voice.clone(), fallback: self.fallback.clone() };
+            return super::p2p_infer(peer_id, request, self.timeout).await?.into_bytes();
+        }
+
+        let params = [("text", text.as_str()), ("primary", self.voice.as_str()), ("fallback", self.fallback.as_str())];
+
+        let span = crate::trace::client(format!("POST /api/services/tts"), self.node.pid.as_str());
+        let response = super::http_client(self.node)?.post(self.node.url("/api/services/tts"))
+        .timeout(self.timeout)
+        .query(&params)
+        .header("traceparent", span.traceparent())
+        .send().await?;
+
+        let bytes = check(response).await?.bytes().await?;
+
+        return Ok(bytes.to_vec());
+    }
+}
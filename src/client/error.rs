@@ -0,0 +1,149 @@
+// ████████ ██   ██  █████  ██       █████  ███    ███ ██    ██ ███████ 
+//    ██    ██   ██ ██   ██ ██      ██   ██ ████  ████ ██    ██ ██      
+//    ██    ███████ ███████ ██      ███████ ██ ████ ██ ██    ██ ███████ 
+//    ██    ██   ██ ██   ██ ██      ██   ██ ██  ██  ██ ██    ██      ██ 
+//    ██    ██   ██ ██   ██ ███████ ██   ██ ██      ██  ██████  ███████                                                                             
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+
+// Errors returned by the client library.
+// Retry and failover logic should match on ClientError::retry() rather than on messages.
+
+use std::fmt;
+use std::io;
+
+use crate::thalamus::http::ErrorReply;
+
+/// What a caller can do about a failed call
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Retry {
+    /// Retrying won't help: the request itself is wrong
+    Never,
+    /// The node is healthy but busy, retry it after a backoff
+    SameNode,
+    /// The node is down, slow or can't serve the request, try another one
+    OtherNode,
+}
+
+#[derive(Debug)]
+pub enum ClientError {
+    /// No answer from the node: connection refused or reset, dns failure, p2p node down
+    Transport(String),
+    /// No answer within the call's timeout
+    Timeout,
+    /// The node answered with an error status, and its error body if it sent one
+    Status { status: u16, error: Option<ErrorReply> },
+    /// The node answered but the reply couldn't be decoded
+    Decode(String),
+    /// The node doesn't have the model or service asked for
+    NotCapable(String),
+    /// Reading the input or local state failed
+    Io(io::Error),
+}
+impl ClientError {
+    pub fn retry(&self) -> Retry {
+        match self {
+            ClientError::Transport(_) => Retry::OtherNode,
+            ClientError::Timeout => Retry::OtherNode,
+            ClientError::Status { status, .. } => match status {
+                408 | 429 | 503 => Retry::SameNode,
+                500..=599 => Retry::OtherNode,
+                _ => Retry::Never,
+            },
+            ClientError::Decode(_) => Retry::Never,
+            ClientError::NotCapable(_) => Retry::OtherNode,
+            ClientError::Io(_) => Retry::Never,
+        }
+    }
+
+    pub fn is_retryable(&self) -> bool {
+        self.retry() != Retry::Never
+    }
+
+    /// Builds the error for a non-success reply from its status and body
+    pub(crate) fn from_reply(status: u16, body: &str) -> ClientError {
+        match serde_json::from_str::<ErrorReply>(body) {
+            Ok(error) => {
+                if error.kind == "not_capable" {
+                    return ClientError::NotCapable(error.error);
+                }
+                ClientError::Status { status, error: Some(error) }
+            },
+            Err(_) => ClientError::Status { status, error: None },
+        }
+    }
+}
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClientError::Transport(e) => write!(f, "transport error: {}", e),
+            ClientError::Timeout => write!(f, "timed out"),
+            ClientError::Status { status, error: Some(error) } => write!(f, "http {}: {} ({})", status, error.error, error.kind),
+            ClientError::Status { status, error: None } => write!(f, "http {}", status),
+            ClientError::Decode(e) => write!(f, "decode error: {}", e),
+            ClientError::NotCapable(e) => write!(f, "not capable: {}", e),
+            ClientError::Io(e) => write!(f, "io error: {}", e),
+        }
+    }
+}
+impl std::error::Error for ClientError {}
+
+impl From<reqwest::Error> for ClientError {
+    fn from(e: reqwest::Error) -> ClientError {
+        if e.is_timeout() {
+            return ClientError::Timeout;
+        }
+        if e.is_decode() {
+            return ClientError::Decode(format!("{}", e));
+        }
+        match e.status() {
+            Some(status) => ClientError::Status { status: status.as_u16(), error: None },
+            None => ClientError::Transport(format!("{}", e)),
+        }
+    }
+}
+impl From<io::Error> for ClientError {
+    fn from(e: io::Error) -> ClientError {
+        ClientError::Io(e)
+    }
+}
+impl From<crate::storage::Error> for ClientError {
+    fn from(e: crate::storage::Error) -> ClientError {
+        match e {
+            crate::storage::Error(crate::storage::ErrorKind::Io(e), _) => ClientError::Io(e),
+            e => ClientError::Io(io::Error::new(io::ErrorKind::Other, format!("{}", e))),
+        }
+    }
+}
+impl From<serde_json::Error> for ClientError {
+    fn from(e: serde_json::Error) -> ClientError {
+        ClientError::Decode(format!("{}", e))
+    }
+}
+impl From<std::time::SystemTimeError> for ClientError {
+    fn from(e: std::time::SystemTimeError) -> ClientError {
+        ClientError::Io(io::Error::new(io::ErrorKind::Other, e))
+    }
+}
+
+/// Passes successful replies through and turns the rest into errors
+pub(crate) async fn check(response: reqwest::Response) -> Result<reqwest::Response, ClientError> {
+    if response.status().is_success() {
+        return Ok(response);
+    }
+    let status = response.status().as_u16();
+    let body = response.text().await.unwrap_or_default();
+    Err(ClientError::from_reply(status, body.as_str()))
+}
+
+/// Passes successful replies through and turns the rest into errors (blocking)
+pub(crate) fn check_blocking(response: reqwest::blocking::Response) -> Result<reqwest::blocking::Response, ClientError> {
+    if response.status().is_success() {
+        return Ok(response);
+    }
+    let status = response.status().as_u16();
+    let body = response.text().unwrap_or_default();
+    Err(ClientError::from_reply(status, body.as_str()))
+}
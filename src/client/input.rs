@@ -0,0 +1,213 @@
+// ████████ ██   ██  █████  ██       █████  ███    ███ ██    ██ ███████ 
+//    ██    ██   ██ ██   ██ ██      ██   ██ ████  ████ ██    ██ ██      
+//    ██    ███████ ███████ ██      ███████ ██ ████ ██ ██    ██ ███████ 
+//    ██    ██   ██ ██   ██ ██      ██   ██ ██  ██  ██ ██    ██      ██ 
+//    ██    ██   ██ ██   ██ ███████ ██   ██ ██      ██  ██████  ███████                                                                             
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+
+// Inputs for client calls: a file on disk, bytes already in memory, or a Read/AsyncRead stream.
+// Everything but a path needs a MIME type so the server gets a sensible file name.
+
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+
+use bytes::Bytes;
+use mime::Mime;
+use tokio::io::AsyncRead;
+
+enum Source {
+    Path(PathBuf),
+    Bytes(Bytes),
+    Reader(Box<dyn Read + Send>),
+    AsyncReader(Pin<Box<dyn AsyncRead + Send + Sync>>),
+}
+
+pub struct Input {
+    source: Source,
+    mime: Mime,
+}
+impl Input {
+
+    /// A file on disk, its MIME type guessed from the extension
+    pub fn path<P: AsRef<Path>>(path: P) -> Input {
+        let path = path.as_ref().to_path_buf();
+        let mime = crate::thalamus::tools::find_mimetype(&path.to_string_lossy().to_string()).parse::<Mime>().unwrap_or(mime::APPLICATION_OCTET_STREAM);
+        Input {
+            source: Source::Path(path),
+            mime: mime,
+        }
+    }
+
+    /// A copy of a borrowed buffer
+    pub fn slice(data: &[u8], mime: Mime) -> Input {
+        Input::bytes(Bytes::copy_from_slice(data), mime)
+    }
+
+    pub fn bytes<B: Into<Bytes>>(data: B, mime: Mime) -> Input {
+        Input {
+            source: Source::Bytes(data.into()),
+            mime: mime,
+        }
+    }
+
+    /// A blocking reader, streamed by blocking calls and buffered by async ones
+    pub fn reader<R: Read + Send + 'static>(reader: R, mime: Mime) -> Input {
+        Input {
+            source: Source::Reader(Box::new(reader)),
+            mime: mime,
+        }
+    }
+
+    /// An async reader, streamed by async calls (blocking calls refuse it)
+    pub fn async_reader<R: AsyncRead + Send + Sync + 'static>(reader: R, mime: Mime) -> Input {
+        Input {
+            source: Source::AsyncReader(Box::pin(reader)),
+            mime: mime,
+        }
+    }
+
+    pub fn mime(&self) -> &Mime {
+        &self.mime
+    }
+
+    /// File extension for the MIME type (e.g. wav, jpg)
+    pub fn extension(&self) -> String {
+        match (self.mime.type_().as_str(), self.mime.subtype().as_str()) {
+            (_, "jpeg") => format!("jpg"),
+            ("audio", "x-wav") | ("audio", "wave") | ("audio", "vnd.wave") => format!("wav"),
+            ("audio", "mpeg") => format!("mp3"),
+            (_, "octet-stream") => match &self.source {
+                Source::Path(path) => path.extension().map(|e| e.to_string_lossy().to_string()).unwrap_or(format!("bin")),
+                _ => format!("bin"),
+            },
+            (_, subtype) => subtype.to_string(),
+        }
+    }
+
+    /// A second handle on the same input, for retries. Streams can only be read once.
+    pub fn try_clone(&self) -> Option<Input> {
+        let source = match &self.source {
+            Source::Path(path) => Source::Path(path.clone()),
+            Source::Bytes(bytes) => Source::Bytes(bytes.clone()),
+            _ => return None,
+        };
+        Some(Input { source: source, mime: self.mime.clone() })
+    }
+
+    /// Buffers streams in memory so the input can be sent more than once (blocking)
+    pub fn buffered(self) -> io::Result<Input> {
+        match self.source {
+            Source::Path(_) | Source::Bytes(_) => Ok(self),
+            _ => {
+                let mime = self.mime.clone();
+                Ok(Input::bytes(self.into_vec()?, mime))
+            }
+        }
+    }
+
+    /// Buffers streams in memory so the input can be sent more than once
+    pub async fn async_buffered(self) -> io::Result<Input> {
+        match self.source {
+            Source::Path(_) | Source::Bytes(_) => Ok(self),
+            _ => {
+                let mime = self.mime.clone();
+                Ok(Input::bytes(self.async_into_vec().await?, mime))
+            }
+        }
+    }
+
+    /// Reads the whole input into memory (blocking)
+    pub(crate) fn into_vec(self) -> io::Result<Vec<u8>> {
+        match self.source {
+            Source::Path(path) => std::fs::read(path),
+            Source::Bytes(bytes) => Ok(bytes.to_vec()),
+            Source::Reader(mut reader) => {
+                let mut data = Vec::new();
+                reader.read_to_end(&mut data)?;
+                Ok(data)
+            },
+            Source::AsyncReader(_) => Err(io::Error::new(io::ErrorKind::Unsupported, "async readers need the async_ client calls")),
+        }
+    }
+
+    /// Reads the whole input into memory
+    pub(crate) async fn async_into_vec(self) -> io::Result<Vec<u8>> {
+        match self.source {
+            Source::Path(path) => tokio::fs::read(path).await,
+            Source::Bytes(bytes) => Ok(bytes.to_vec()),
+            Source::Reader(mut reader) => {
+                match tokio::task::spawn_blocking(move || {
+                    let mut data = Vec::new();
+                    reader.read_to_end(&mut data)?;
+                    Ok(data)
+                }).await {
+                    Ok(data) => data,
+                    Err(e) => Err(io::Error::new(io::ErrorKind::Other, e)),
+                }
+            },
+            Source::AsyncReader(mut reader) => {
+                let mut data = Vec::new();
+                tokio::io::AsyncReadExt::read_to_end(&mut reader, &mut data).await?;
+                Ok(data)
+            },
+        }
+    }
+
+    /// A multipart file field for the blocking client
+    pub(crate) fn into_part(self, file_name: String) -> io::Result<reqwest::blocking::multipart::Part> {
+        let mime = self.mime.to_string();
+        let part = match self.source {
+            Source::Path(path) => reqwest::blocking::multipart::Part::reader(File::open(path)?),
+            Source::Bytes(bytes) => reqwest::blocking::multipart::Part::bytes(bytes.to_vec()),
+            Source::Reader(reader) => reqwest::blocking::multipart::Part::reader(reader),
+            Source::AsyncReader(_) => return Err(io::Error::new(io::ErrorKind::Unsupported, "async readers need the async_ client calls")),
+        };
+        return part.file_name(file_name).mime_str(mime.as_str()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e));
+    }
+
+    /// A multipart file field for the async client, streamed where possible
+    pub(crate) async fn into_async_part(self, file_name: String) -> io::Result<reqwest::multipart::Part> {
+        let mime = self.mime.to_string();
+        let part = match self.source {
+            Source::Path(path) => {
+                let file = tokio::fs::File::open(path).await?;
+                reqwest::multipart::Part::stream(reqwest::Body::wrap_stream(tokio_util::io::ReaderStream::new(file)))
+            },
+            Source::Bytes(bytes) => reqwest::multipart::Part::stream(reqwest::Body::from(bytes)),
+            Source::Reader(reader) => {
+                let input = Input { source: Source::Reader(reader), mime: self.mime };
+                reqwest::multipart::Part::bytes(input.async_into_vec().await?)
+            },
+            Source::AsyncReader(reader) => {
+                reqwest::multipart::Part::stream(reqwest::Body::wrap_stream(tokio_util::io::ReaderStream::new(reader)))
+            },
+        };
+        return part.file_name(file_name).mime_str(mime.as_str()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e));
+    }
+}
+impl From<String> for Input {
+    fn from(path: String) -> Input {
+        Input::path(path)
+    }
+}
+impl From<&str> for Input {
+    fn from(path: &str) -> Input {
+        Input::path(path)
+    }
+}
+impl From<PathBuf> for Input {
+    fn from(path: PathBuf) -> Input {
+        Input::path(path)
+    }
+}
+impl From<&Path> for Input {
+    fn from(path: &Path) -> Input {
+        Input::path(path)
+    }
+}
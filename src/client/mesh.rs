@@ -0,0 +1,546 @@
+// ████████ ██   ██  █████  ██       █████  ███    ███ ██    ██ ███████ 
+//    ██    ██   ██ ██   ██ ██      ██   ██ ████  ████ ██    ██ ██      
+//    ██    ███████ ███████ ██      ███████ ██ ████ ██ ██    ██ ███████ 
+//    ██    ██   ██ ██   ██ ██      ██   ██ ██  ██  ██ ██    ██      ██ 
+//    ██    ██   ██ ██   ██ ███████ ██   ██ ██      ██  ██████  ███████                                                                             
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+
+// Mesh-level calls on ThalamusClient: pick the best node for a job, fail over to the
+// next-best one on transport errors and 5xx, and skip nodes whose circuit breaker is open.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use super::{ClientError, Input, Retry};
+use crate::thalamus::services::llama::LlamaModel;
+use crate::thalamus::services::whisper::{STTReply, WhisperModel};
+use crate::{ThalamusClient, ThalamusNode};
+
+/// How hard the mesh tries before giving up on a call
+#[derive(Debug, Clone)]
+pub struct MeshPolicy {
+    /// Calls made in total, across all nodes
+    pub attempts: usize,
+    /// Backoff before the second attempt, doubled for each one after
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+    /// Timeout for each attempt
+    pub timeout: Duration,
+    /// Consecutive failures that open a node's circuit breaker
+    pub failure_threshold: u32,
+    /// How long a node with an open breaker is skipped
+    pub cooldown: Duration,
+}
+impl Default for MeshPolicy {
+    fn default() -> MeshPolicy {
+        MeshPolicy {
+            attempts: 3,
+            backoff: Duration::from_millis(250),
+            max_backoff: Duration::from_secs(5),
+            timeout: super::DEFAULT_TIMEOUT,
+            failure_threshold: 3,
+            cooldown: Duration::from_secs(60),
+        }
+    }
+}
+impl MeshPolicy {
+    fn backoff(&self, attempt: usize) -> Duration {
+        let backoff = self.backoff.saturating_mul(1 << attempt.min(16) as u32);
+        std::cmp::min(backoff, self.max_backoff)
+    }
+}
+
+/// Circuit breaker state of a node
+#[derive(Debug, Clone, Default)]
+pub struct Breaker {
+    pub failures: u32,
+    pub open_until: Option<Instant>,
+    /// Set while the one probe call of a half-open breaker is in flight; the claim lapses
+    /// at this instant in case the caller never reports back
+    pub probing_until: Option<Instant>,
+}
+impl Breaker {
+    /// Closed, or open with its cooldown over and no probe in flight (half-open)
+    pub fn allows(&self) -> bool {
+        self.allows_at(Instant::now())
+    }
+
+    fn allows_at(&self, now: Instant) -> bool {
+        match (self.open_until, self.probing_until) {
+            (None, _) => true,
+            (Some(until), _) if now < until => false,
+            (Some(_), Some(probing_until)) => now >= probing_until,
+            (Some(_), None) => true,
+        }
+    }
+
+    // Lets a call through if allowed; a half-open breaker lets only this one call, the
+    // probe, through until it succeeds or fails
+    fn admit(&mut self, now: Instant, policy: &MeshPolicy) -> bool {
+        if !self.allows_at(now) {
+            return false;
+        }
+        if self.open_until.is_some() {
+            self.probing_until = Some(now + policy.cooldown);
+        }
+        true
+    }
+
+    // A failed call; opens the breaker at the threshold, or reopens it when the probe failed.
+    // Returns whether it's open.
+    fn fail(&mut self, now: Instant, policy: &MeshPolicy) -> bool {
+        self.failures += 1;
+        self.probing_until = None;
+        if self.failures >= policy.failure_threshold {
+            self.open_until = Some(now + policy.cooldown);
+            return true;
+        }
+        false
+    }
+}
+
+// Breakers are keyed by pid, like the http pool, so every clone of a node shares one
+static BREAKERS: OnceLock<Mutex<HashMap<String, Breaker>>> = OnceLock::new();
+
+fn breakers() -> std::sync::MutexGuard<'static, HashMap<String, Breaker>> {
+    BREAKERS.get_or_init(|| Mutex::new(HashMap::new())).lock().unwrap()
+}
+
+/// Current circuit breaker state of a node
+pub fn breaker(pid: &str) -> Breaker {
+    breakers().get(pid).cloned().unwrap_or_default()
+}
+
+// Whether a call to the node may go ahead now, claiming the probe of a half-open breaker
+fn admit(pid: &str, policy: &MeshPolicy) -> bool {
+    match breakers().get_mut(pid) {
+        Some(breaker) => breaker.admit(Instant::now(), policy),
+        None => true,
+    }
+}
+
+fn record_success(pid: &str) {
+    breakers().remove(pid);
+}
+
+fn record_failure(pid: &str, policy: &MeshPolicy) {
+    let mut breakers = breakers();
+    let breaker = breakers.entry(pid.to_string()).or_default();
+    if breaker.fail(Instant::now(), policy) {
+        log::warn!("Circuit breaker open for {} after {} failures", pid, breaker.failures);
+    }
+}
+
+// The call ended without saying anything about the node's health; frees the probe
+fn release(pid: &str) {
+    match breakers().get_mut(pid) {
+        Some(breaker) => breaker.probing_until = None,
+        None => {}
+    }
+}
+
+// What happens after a failed attempt
+#[derive(Debug, PartialEq)]
+enum Next {
+    Stop,
+    SameNode,
+    OtherNode,
+}
+
+fn on_failure(node: &ThalamusNode, e: &ClientError, policy: &MeshPolicy) -> Next {
+    log::warn!("{}: mesh call failed: {}", node.pid, e);
+    let next = next(e);
+    match next {
+        Next::OtherNode if !matches!(e, ClientError::NotCapable(_)) => record_failure(node.pid.as_str(), policy),
+        _ => release(node.pid.as_str()),
+    }
+    return next;
+}
+
+// Where a call goes after failing with `e`
+fn next(e: &ClientError) -> Next {
+    match e {
+        // Not the node's fault, it just lacks the model
+        ClientError::NotCapable(_) => return Next::OtherNode,
+        _ => {}
+    }
+    match e.retry() {
+        Retry::Never => Next::Stop,
+        Retry::SameNode => Next::SameNode,
+        Retry::OtherNode => Next::OtherNode,
+    }
+}
+
+impl ThalamusClient {
+
+    /// Online nodes that may serve a capability, best score (lowest time) first
+    pub fn candidates<F: Fn(&ThalamusNode) -> Option<i64>>(&self, capability: &str, score: F) -> Vec<ThalamusNode> {
+        let mut nodes: Vec<ThalamusNode> = self.nodes.iter().filter(|node| {
+            if !node.is_online || !breaker(node.pid.as_str()).allows() {
+                return false;
+            }
+            // Skip nodes that can't hold the model at all
+            match &node.hardware {
+                Some(hardware) if !hardware.fits(capability) => return false,
+                _ => {}
+            }
+            // Nodes we haven't learned capabilities for yet are worth a try
+            match &node.capablities {
+                Some(capabilities) if !capabilities.is_empty() => capabilities.iter().any(|c| c.tag == capability),
+                _ => true,
+            }
+        }).cloned().collect();
+        nodes.sort_by_key(|node| load_score(node, capability, score(node)));
+        return nodes;
+    }
+
+    /// Runs a call on the best node, failing over to the next-best (blocking)
+    pub fn with_failover<T, S, F>(&self, capability: &str, score: S, mut call: F) -> Result<T, ClientError>
+    where
+        S: Fn(&ThalamusNode) -> Option<i64>,
+        F: FnMut(&ThalamusNode) -> Result<T, ClientError>,
+    {
+        let nodes = self.candidates(capability, score);
+        if nodes.is_empty() {
+            return Err(ClientError::NotCapable(format!("no available node provides {}", capability)));
+        }
+
+        let mut index = 0;
+        let mut last_error = None;
+        for attempt in 0..self.policy.attempts {
+            if attempt > 0 {
+                std::thread::sleep(self.policy.backoff(attempt - 1));
+            }
+            let node = &nodes[index % nodes.len()];
+            // Another caller holds the probe of this node's half-open breaker
+            if !admit(node.pid.as_str(), &self.policy) {
+                index += 1;
+                continue;
+            }
+            match call(node) {
+                Ok(result) => {
+                    record_success(node.pid.as_str());
+                    return Ok(result);
+                },
+                Err(e) => {
+                    match on_failure(node, &e, &self.policy) {
+                        Next::Stop => return Err(e),
+                        Next::SameNode => {},
+                        Next::OtherNode => index += 1,
+                    }
+                    last_error = Some(e);
+                }
+            }
+        }
+        return Err(last_error.unwrap_or(ClientError::NotCapable(format!("no available node provides {}", capability))));
+    }
+
+    /// Runs a call on the best node, failing over to the next-best
+    pub async fn async_with_failover<T, S, F, Fut>(&self, capability: &str, score: S, mut call: F) -> Result<T, ClientError>
+    where
+        S: Fn(&ThalamusNode) -> Option<i64>,
+        F: FnMut(ThalamusNode) -> Fut,
+        Fut: Future<Output = Result<T, ClientError>>,
+    {
+        let nodes = self.candidates(capability, score);
+        if nodes.is_empty() {
+            return Err(ClientError::NotCapable(format!("no available node provides {}", capability)));
+        }
+
+        let mut index = 0;
+        let mut last_error = None;
+        for attempt in 0..self.policy.attempts {
+            if attempt > 0 {
+                tokio::time::sleep(self.policy.backoff(attempt - 1)).await;
+            }
+            let node = nodes[index % nodes.len()].clone();
+            let pid = node.pid.clone();
+            // Another caller holds the probe of this node's half-open breaker
+            if !admit(pid.as_str(), &self.policy) {
+                index += 1;
+                continue;
+            }
+            match call(node.clone()).await {
+                Ok(result) => {
+                    record_success(pid.as_str());
+                    return Ok(result);
+                },
+                Err(e) => {
+                    match on_failure(&node, &e, &self.policy) {
+                        Next::Stop => return Err(e),
+                        Next::SameNode => {},
+                        Next::OtherNode => index += 1,
+                    }
+                    last_error = Some(e);
+                }
+            }
+        }
+        return Err(last_error.unwrap_or(ClientError::NotCapable(format!("no available node provides {}", capability))));
+    }
+
+    /// Speech to text on the best node for the model (blocking)
+    pub fn transcribe<I: Into<Input>>(&self, audio: I, model: WhisperModel) -> Result<STTReply, ClientError> {
+        let audio = audio.into().buffered()?;
+        let timeout = self.policy.timeout;
+        return self.with_failover(format!("whisper:{}", model).as_str(), |node| node.stats.median(format!("whisper:{}", model).as_str()), |node| {
+            node.whisper(model).timeout(timeout).transcribe(retry_input(&audio)?)
+        });
+    }
+
+    /// Speech to text on the best node for the model
+    pub async fn async_transcribe<I: Into<Input>>(&self, audio: I, model: WhisperModel) -> Result<STTReply, ClientError> {
+        let audio = audio.into().async_buffered().await?;
+        let timeout = self.policy.timeout;
+        let audio = &audio;
+        return self.async_with_failover(format!("whisper:{}", model).as_str(), |node| node.stats.median(format!("whisper:{}", model).as_str()), |node| async move {
+            node.whisper(model).timeout(timeout).async_transcribe(retry_input(audio)?).await
+        }).await;
+    }
+
+    /// Completes a prompt on the best node for the model (blocking)
+    pub fn chat(&self, prompt: String, model: LlamaModel) -> Result<String, ClientError> {
+        let timeout = self.policy.timeout;
+        return self.with_failover(format!("llama:{}", model).as_str(), |node| node.stats.median(format!("llama:{}", model).as_str()), |node| {
+            node.llama(model).timeout(timeout).prompt(prompt.clone())
+        });
+    }
+
+    /// Completes a prompt on the best node for the model
+    pub async fn async_chat(&self, prompt: String, model: LlamaModel) -> Result<String, ClientError> {
+        let timeout = self.policy.timeout;
+        let prompt = &prompt;
+        return self.async_with_failover(format!("llama:{}", model).as_str(), |node| node.stats.median(format!("llama:{}", model).as_str()), |node| async move {
+            node.llama(model).timeout(timeout).async_prompt(prompt.clone()).await
+        }).await;
+    }
+
+    /// Speaks text on the best tts node, returning wav bytes (blocking)
+    pub fn say(&self, text: String, voice: &str) -> Result<Vec<u8>, ClientError> {
+        let timeout = self.policy.timeout;
+        return self.with_failover("tts", |node| node.stats.median("tts"), |node| {
+            node.tts(voice).timeout(timeout).say(text.clone())
+        });
+    }
+
+    /// Speaks text on the best tts node, returning wav bytes
+    pub async fn async_say(&self, text: String, voice: &str) -> Result<Vec<u8>, ClientError> {
+        let timeout = self.policy.timeout;
+        let text = &text;
+        return self.async_with_failover("tts", |node| node.stats.median("tts"), |node| async move {
+            node.tts(voice).timeout(timeout).async_say(text.clone()).await
+        }).await;
+    }
+
+    /// Upscales an image on the best srgan node (blocking)
+    pub fn upscale<I: Into<Input>>(&self, image: I) -> Result<Vec<u8>, ClientError> {
+        let image = image.into().buffered()?;
+        let timeout = self.policy.timeout;
+        return self.with_failover("srgan", |node| node.stats.median("srgan"), |node| {
+            node.srgan_timeout(retry_input(&image)?, timeout)
+        });
+    }
+
+    /// Upscales an image on the best srgan node
+    pub async fn async_upscale<I: Into<Input>>(&self, image: I) -> Result<Vec<u8>, ClientError> {
+        let image = image.into().async_buffered().await?;
+        let timeout = self.policy.timeout;
+        let image = &image;
+        return self.async_with_failover("srgan", |node| node.stats.median("srgan"), |node| async move {
+            node.async_srgan(retry_input(image)?, timeout).await
+        }).await;
+    }
+
+    /// Runs object detection on the best yolo node (blocking)
+    pub fn detect<I: Into<Input>>(&self, image: I) -> Result<STTReply, ClientError> {
+        let image = image.into().buffered()?;
+        let timeout = self.policy.timeout;
+        return self.with_failover("yolo:v7", |node| node.stats.median("yolo:v7"), |node| {
+            node.yolov7_timeout(retry_input(&image)?, timeout)
+        });
+    }
+
+    /// Runs object detection on the best yolo node
+    pub async fn async_detect<I: Into<Input>>(&self, image: I) -> Result<STTReply, ClientError> {
+        let image = image.into().async_buffered().await?;
+        let timeout = self.policy.timeout;
+        let image = &image;
+        return self.async_with_failover("yolo:v7", |node| node.stats.median("yolo:v7"), |node| async move {
+            node.async_yolov7(retry_input(image)?, timeout).await
+        }).await;
+    }
+}
+
+// Inputs are buffered before the first attempt, so they always clone
+fn retry_input(input: &Input) -> Result<Input, ClientError> {
+    match input.try_clone() {
+        Some(input) => Ok(input),
+        None => Err(ClientError::Io(std::io::Error::new(std::io::ErrorKind::Other, "input can't be resent"))),
+    }
+}
+
+// Live latency when the node has served this capability recently, else the benchmark,
+// scaled up by queue depth and error rate
+fn load_score(node: &ThalamusNode, capability: &str, benchmark: Option<i64>) -> i64 {
+    let traffic = match node.traffic.get(capability) {
+        Some(traffic) => traffic,
+        None => return benchmark.unwrap_or(i64::MAX),
+    };
+    let latency = match traffic.latency_ms.or(benchmark.map(|b| b as f64)) {
+        Some(latency) => latency,
+        None => return i64::MAX,
+    };
+    return (latency * (1.0 + traffic.queue_depth as f64) * (1.0 + 4.0 * traffic.error_rate)) as i64;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> MeshPolicy {
+        MeshPolicy { failure_threshold: 2, cooldown: Duration::from_secs(10), ..MeshPolicy::default() }
+    }
+
+    fn status(status: u16) -> ClientError {
+        ClientError::Status { status: status, error: None }
+    }
+
+    // A breaker just opened at `now`
+    fn open(now: Instant, policy: &MeshPolicy) -> Breaker {
+        let mut breaker = Breaker::default();
+        for _ in 0..policy.failure_threshold {
+            breaker.fail(now, policy);
+        }
+        breaker
+    }
+
+    #[test]
+    fn busy_nodes_are_retried_and_failing_ones_skipped() {
+        for code in [408, 429, 503] {
+            assert_eq!(next(&status(code)), Next::SameNode, "{}", code);
+        }
+        for code in [500, 502, 504] {
+            assert_eq!(next(&status(code)), Next::OtherNode, "{}", code);
+        }
+        for code in [400, 404, 413] {
+            assert_eq!(next(&status(code)), Next::Stop, "{}", code);
+        }
+        assert_eq!(next(&ClientError::Transport(format!("connection refused"))), Next::OtherNode);
+        assert_eq!(next(&ClientError::Timeout), Next::OtherNode);
+        assert_eq!(next(&ClientError::NotCapable(format!("no llama:65B"))), Next::OtherNode);
+        assert_eq!(next(&ClientError::Decode(format!("bad json"))), Next::Stop);
+        assert_eq!(next(&ClientError::Io(std::io::Error::new(std::io::ErrorKind::NotFound, "input"))), Next::Stop);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let policy = MeshPolicy::default();
+        assert_eq!(policy.backoff(0), Duration::from_millis(250));
+        assert_eq!(policy.backoff(1), Duration::from_millis(500));
+        assert_eq!(policy.backoff(2), Duration::from_secs(1));
+        assert_eq!(policy.backoff(4), Duration::from_secs(4));
+        assert_eq!(policy.backoff(5), Duration::from_secs(5));
+        assert_eq!(policy.backoff(100), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn breaker_opens_at_the_threshold_until_the_cooldown_ends() {
+        let policy = policy();
+        let now = Instant::now();
+        let mut breaker = Breaker::default();
+        assert!(breaker.allows_at(now));
+        assert!(!breaker.fail(now, &policy));
+        assert!(breaker.allows_at(now));
+
+        assert!(breaker.fail(now, &policy));
+        assert!(!breaker.allows_at(now));
+        assert!(!breaker.allows_at(now + Duration::from_secs(9)));
+        assert!(breaker.allows_at(now + policy.cooldown));
+    }
+
+    #[test]
+    fn closed_breaker_admits_every_caller() {
+        let policy = policy();
+        let now = Instant::now();
+        let mut breaker = Breaker::default();
+        assert!(breaker.admit(now, &policy));
+        assert!(breaker.admit(now, &policy));
+        assert!(breaker.probing_until.is_none());
+    }
+
+    #[test]
+    fn half_open_breaker_lets_one_probe_through() {
+        let policy = policy();
+        let now = Instant::now();
+        let mut breaker = open(now, &policy);
+        let later = now + policy.cooldown;
+
+        assert!(breaker.admit(later, &policy));
+        assert!(!breaker.admit(later, &policy));
+        assert!(!breaker.allows_at(later + Duration::from_secs(9)));
+
+        // A probe that never reports back stops holding the breaker after another cooldown
+        assert!(breaker.admit(later + policy.cooldown, &policy));
+    }
+
+    #[test]
+    fn failed_probe_reopens_the_breaker() {
+        let policy = policy();
+        let now = Instant::now();
+        let mut breaker = open(now, &policy);
+        let later = now + policy.cooldown;
+
+        assert!(breaker.admit(later, &policy));
+        assert!(breaker.fail(later, &policy));
+        assert!(breaker.probing_until.is_none());
+        assert!(!breaker.allows_at(later + Duration::from_secs(9)));
+        assert!(breaker.allows_at(later + policy.cooldown));
+    }
+
+    #[test]
+    fn successful_probe_closes_the_breaker() {
+        let policy = policy();
+        let pid = "mesh-test-close";
+        for _ in 0..policy.failure_threshold {
+            record_failure(pid, &policy);
+        }
+        assert!(!breaker(pid).allows());
+
+        // Cooldown over
+        breakers().get_mut(pid).unwrap().open_until = Some(Instant::now());
+        assert!(admit(pid, &policy));
+        assert!(!admit(pid, &policy));
+
+        record_success(pid);
+        assert_eq!(breaker(pid).failures, 0);
+        assert!(breaker(pid).open_until.is_none());
+        assert!(admit(pid, &policy));
+        assert!(admit(pid, &policy));
+    }
+
+    #[test]
+    fn failures_that_say_nothing_about_the_node_release_the_probe() {
+        let policy = policy();
+        let node = ThalamusNode::new(format!("mesh-test-release"), format!("0.0.1"), format!("127.0.0.1"), 8050);
+        let pid = node.pid.as_str();
+        for _ in 0..policy.failure_threshold {
+            record_failure(pid, &policy);
+        }
+        breakers().get_mut(pid).unwrap().open_until = Some(Instant::now());
+
+        assert!(admit(pid, &policy));
+        assert_eq!(on_failure(&node, &status(404), &policy), Next::Stop);
+        assert!(admit(pid, &policy));
+        assert_eq!(on_failure(&node, &ClientError::NotCapable(format!("no tts")), &policy), Next::OtherNode);
+        assert_eq!(breaker(pid).failures, policy.failure_threshold);
+
+        // A transport failure counts against the node and reopens it
+        assert!(admit(pid, &policy));
+        assert_eq!(on_failure(&node, &ClientError::Timeout, &policy), Next::OtherNode);
+        assert!(!breaker(pid).allows());
+    }
+}
@@ -12,9 +12,9 @@ use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
 use serde::{Serialize, Deserialize};
 
-use std::error::Error;
 use std::sync::{Arc, Mutex};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 
 // use tokio::task;
@@ -25,6 +25,16 @@ extern crate rouille;
 
 pub mod thalamus;
 pub mod p2p;
+pub mod client;
+pub mod storage;
+pub mod cli;
+pub mod bench;
+pub mod logging;
+pub mod trace;
+
+pub use crate::client::{ClientError, Retry};
+pub use crate::thalamus::services::llama::LlamaModel;
+pub use crate::thalamus::services::whisper::{WhisperFormat, WhisperModel};
 
 use clap::Parser;
 
@@ -44,6 +54,47 @@ pub struct Args {
     pub encrypt: bool,
     #[arg(short, long, default_value = "thalamus")]
     pub key: String,
+    /// Multiaddr of a rendezvous point to register with (repeatable)
+    #[arg(long)]
+    pub rendezvous: Vec<String>,
+    /// Multiaddr (ending in /p2p/<peer id>) of a DHT bootstrap peer (repeatable)
+    #[arg(long)]
+    pub bootstrap: Vec<String>,
+    /// Act as a circuit relay for nodes behind NAT
+    #[arg(long, default_value_t = false)]
+    pub relay: bool,
+    /// State store backend: json (clients.json) or sqlite (thalamus.db)
+    #[arg(long, default_value = "json")]
+    pub storage: String,
+    /// Hours between scheduled re-benchmarks of each node (0 disables them)
+    #[arg(long, default_value_t = 24)]
+    pub benchmark_interval: u64,
+    /// Log level, optionally per module, e.g. "info,thalamus::p2p=debug,libp2p=warn"
+    #[arg(long, default_value = "info")]
+    pub log_level: String,
+    /// Log line format: logfmt, json or text
+    #[arg(long, default_value = "logfmt")]
+    pub log_format: String,
+    /// Rotate output.log once it reaches this many megabytes
+    #[arg(long, default_value_t = 50)]
+    pub log_max_size: u64,
+    /// Number of rotated logs to keep (output.log.1, output.log.2, ...)
+    #[arg(long, default_value_t = 10)]
+    pub log_keep: usize,
+    /// Hours to keep job outputs in the artifact store
+    #[arg(long, default_value_t = 24)]
+    pub artifact_ttl: u64,
+    /// Megabytes the artifact store may use before the oldest outputs are deleted
+    #[arg(long, default_value_t = 2048)]
+    pub artifact_max_size: u64,
+    /// OTLP/HTTP collector to export traces to, e.g. http://localhost:4318
+    #[arg(long)]
+    pub otlp_endpoint: Option<String>,
+    /// Send CLI calls to this node (pid or host:port) instead of picking one
+    #[arg(long, global = true)]
+    pub node: Option<String>,
+    #[command(subcommand)]
+    pub command: Option<crate::cli::Command>,
 }
 
 pub async fn nodex_discovery(thalamus: Arc<Mutex<ThalamusClient>>){
@@ -53,7 +104,7 @@ pub async fn nodex_discovery(thalamus: Arc<Mutex<ThalamusClient>>){
     std::mem::drop(thalamus_x);
     
     for node in thx_clone.nodes{
-        let nodexs_wrap = node.nodex();
+        let nodexs_wrap = node.async_nodex(std::time::Duration::from_secs(30)).await;
         match nodexs_wrap {
             Ok(nodexs) => {
                 for nodex in nodexs{
@@ -61,8 +112,12 @@ pub async fn nodex_discovery(thalamus: Arc<Mutex<ThalamusClient>>){
 
                     let existing_index = thalamus_x.nodes.clone().iter().position(|r| r.pid == nodex.pid.to_string());
                     match existing_index {
-                        Some(_) => {
-
+                        Some(index) => {
+                            // A node's own record carries its current traffic
+                            if nodex.pid == node.pid {
+                                thalamus_x.nodes[index].traffic = nodex.traffic;
+                                thalamus_x.save();
+                            }
                         },
                         None => {
                             thalamus_x.nodes.push(nodex);
@@ -100,6 +155,8 @@ pub async fn mdns_discovery(thalamus: Arc<Mutex<ThalamusClient>>, discovery: sim
                                 Some(index) => {
       
                                     thalamus_x.nodes[index].is_online = true;
+                                    thalamus_x.nodes[index].p2p_only = false;
+                                    thalamus_x.nodes[index].version = v.version.to_string();
                                     thalamus_x.nodes[index].last_ping = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
                                     // log::info!("NODE_ONLINE: {:?}", thalamus_x.nodes[index].clone());
                                 
@@ -174,7 +231,7 @@ pub async fn mdns_discovery(thalamus: Arc<Mutex<ThalamusClient>>, discovery: sim
             let thx = thalamus_x.clone();
             std::mem::drop(thalamus_x);
             for node in thx.nodes {
-                if !node.jobs.iter().any(|i| i.job_identifier=="calculate_stats") && node.stats.whisper_stt_tiny.is_none(){
+                if !node.jobs.iter().any(|i| i.job_identifier=="calculate_stats") && node.stats.benchmarks.is_empty(){
                     log::warn!("STATS ARE MISSING FOR NODE: {:?}.....CALCULATING NOW...", node.pid.clone());
                     calc_stats(Arc::clone(&thalamus), node.pid.to_string(), node.version.to_string(), format!("{}", node.ip_address), node.port);
                 }
@@ -187,39 +244,191 @@ pub async fn mdns_discovery(thalamus: Arc<Mutex<ThalamusClient>>, discovery: sim
     Ok(discovery)
 }
 
-pub fn calc_stats(thalamus: Arc<Mutex<ThalamusClient>>, pid: String, version: String, ipx: String, port: u16){
-    // Calculate Stats for new node
+/// Benchmarks a node in the background, returning None when a run is already going
+pub fn calc_stats(thalamus: Arc<Mutex<ThalamusClient>>, pid: String, version: String, ipx: String, port: u16) -> Option<ThalamusNodeJob>{
+
+    // Commit job to memory
+    let job = ThalamusNodeJob::new("calculate_stats".to_string());
+    let mut peer_id: Option<String> = None;
+    let mut p2p_only = false;
+    let mut thalamus_x = thalamus.lock().unwrap();
+    for node in &mut thalamus_x.nodes{
+        if node.pid == pid.to_string(){
+            if node.jobs.iter().any(|i| i.job_identifier == "calculate_stats") {
+                return None;
+            }
+            node.jobs.push(job.clone());
+            peer_id = node.peer_id.clone();
+            p2p_only = node.p2p_only;
+        }
+    }
+    thalamus_x.save();
+    std::mem::drop(thalamus_x);
+
+    // Calculate Stats for the node
     let node_thc = Arc::clone(&thalamus);
+    let thread_job = job.clone();
     std::thread::spawn(move || {
+        let job = thread_job;
+
+        // Generate stats using dummy node data
+        let mut node_ref = ThalamusNode::new(pid.to_string(), version.to_string(), ipx, port);
+        node_ref.peer_id = peer_id;
+        node_ref.p2p_only = p2p_only;
+
+        // Hardware inventory, so selection knows what the node can hold
+        match node_ref.hardware() {
+            Ok(hardware) => {
+                let mut thalamus_x = node_thc.lock().unwrap();
+                for node in &mut thalamus_x.nodes{
+                    if node.pid == pid.to_string(){
+                        node.hardware = Some(hardware.clone());
+                    }
+                }
+                thalamus_x.save();
+                std::mem::drop(thalamus_x);
+            },
+            Err(e) => log::error!("{}: unable to fetch hardware: {}", pid, e),
+        }
+        // Checked between measurements, so a cancel stops the run after the test in flight
+        let cancel_thc = Arc::clone(&node_thc);
+        let cancel_oid = job.oid.clone();
+        let is_cancelled = move || {
+            let thalamus_x = cancel_thc.lock().unwrap();
+            return thalamus_x.nodes.iter()
+                .flat_map(|n| n.jobs.iter())
+                .any(|x| x.oid == cancel_oid && x.status.as_deref() == Some("cancelled"));
+        };
+        let mut stats = ThalamusNodeStats::calculate(node_ref.clone(), &is_cancelled);
+        stats.benchmarked_at = Some(SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64);
+        stats.benchmarked_version = Some(version.to_string());
 
-        // Commit job to memory
-        let job = ThalamusNodeJob::new("calculate_stats".to_string());
+        // Cancelled while running: drop the results and keep the old stats
         let mut thalamus_x = node_thc.lock().unwrap();
-        for node in &mut thalamus_x.nodes{
-            if node.pid == pid.to_string(){
-                node.jobs.push(job.clone());
+        let cancelled = thalamus_x.nodes.iter()
+            .filter(|n| n.pid == pid)
+            .flat_map(|n| n.jobs.iter())
+            .any(|x| x.oid == job.oid && x.status.as_deref() == Some("cancelled"));
+        if cancelled {
+            for node in &mut thalamus_x.nodes{
+                if node.pid == pid.to_string(){
+                    node.jobs.retain(|x| x.oid != job.oid);
+                }
             }
+            thalamus_x.save();
+            std::mem::drop(thalamus_x);
+            log::warn!("{}: benchmark {} cancelled", pid, job.oid);
+            match crate::storage::store().record_job(&crate::storage::JobRecord::new(pid.to_string(), &job, "cancelled")) {
+                Ok(_) => {},
+                Err(e) => log::error!("Unable to record job {}: {}", job.oid, e),
+            }
+            return;
         }
-        thalamus_x.save();
-        std::mem::drop(thalamus_x);
-
-        // Generate stats using dummy node data
-        let node_ref = ThalamusNode::new(pid.to_string(), version.to_string(), ipx, port);
-        let stats = ThalamusNodeStats::calculate(node_ref.clone());
 
         // Commit stats to memory
-        let mut thalamus_x = node_thc.lock().unwrap();
         for node in &mut thalamus_x.nodes{
             if node.pid == pid.to_string(){
                 node.stats = stats.clone();
-                let index = node.jobs.iter().position(|x| *x.oid == job.oid.to_string() || *x.job_identifier == format!("calculate_stats")).unwrap();
-                node.jobs.remove(index);
+                node.jobs.retain(|x| x.oid != job.oid);
             }
         }
         thalamus_x.save();
         std::mem::drop(thalamus_x);
+
+        // Keep the run in history
+        let store = crate::storage::store();
+        match store.record_job(&crate::storage::JobRecord::new(pid.to_string(), &job, "completed")) {
+            Ok(_) => {},
+            Err(e) => log::error!("Unable to record job {}: {}", job.oid, e),
+        }
+        let benchmark = crate::storage::BenchmarkRecord {
+            pid: pid.to_string(),
+            version: version.to_string(),
+            ran_at: stats.benchmarked_at.unwrap_or(0),
+            stats: stats,
+        };
+        match store.record_benchmark(&benchmark) {
+            Ok(_) => {},
+            Err(e) => log::error!("Unable to record benchmark for {}: {}", pid, e),
+        }
     
     });
+
+    return Some(job);
+}
+
+/// Drops the jobs the previous run left on node records: this node's own jobs and the
+/// benchmarks it was running on any node, since those threads died with that process.
+/// Peers' jobs are left alone; their next snapshot replaces them.
+pub fn reconcile_jobs(thalamus: Arc<Mutex<ThalamusClient>>){
+    let own_pid = std::fs::read_to_string("/opt/thalamus/pid").unwrap_or_default().trim().to_string();
+    let mut thalamus_x = thalamus.lock().unwrap();
+    let mut stale: Vec<(String, ThalamusNodeJob)> = Vec::new();
+    for node in &mut thalamus_x.nodes{
+        let own = node.pid == own_pid;
+        let (dropped, kept): (Vec<ThalamusNodeJob>, Vec<ThalamusNodeJob>) = node.jobs.drain(..)
+            .partition(|job| own || job.job_identifier == "calculate_stats");
+        node.jobs = kept;
+        for job in dropped{
+            stale.push((node.pid.to_string(), job));
+        }
+    }
+    if stale.is_empty() {
+        return;
+    }
+    thalamus_x.save();
+    std::mem::drop(thalamus_x);
+
+    log::warn!("Cleared {} stale jobs from the last run", stale.len());
+    let store = crate::storage::store();
+    for (pid, job) in stale.iter(){
+        if job.job_identifier == "calculate_stats" {
+            match store.record_job(&crate::storage::JobRecord::new(pid.to_string(), job, "abandoned")) {
+                Ok(_) => {},
+                Err(e) => log::error!("Unable to record job {}: {}", job.oid, e),
+            }
+        }
+    }
+}
+
+/// Marks a node job cancelled; a running benchmark finishes the test in flight and then stops.
+/// Returns None if no node has a job with that oid.
+pub fn cancel_node_job(thalamus: Arc<Mutex<ThalamusClient>>, oid: &str) -> Option<ThalamusNodeJob>{
+    let mut thalamus_x = thalamus.lock().unwrap();
+    let mut cancelled: Option<ThalamusNodeJob> = None;
+    for node in &mut thalamus_x.nodes{
+        for job in &mut node.jobs{
+            if job.oid == oid {
+                job.status = Some(format!("cancelled"));
+                cancelled = Some(job.clone());
+            }
+        }
+    }
+    if cancelled.is_some() {
+        thalamus_x.save();
+    }
+    std::mem::drop(thalamus_x);
+    return cancelled;
+}
+
+/// Re-benchmarks nodes whose stats are older than the interval or predate a version change
+pub fn benchmark_scheduler(thalamus: Arc<Mutex<ThalamusClient>>, interval: std::time::Duration){
+    std::thread::spawn(move || {
+        loop {
+            std::thread::sleep(std::time::Duration::from_secs(60));
+
+            let thalamus_x = thalamus.lock().unwrap();
+            let thx = thalamus_x.clone();
+            std::mem::drop(thalamus_x);
+
+            for node in thx.nodes {
+                if node.is_online && node.stats.benchmark_due(node.version.as_str(), interval) {
+                    log::warn!("Re-benchmarking node {}", node.pid);
+                    calc_stats(Arc::clone(&thalamus), node.pid.to_string(), node.version.to_string(), format!("{}", node.ip_address), node.port);
+                }
+            }
+        }
+    });
 }
 
 
@@ -227,12 +436,19 @@ pub fn calc_stats(thalamus: Arc<Mutex<ThalamusClient>>, pid: String, version: St
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ThalamusClient {
     pub nodes: Vec<ThalamusNode>,
+    /// Retry, backoff and circuit breaker settings for mesh-level calls
+    #[serde(skip)]
+    pub policy: crate::client::MeshPolicy,
+    #[serde(default)]
+    pub schema_version: u64,
 }
 impl ThalamusClient {
     pub fn new() -> ThalamusClient {
         let x: Vec<ThalamusNode> = Vec::new();
         ThalamusClient { 
             nodes: x,
+            policy: crate::client::MeshPolicy::default(),
+            schema_version: crate::storage::SCHEMA_VERSION,
         }
     }
 
@@ -282,66 +498,82 @@ impl ThalamusClient {
 
 
 
-    pub fn save(&self){
-        std::fs::File::create("/opt/thalamus/clients.json").expect("create failed");
-        let j = serde_json::to_string(&self).unwrap();
-        std::fs::write("/opt/thalamus/clients.json", j).expect("Unable to write file");
-
-        if self.nodes.len() > 0 {
-            std::fs::File::create("/opt/thalamus/clients.bak.json").expect("create failed");
-            let j = serde_json::to_string(&self).unwrap();
-            std::fs::write("/opt/thalamus/clients.bak.json", j).expect("Unable to write file");
-        }
+    /// Finds the nodes advertising a capability (e.g. whisper:medium, llama:7B) across the mesh.
+    /// Blocks on a DHT lookup, so call it on a clone rather than while holding the client lock.
+    pub fn find_providers(&self, capability: &str) -> Result<Vec<ThalamusNode>, ClientError> {
+        let providers = crate::p2p::find_providers(capability)?;
+        return Ok(self.providing(providers));
     }
 
-    pub fn load(retries: i64) -> Result<ThalamusClient, Box<dyn Error>>{
+    /// Finds the nodes advertising a capability across the mesh without blocking the runtime.
+    pub async fn async_find_providers(&self, capability: &str) -> Result<Vec<ThalamusNode>, ClientError> {
+        let providers = crate::p2p::async_find_providers(capability).await?;
+        return Ok(self.providing(providers));
+    }
 
-        if !std::path::Path::new("/opt/thalamus/clients.json").exists(){
-            let new_c = ThalamusClient::new();
-            new_c.save();
-            return Ok(new_c);
+    // Known nodes whose peer id is in a provider lookup
+    fn providing(&self, providers: Vec<String>) -> Vec<ThalamusNode> {
+        let mut nodes: Vec<ThalamusNode> = Vec::new();
+        for node in self.nodes.iter() {
+            match &node.peer_id {
+                Some(peer_id) => {
+                    if providers.contains(peer_id) {
+                        nodes.push(node.clone());
+                    }
+                },
+                None => {}
+            }
         }
+        return nodes;
+    }
 
-        let save_file = std::fs::read_to_string("/opt/thalamus/clients.json");
-        match save_file {
-            Ok(save_data) => {
-                let v: Result<ThalamusClient, _> = serde_json::from_str(&save_data);
-                match v {
-                    Ok(v2) => {
-                        return Ok(v2);
-                    },
-                    Err(e) => {
-                        log::error!("{}", format!("Unable to parse save file: {}", e));
-                        
-                        if retries < 10 {
-                            std::fs::copy("/opt/thalamus/clients.bak.json", "/opt/thalamus/clients.json")?;
-                            std::thread::sleep(std::time::Duration::from_secs(2));
-                            return Self::load(retries + 1);
-                        } else {
-                            log::warn!("Unable to parse save file after 10 attempts....creating new save file.");
-                            let new_c = ThalamusClient::new();
-                            new_c.save();
-                            return Ok(new_c);
-                        }
-                 
-                    }
-                }
-                
+    /// Finished jobs, most recent first, optionally for one node
+    pub fn job_history(&self, pid: Option<&str>, limit: usize) -> Result<Vec<crate::storage::JobRecord>, ClientError> {
+        return Ok(crate::storage::store().jobs(pid, limit)?);
+    }
+
+    /// Benchmark runs of a node, most recent first
+    pub fn benchmark_history(&self, pid: &str, limit: usize) -> Result<Vec<crate::storage::BenchmarkRecord>, ClientError> {
+        return Ok(crate::storage::store().benchmarks(pid, limit)?);
+    }
+
+    /// Hands the client to the storage backend (debounced for clients.json)
+    pub fn save(&self){
+        crate::storage::store().save(self);
+    }
+
+    /// Saves and writes any pending update immediately
+    pub fn flush(&self) -> Result<(), ClientError>{
+        self.save();
+        crate::storage::store().flush()?;
+        return Ok(());
+    }
+
+    /// Loads the saved client, creating one when nothing was saved. Only transient I/O and
+    /// lock errors are retried; unreadable or newer-schema state is returned as an error
+    /// rather than overwritten.
+    pub fn load(retries: i64) -> Result<ThalamusClient, ClientError>{
+
+        match crate::storage::store().load() {
+            Ok(Some(client)) => {
+                return Ok(client);
+            },
+            Ok(None) => {},
+            Err(e) if crate::storage::transient(&e) && retries < 10 => {
+                log::error!("{}", format!("Unable to read save file, retrying: {}", e));
+                std::thread::sleep(std::time::Duration::from_secs(2));
+                return Self::load(retries + 1);
             },
             Err(e) => {
-                log::error!("{}", format!("Unable to read save file: {}", e));
-                if retries < 10 {
-                    std::fs::copy("/opt/thalamus/clients.bak.json", "/opt/thalamus/clients.json")?;
-                    std::thread::sleep(std::time::Duration::from_secs(2));
-                    return Self::load(retries + 1);
-                } else {
-                    log::warn!("Unable to read save file after 10 attempts....creating new save file.");
-                    let new_c = ThalamusClient::new();
-                    new_c.save();
-                    return Ok(new_c);
-                }
+                log::error!("{}", format!("Unable to load save file: {}", e));
+                return Err(e.into());
             }
         }
+
+        log::warn!("No usable save file....creating new save file.");
+        let new_c = ThalamusClient::new();
+        new_c.save();
+        return Ok(new_c);
     }
 
     // pub fn select_optimal_node(&self, node_type: String) -> Result<ThalamusNode, Box<dyn Error + '_>> {
@@ -376,14 +608,16 @@ impl ThalamusClient {
 
 
 
-pub fn fetch_version(host: &str, port: u16) -> Result<VersionReply, Box<dyn Error>> {
+pub fn fetch_version(host: &str, port: u16) -> Result<VersionReply, ClientError> {
     let client = reqwest::blocking::Client::builder().build()?;
-    return Ok(client.get(format!("http://{}:{}/api/thalamus/version", host, port.clone())).send()?.json()?);
+    let response = client.get(format!("http://{}:{}/api/thalamus/version", host, port.clone())).send()?;
+    return Ok(crate::client::error::check_blocking(response)?.json()?);
 }
 
-pub async fn async_fetch_version(host: &str, port: u16) -> Result<VersionReply, Box<dyn Error>> {
+pub async fn async_fetch_version(host: &str, port: u16) -> Result<VersionReply, ClientError> {
     let client = reqwest::Client::builder().build()?;
-    return Ok(client.get(format!("http://{}:{}/api/thalamus/version", host, port.clone())).send().await?.json().await?);
+    let response = client.get(format!("http://{}:{}/api/thalamus/version", host, port.clone())).send().await?;
+    return Ok(crate::client::error::check(response).await?.json().await?);
 }
 
 
@@ -402,6 +636,19 @@ pub struct ThalamusNode {
     pub last_ping: i64,
     pub stats: ThalamusNodeStats,
     pub is_online: bool,
+    #[serde(default)]
+    pub peer_id: Option<String>,
+    #[serde(default)]
+    pub p2p_only: bool,
+    /// Reached through a relay circuit rather than a direct connection
+    #[serde(default)]
+    pub relayed: bool,
+    /// Rolling stats from the node's real traffic, keyed like "whisper:base"
+    #[serde(default)]
+    pub traffic: BTreeMap<String, crate::thalamus::traffic::RollingStats>,
+    /// What the node runs on, from /api/thalamus/hardware
+    #[serde(default)]
+    pub hardware: Option<crate::thalamus::hardware::Hardware>,
 }
 impl ThalamusNode {
 
@@ -417,317 +664,172 @@ impl ThalamusNode {
             last_ping: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64,
             stats: ThalamusNodeStats::new(),
             is_online: true,
+            peer_id: None,
+            p2p_only: false,
+            relayed: false,
+            traffic: BTreeMap::new(),
+            hardware: None,
         };
         let stats = ThalamusNodeStats::new();
         node.stats = stats;
         return node;
     }
 
-    pub fn yolov7(&self, file_path: String) -> Result<STTReply, Box<dyn Error>>{
-        let form = reqwest::blocking::multipart::Form::new().file("image_file", file_path.as_str())?;
-
-        let client = reqwest::blocking::Client::builder().timeout(None).build()?;
-
-        return Ok(client.post(format!("http://{}:{}/api/services/image/yolo/v7", self.ip_address.clone(), self.port.clone()))
-        .multipart(form)
-        .send()?.json()?);
-    }
-
-    pub fn whisper_stt_tiny(&self, file_path: String) -> Result<STTReply, Box<dyn Error>>{
-        let form = reqwest::blocking::multipart::Form::new().text("method", "tiny").file("speech", file_path.as_str())?;
-
-        let client = reqwest::blocking::Client::builder().timeout(None).build()?;
-
-        return Ok(client.post(format!("http://{}:{}/api/services/whisper", self.ip_address.clone(), self.port.clone()))
-        .multipart(form)
-        .send()?.json()?);
-    }
-
-    pub fn whisper_stt_base(&self, file_path: String) -> Result<STTReply, Box<dyn Error>>{
-        let form = reqwest::blocking::multipart::Form::new().text("method", "basic").file("speech", file_path.as_str())?;
-
-        let client = reqwest::blocking::Client::builder().timeout(None).build()?;
-
-        return Ok(client.post(format!("http://{}:{}/api/services/whisper", self.ip_address.clone(), self.port.clone()))
-        .multipart(form)
-        .send()?.json()?);
-    }
-
-    pub fn whisper_stt_medium(&self, file_path: String) -> Result<STTReply, Box<dyn Error>>{
-        let form = reqwest::blocking::multipart::Form::new().text("method", "medium").file("speech", file_path.as_str())?;
-
-        let client = reqwest::blocking::Client::builder().timeout(None).build()?;
-
-        return Ok(client.post(format!("http://{}:{}/api/services/whisper", self.ip_address.clone(), self.port.clone()))
-        .multipart(form)
-        .send()?.json()?);
-    }
-
-    pub fn whisper_stt_large(&self, file_path: String) -> Result<STTReply, Box<dyn Error>>{
-        let form = reqwest::blocking::multipart::Form::new().text("method", "large").file("speech", file_path.as_str())?;
-
-        let client = reqwest::blocking::Client::builder().timeout(None).build()?;
-
-        return Ok(client.post(format!("http://{}:{}/api/services/whisper", self.ip_address.clone(), self.port.clone()))
-        .multipart(form)
-        .send()?.json()?);
-    }
-
-    pub fn whisper_vwav_tiny(&self, file_path: String) -> Result<Vec<u8>, Box<dyn Error>>{
-        
-        let url = format!("http://{}:{}/api/services/whisper/vwav", self.ip_address.clone(), self.port.clone());
-
-        log::info!("Fetching VWAV from {}", url);
-        
-        let form = reqwest::blocking::multipart::Form::new().text("method", "tiny").file("speech", file_path.as_str())?;
-
-        let client = reqwest::blocking::Client::builder().timeout(None).build()?;
-
-        let bytes = client.post(url)
-        .multipart(form)
-        .send()?.bytes()?;
-
-        return Ok(bytes.to_vec());
+    /// Returns the peer id to use when this node is only reachable over p2p
+    pub(crate) fn via_p2p(&self) -> Option<String> {
+        if self.p2p_only {
+            return self.peer_id.clone();
+        }
+        None
     }
 
-    pub fn whisper_vwav_base(&self, file_path: String) -> Result<Vec<u8>, Box<dyn Error>>{
-                
-        let url = format!("http://{}:{}/api/services/whisper/vwav", self.ip_address.clone(), self.port.clone());
-
-        log::info!("Fetching VWAV from {}", url);
-        
-        
-        let form = reqwest::blocking::multipart::Form::new().text("method", "base").file("speech", file_path.as_str())?;
-
-        let client = reqwest::blocking::Client::builder().timeout(None).build()?;
-
-        let bytes = client.post(url)
-        .multipart(form)
-        .send()?.bytes()?;
-
-        return Ok(bytes.to_vec());
+    pub fn yolov7<I: Into<crate::client::Input>>(&self, image: I) -> Result<STTReply, ClientError>{
+        return self.yolov7_timeout(image, crate::client::DEFAULT_TIMEOUT);
     }
 
-    pub fn whisper_vwav_medium(&self, file_path: String) -> Result<Vec<u8>, Box<dyn Error>>{
-        let url = format!("http://{}:{}/api/services/whisper/vwav", self.ip_address.clone(), self.port.clone());
-
-        log::info!("Fetching VWAV from {}", url);
-        
-        let form = reqwest::blocking::multipart::Form::new().text("method", "medium").file("speech", file_path.as_str())?;
+    pub fn yolov7_timeout<I: Into<crate::client::Input>>(&self, image: I, timeout: Duration) -> Result<STTReply, ClientError>{
+        let image = image.into();
+        if let Some(peer_id) = self.via_p2p() {
+            return crate::p2p::infer(peer_id.as_str(), crate::p2p::infer::InferRequest::Yolov7 { image: image.into_vec()? }, timeout)?.into_json();
+        }
 
-        let client = reqwest::blocking::Client::builder().timeout(None).build()?;
+        let file_name = format!("image.{}", image.extension());
+        let form = reqwest::blocking::multipart::Form::new().part("image_file", image.into_part(file_name)?);
 
-        let bytes = client.post(url)
+        let span = crate::trace::client(format!("POST /api/services/image/yolo/v7"), self.pid.as_str());
+        let response = crate::client::blocking_http_client(self)?.post(format!("http://{}:{}/api/services/image/yolo/v7", self.ip_address.clone(), self.port.clone()))
+        .timeout(timeout)
         .multipart(form)
-        .send()?.bytes()?;
+        .header("traceparent", span.traceparent())
+        .send()?;
 
-        return Ok(bytes.to_vec());
+        return Ok(crate::client::error::check_blocking(response)?.json()?);
     }
 
-    pub fn whisper_vwav_large(&self, file_path: String) -> Result<Vec<u8>, Box<dyn Error>>{
-        let url = format!("http://{}:{}/api/services/whisper/vwav", self.ip_address.clone(), self.port.clone());
-
-        log::info!("Fetching VWAV from {}", url);
-        
-        let form = reqwest::blocking::multipart::Form::new().text("method", "large").file("speech", file_path.as_str())?;
-
-        let client = reqwest::blocking::Client::builder().timeout(None).build()?;
-
-        let bytes = client.post(url)
-        .multipart(form)
-        .send()?.bytes()?;
-
-        return Ok(bytes.to_vec());
+    pub fn srgan<I: Into<crate::client::Input>>(&self, image: I) -> Result<Vec<u8>, ClientError>{
+        return self.srgan_timeout(image, crate::client::DEFAULT_TIMEOUT);
     }
 
-    pub fn srgan(&self, file_path: String) -> Result<Vec<u8>, Box<dyn Error>>{
-
-        let parts: Vec<&str> = file_path.split('.').collect();
-
-        let extension = parts[parts.len() - 1];
+    pub fn srgan_timeout<I: Into<crate::client::Input>>(&self, image: I, timeout: Duration) -> Result<Vec<u8>, ClientError>{
+        let image = image.into();
 
+        // The server stores uploads under this name, so keep it unique
         let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as i64;
 
-        let new_file_name = format!("{}.{}", timestamp, extension);
+        let new_file_name = format!("{}.{}", timestamp, image.extension());
 
-        let form = reqwest::blocking::multipart::Form::new().text("filename", new_file_name).file("input_file", file_path.as_str())?;
+        if let Some(peer_id) = self.via_p2p() {
+            return crate::p2p::infer(peer_id.as_str(), crate::p2p::infer::InferRequest::Srgan { filename: new_file_name, image: image.into_vec()? }, timeout)?.into_bytes();
+        }
 
-        let client = reqwest::blocking::Client::builder().timeout(None).build()?;
+        let form = reqwest::blocking::multipart::Form::new().text("filename", new_file_name.clone()).part("input_file", image.into_part(new_file_name)?);
 
-        let bytes = client.post(format!("http://{}:{}/api/services/image/srgan", self.ip_address.clone(), self.port.clone()))
+        let span = crate::trace::client(format!("POST /api/services/image/srgan"), self.pid.as_str());
+        let response = crate::client::blocking_http_client(self)?.post(format!("http://{}:{}/api/services/image/srgan", self.ip_address.clone(), self.port.clone()))
+        .timeout(timeout)
         .multipart(form)
-        .send()?.bytes()?;
+        .header("traceparent", span.traceparent())
+        .send()?;
+
+        let bytes = crate::client::error::check_blocking(response)?.bytes()?;
 
         return Ok(bytes.to_vec());
     }
 
-    pub fn llama(&self, prompt: String, model: String) -> Result<String, Box<dyn Error>>{
-        let params = [("model", model.as_str()), ("prompt", prompt.as_str())];
-
-        let client = reqwest::blocking::Client::builder().timeout(None).build()?;
+    pub fn hardware(&self) -> Result<crate::thalamus::hardware::Hardware, ClientError>{
+        if let Some(peer_id) = self.via_p2p() {
+            return crate::p2p::infer(peer_id.as_str(), crate::p2p::infer::InferRequest::Hardware, Duration::from_secs(30))?.into_json();
+        }
 
-        let bytes = client.post(format!("http://{}:{}/api/services/llama", self.ip_address.clone(), self.port.clone()))
-        .form(&params)
-        .send()?.text()?;
+        let span = crate::trace::client(format!("GET /api/thalamus/hardware"), self.pid.as_str());
+        let response = crate::client::blocking_http_client(self)?.get(format!("http://{}:{}/api/thalamus/hardware", self.ip_address.clone(), self.port.clone()))
+        .timeout(Duration::from_secs(30))
+        .header("traceparent", span.traceparent())
+        .send()?;
 
-        return Ok(bytes.to_string());
+        return Ok(crate::client::error::check_blocking(response)?.json()?);
     }
 
-    pub fn tts(&self, prompt: String, primary: String, fallback: String) -> Result<Vec<u8>, Box<dyn Error>>{
-        let params = [("text", prompt.as_str()), ("primary", primary.as_str()), ("fallback", fallback.as_str())];
-
-        let client = reqwest::blocking::Client::builder().timeout(None).build()?;
-
-        let bytes = client.post(format!("http://{}:{}/api/services/tts", self.ip_address.clone(), self.port.clone()))
-        .form(&params)
-        .send()?.bytes()?;
-
-        return Ok(bytes.to_vec());
+    pub fn nodex(&self) -> Result<Vec<ThalamusNode>, ClientError>{
+        return self.nodex_timeout(Duration::from_secs(30));
     }
 
-    pub fn nodex(&self) -> Result<Vec<ThalamusNode>, Box<dyn Error>>{
-        let client = reqwest::blocking::Client::builder().timeout(None).build()?;
-
+    pub fn nodex_timeout(&self, timeout: Duration) -> Result<Vec<ThalamusNode>, ClientError>{
         let mut url = format!("http://{}:{}/api/nodex", self.ip_address.clone(), self.port.clone());
         if !url.contains(":") {
             url = format!("{}:{}", url, self.port.clone());
         }
 
-        return Ok(client.get(url)
-        .send()?.json()?);
+        let span = crate::trace::client(format!("GET /api/nodex"), self.pid.as_str());
+        let response = crate::client::blocking_http_client(self)?.get(url)
+        .timeout(timeout)
+        .header("traceparent", span.traceparent())
+        .send()?;
+
+        return Ok(crate::client::error::check_blocking(response)?.json()?);
     }
 
-    pub fn test_tts(&self) -> Result<std::option::Option<i64>, std::sync::mpsc::RecvTimeoutError>{
-        log::info!("{}: Running TTS test...", self.pid);
+    // Times a call on its own thread, giving up after the timeout
+    fn timed<F>(&self, timeout: Duration, call: F) -> Result<std::option::Option<i64>, std::sync::mpsc::RecvTimeoutError>
+    where
+        F: FnOnce(&ThalamusNode) -> Result<(), ClientError> + Send + 'static,
+    {
         let (sender, receiver) = mpsc::channel();
         let node_c = self.clone();
         let _t = thread::spawn(move || {
-            let start_timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as i64;
-            let _tts = node_c.tts(format!("hello, my name is sam."), format!("coqui-tts:en_ljspeech"), format!("opensamfoundation")).unwrap();
-            let end_timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as i64;
-            let time_elapsed = Some(end_timestamp - start_timestamp);
+            let start = Instant::now();
+            let result = call(&node_c);
+            let time_elapsed = match result {
+                Ok(_) => Some(start.elapsed().as_millis() as i64),
+                Err(e) => {
+                    log::error!("{}: test failed: {} (retry: {:?})", node_c.pid, e, e.retry());
+                    None
+                }
+            };
 
             match sender.send(time_elapsed) {
                 Ok(()) => {}, // everything good
                 Err(_) => {}, // we have been released, don't panic
             }
         });
-        return receiver.recv_timeout(std::time::Duration::from_millis(100));
+        return receiver.recv_timeout(timeout);
     }
 
-    pub fn test_yolov7(&self) -> Result<std::option::Option<i64>, std::sync::mpsc::RecvTimeoutError>{
-        log::info!("{}: Running YOLOv7 test...", self.pid);
-        let (sender, receiver) = mpsc::channel();
-        let node_c = self.clone();
-        let _t = thread::spawn(move || {
-            let start_timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as i64;
-            let _ = node_c.yolov7("/opt/thalamus/test.jpg".to_string()).unwrap();
-            let end_timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as i64;
-            let time_elapsed = Some(end_timestamp - start_timestamp);
-
-            match sender.send(time_elapsed) {
-                Ok(()) => {}, // everything good
-                Err(_) => {}, // we have been released, don't panic
-            }
-        });
-        return receiver.recv_timeout(std::time::Duration::from_millis(100));
+    pub fn test_tts(&self, fixtures: &crate::bench::Fixtures, timeout: Duration) -> Result<std::option::Option<i64>, std::sync::mpsc::RecvTimeoutError>{
+        log::info!("{}: Running TTS test...", self.pid);
+        let voice = fixtures.tts_voice.clone();
+        let text = fixtures.tts_text.clone();
+        return self.timed(timeout, move |node| node.tts(voice.as_str()).timeout(timeout).say(text).map(|_| ()));
     }
 
+    pub fn test_yolov7(&self, fixtures: &crate::bench::Fixtures, timeout: Duration) -> Result<std::option::Option<i64>, std::sync::mpsc::RecvTimeoutError>{
+        log::info!("{}: Running YOLOv7 test...", self.pid);
+        let image = fixtures.jpg.clone();
+        return self.timed(timeout, move |node| node.yolov7_timeout(image, timeout).map(|_| ()));
+    }
 
-    pub fn test_srgan(&self) -> Result<std::option::Option<i64>, std::sync::mpsc::RecvTimeoutError>{
+    pub fn test_srgan(&self, fixtures: &crate::bench::Fixtures, timeout: Duration) -> Result<std::option::Option<i64>, std::sync::mpsc::RecvTimeoutError>{
         log::info!("{}: Running SRGAN test...", self.pid);
-        let (sender, receiver) = mpsc::channel();
-        let node_c = self.clone();
-        let _t = thread::spawn(move || {
-            let start_timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as i64;
-            let _srgan = node_c.srgan("/opt/thalamus/test.jpg".to_string()).unwrap();
-            let end_timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as i64;
-            let time_elapsed = Some(end_timestamp - start_timestamp);
-
-            match sender.send(time_elapsed) {
-                Ok(()) => {}, // everything good
-                Err(_) => {}, // we have been released, don't panic
-            }
-        });
-        return receiver.recv_timeout(std::time::Duration::from_millis(100));
+        let image = fixtures.jpg.clone();
+        return self.timed(timeout, move |node| node.srgan_timeout(image, timeout).map(|_| ()));
     }
 
-    pub fn test_llama(&self, model: String) -> Result<std::option::Option<i64>, std::sync::mpsc::RecvTimeoutError>{
+    pub fn test_llama(&self, model: LlamaModel, fixtures: &crate::bench::Fixtures, timeout: Duration) -> Result<std::option::Option<i64>, std::sync::mpsc::RecvTimeoutError>{
         log::info!("{}: Running LLAMA {} test...", self.pid, model);
-        let (sender, receiver) = mpsc::channel();
-        let node_c = self.clone();
-        let _t = thread::spawn(move || {
-            let start_timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as i64;
-            let _llama = node_c.llama("Tell me about Abraham Lincoln.".to_string(), model.to_string()).unwrap();
-            let end_timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as i64;
-            let time_elapsed = Some(end_timestamp - start_timestamp);
-
-            match sender.send(time_elapsed) {
-                Ok(()) => {}, // everything good
-                Err(_) => {}, // we have been released, don't panic
-            }
-        });
-        return receiver.recv_timeout(std::time::Duration::from_secs(100));
+        let prompt = fixtures.prompt.clone();
+        return self.timed(timeout, move |node| node.llama(model).timeout(timeout).prompt(prompt).map(|_| ()));
     }
 
-    pub fn test_whisper_stt(&self, model: String) -> Result<std::option::Option<i64>, std::sync::mpsc::RecvTimeoutError>{
+    pub fn test_whisper_stt(&self, model: WhisperModel, fixtures: &crate::bench::Fixtures, timeout: Duration) -> Result<std::option::Option<i64>, std::sync::mpsc::RecvTimeoutError>{
         log::info!("{}: Running Whisper STT {} test...", self.pid, model);
-        let (sender, receiver) = mpsc::channel();
-        let node_c = self.clone();
-        let _t = thread::spawn(move || {
-            let start_timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as i64;
-            if model == "tiny".to_string() {
-                let _stt = node_c.whisper_stt_tiny("/opt/thalamus/test.wav".to_string()).unwrap();
-            }
-            if model == "base".to_string() {
-                let _stt = node_c.whisper_stt_base("/opt/thalamus/test.wav".to_string()).unwrap();
-            }
-            if model == "medium".to_string() {
-                let _stt = node_c.whisper_stt_medium("/opt/thalamus/test.wav".to_string()).unwrap();
-            }
-            if model == "large".to_string() {
-                let _stt = node_c.whisper_stt_large("/opt/thalamus/test.wav".to_string()).unwrap();
-            } 
-           
-            let end_timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as i64;
-            let time_elapsed = Some(end_timestamp - start_timestamp);
-
-            match sender.send(time_elapsed) {
-                Ok(()) => {}, // everything good
-                Err(_) => {}, // we have been released, don't panic
-            }
-        });
-        return receiver.recv_timeout(std::time::Duration::from_secs(100));
+        let wav = fixtures.wav.clone();
+        return self.timed(timeout, move |node| node.whisper(model).timeout(timeout).transcribe(wav).map(|_| ()));
     }
 
-    pub fn test_whisper_vwav(&self, model: String) -> Result<std::option::Option<i64>, std::sync::mpsc::RecvTimeoutError>{
+    pub fn test_whisper_vwav(&self, model: WhisperModel, fixtures: &crate::bench::Fixtures, timeout: Duration) -> Result<std::option::Option<i64>, std::sync::mpsc::RecvTimeoutError>{
         log::info!("{}: Running Whisper VWAV {} test...", self.pid, model);
-        let (sender, receiver) = mpsc::channel();
-        let node_c = self.clone();
-        let _t = thread::spawn(move || {
-            let start_timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as i64;
-            if model == "tiny".to_string() {
-                let _stt = node_c.whisper_vwav_tiny("/opt/thalamus/test.wav".to_string()).unwrap();
-            }
-            if model == "base".to_string() {
-                let _stt = node_c.whisper_vwav_base("/opt/thalamus/test.wav".to_string()).unwrap();
-            }
-            if model == "medium".to_string() {
-                let _stt = node_c.whisper_vwav_medium("/opt/thalamus/test.wav".to_string()).unwrap();
-            }
-            if model == "large".to_string() {
-                let _stt = node_c.whisper_vwav_large("/opt/thalamus/test.wav".to_string()).unwrap();
-            } 
-           
-            let end_timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as i64;
-            let time_elapsed = Some(end_timestamp - start_timestamp);
-
-            match sender.send(time_elapsed) {
-                Ok(()) => {}, // everything good
-                Err(_) => {}, // we have been released, don't panic
-            }
-        });
-        return receiver.recv_timeout(std::time::Duration::from_secs(100));
+        let wav = fixtures.wav.clone();
+        return self.timed(timeout, move |node| node.whisper(model).timeout(timeout).vwav(wav).map(|_| ()));
     }
 }
 
@@ -756,375 +858,210 @@ impl ThalamusNodeJob {
 }
 
 /// Struct for storing the stats of each node
+/// Results are keyed by service and model ("whisper:base", "llama:7B", "srgan"), so new
+/// services and models are benchmarked and selected without changes here.
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(from = "LegacyStats")]
 pub struct ThalamusNodeStats {
-    pub tts_score: Option<i64>,
-    pub llama_7b: Option<i64>,
-    pub llama_13b: Option<i64>,
-    pub llama_30b: Option<i64>,
-    pub llama_65b: Option<i64>,
-    pub llama_score: Option<i64>,
-    pub nst_score: Option<i64>,
-    pub srgan_score: Option<i64>,
-    pub whisper_stt_tiny: Option<i64>,
-    pub whisper_stt_base: Option<i64>,
-    pub whisper_stt_medium: Option<i64>,
-    pub whisper_stt_large: Option<i64>,
-    pub whisper_stt_score: Option<i64>,
-    pub whisper_vwav_tiny: Option<i64>,
-    pub whisper_vwav_base: Option<i64>,
-    pub whisper_vwav_medium: Option<i64>,
-    pub whisper_vwav_large: Option<i64>,
-    pub whisper_vwav_score: Option<i64>,
+    /// Full results per test, e.g. "whisper:base" or "llama:7B"
+    pub benchmarks: BTreeMap<String, crate::bench::Measurement>,
+    /// One comparable score per service, e.g. "whisper" or "llama": the median of its smallest model
+    pub scores: BTreeMap<String, i64>,
+    /// Fixture set the results were measured with, see Fixtures::id
+    pub fixtures: Option<String>,
+    pub benchmarked_at: Option<i64>,
+    /// Node version when the benchmark ran
+    pub benchmarked_version: Option<String>,
 }
 impl ThalamusNodeStats {
     pub fn new() -> ThalamusNodeStats {
         ThalamusNodeStats { 
-            whisper_stt_tiny: None,
-            whisper_stt_base: None,
-            whisper_stt_medium: None,
-            whisper_stt_large: None,
-            whisper_stt_score: None,
-            llama_7b: None,
-            llama_13b: None,
-            llama_30b: None,
-            llama_65b: None,
-            llama_score: None,
-            whisper_vwav_tiny: None,
-            whisper_vwav_base: None,
-            whisper_vwav_medium: None,
-            whisper_vwav_large: None,
-            whisper_vwav_score: None, 
-            srgan_score: None,
-            tts_score: None,
-            nst_score: None
+            benchmarks: BTreeMap::new(),
+            scores: BTreeMap::new(),
+            fixtures: None,
+            benchmarked_at: None,
+            benchmarked_version: None,
         }
     }
 
-    pub fn calculate(node: ThalamusNode) -> ThalamusNodeStats {
-
-        log::info!("Calculating stats for node {}.....", node.pid);
-        
-        // Test STT Tiny
-        let mut whisper_stt_tiny: Option<i64> = None;
-        let whisper_stt_tiny_test = node.test_whisper_stt("tiny".to_string());
-        match whisper_stt_tiny_test {
-            Ok(time_elapsed) => {
-                whisper_stt_tiny = time_elapsed;
-            },
-            Err(e) => {
-                log::error!("{}: Error running STT Tiny test: {:?}", node.pid, e);
-            }
-        }
-        log::info!("{}: STT Tiny test complete in {:?} miliseconds", node.pid, whisper_stt_tiny);
-
-        // Test STT Base
-        let mut whisper_stt_base: Option<i64> = None;
-        if whisper_stt_tiny.is_some(){
-            let whisper_stt_base_test = node.test_whisper_stt("base".to_string());
-            match whisper_stt_base_test {
-                Ok(time_elapsed) => {
-                    whisper_stt_base = time_elapsed;
-                },
-                Err(e) => {
-                    log::error!("{}: Error running STT Base test: {:?}", node.pid, e);
-                }
-            }
-            log::info!("{}: STT Base test complete in {:?} miliseconds", node.pid, whisper_stt_base);    
-        }
+    /// Median time of a test in milliseconds, keyed like the capability tags
+    pub fn median(&self, key: &str) -> Option<i64> {
+        self.benchmarks.get(key).map(|m| m.median)
+    }
 
-        // Test STT Medium
-        let mut whisper_stt_medium: Option<i64> = None;
-        if whisper_stt_base.is_some() {
-            let whisper_stt_medium_test = node.test_whisper_stt("medium".to_string());
-            match whisper_stt_medium_test {
-                Ok(time_elapsed) => {
-                    whisper_stt_medium = time_elapsed;
-                },
-                Err(e) => {
-                    log::error!("{}: Error running STT Medium test: {:?}", node.pid, e);
-                }
-            }
-            log::info!("{}: STT Medium test complete in {:?} miliseconds", node.pid, whisper_stt_medium);    
-        }
+    pub fn score(&self, service: &str) -> Option<i64> {
+        self.scores.get(service).cloned()
+    }
 
-        // Test STT Large
-        let mut whisper_stt_large: Option<i64> = None;
-        if whisper_stt_medium.is_some() {
-            let whisper_stt_large_test = node.test_whisper_stt("large".to_string());
-            match whisper_stt_large_test {
-                Ok(time_elapsed) => {
-                    whisper_stt_large = time_elapsed;
-                },
-                Err(e) => {
-                    log::error!("{}: Error running STT Large test: {:?}", node.pid, e);
-                }
-            }
-            log::info!("{}: STT Large test complete in {:?} miliseconds", node.pid, whisper_stt_large);
+    /// Records a test result; the first result of a service becomes its score
+    pub fn record(&mut self, service: &str, key: String, measurement: crate::bench::Measurement) {
+        if !self.scores.contains_key(service) {
+            self.scores.insert(service.to_string(), measurement.median);
         }
+        self.benchmarks.insert(key, measurement);
+    }
 
-        // Calculate average STT score
-        let mut stt_score = 0;
-        match whisper_stt_tiny {
-            Some(count) => {
-                stt_score = count;
-            },
-            None => {}
-        }
-        match whisper_stt_base {
-            Some(count) => {
-                stt_score  = stt_score + count / 2;
-            },
-            None => {}
-        }
-        match whisper_stt_medium {
-            Some(count) => {
-                stt_score  = stt_score + count / 2;
-            },
-            None => {}
-        }
-        match whisper_stt_large {
-            Some(count) => {
-                stt_score  = stt_score + count / 2;
-            },
-            None => {}
+    /// Whether the stats are missing, older than max_age or from another version of the node
+    pub fn benchmark_due(&self, version: &str, max_age: Duration) -> bool {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+        match (self.benchmarked_at, &self.benchmarked_version) {
+            (Some(at), Some(benchmarked_version)) => benchmarked_version != version || now - at >= max_age.as_secs() as i64,
+            _ => true,
         }
+    }
 
-        let mut final_stt_score: Option<i64> = None;
-        if stt_score > 0 {
-            final_stt_score = Some(stt_score);
-        }
+    /// Benchmarks a node with the settings in benchmark.json
+    pub fn calculate(node: ThalamusNode, cancelled: &dyn Fn() -> bool) -> ThalamusNodeStats {
+        return ThalamusNodeStats::calculate_with(node, &crate::bench::BenchConfig::load(), cancelled);
+    }
 
+    /// Benchmarks a node; sized models run smallest first and stop at the first failure.
+    /// `cancelled` is checked before every measurement, once it's true no further tests run.
+    pub fn calculate_with(node: ThalamusNode, config: &crate::bench::BenchConfig, cancelled: &dyn Fn() -> bool) -> ThalamusNodeStats {
 
-        // Test VWAV Tiny
-        let mut whisper_vwav_tiny: Option<i64> = None;
-        let whisper_vwav_tiny_test = node.test_whisper_vwav("tiny".to_string());
-        match whisper_vwav_tiny_test {
-            Ok(time_elapsed) => {
-                whisper_vwav_tiny = time_elapsed;
-            },
-            Err(e) => {
-                log::error!("{}: Error running VWAV Tiny test: {:?}", node.pid, e);
-            }
-        }
-        log::info!("{}: VWAV Tiny test complete in {:?} miliseconds", node.pid, whisper_vwav_tiny);
-
-        // Test VWAV Base
-        let mut whisper_vwav_base: Option<i64> = None;
-        if whisper_vwav_tiny.is_some() {
-            let whisper_vwav_base_test = node.test_whisper_vwav("base".to_string());
-            match whisper_vwav_base_test {
-                Ok(time_elapsed) => {
-                    whisper_vwav_base = time_elapsed;
-                },
-                Err(e) => {
-                    log::error!("{}: Error running VWAV Base test: {:?}", node.pid, e);
-                }
+        log::info!("Calculating stats for node {}.....", node.pid);
+        let fixtures = &config.fixtures;
+        let timeouts = &config.timeouts;
+        let pid = node.pid.as_str();
+        let mut stats = ThalamusNodeStats::new();
+        stats.fixtures = Some(fixtures.id());
+
+        // Whisper STT
+        for model in config.whisper_models.iter().cloned() {
+            let key = format!("whisper:{}", model);
+            match crate::bench::measure(pid, key.as_str(), config, cancelled, || node.test_whisper_stt(model, fixtures, timeouts.whisper(model))) {
+                Some(measurement) => stats.record("whisper", key, measurement),
+                None => break,
             }
-            log::info!("{}: VWAV Base test complete in {:?} miliseconds", node.pid, whisper_vwav_base);
         }
 
-        // Test VWAV Medium
-        let mut whisper_vwav_medium: Option<i64> = None;
-        if whisper_vwav_base.is_some() {
-            let whisper_vwav_medium_test = node.test_whisper_vwav("medium".to_string());
-            match whisper_vwav_medium_test {
-                Ok(time_elapsed) => {
-                    whisper_vwav_medium = time_elapsed;
-                },
-                Err(e) => {
-                    log::error!("{}: Error running VWAV Medium test: {:?}", node.pid, e);
-                }
+        // Whisper VWAV
+        for model in config.whisper_models.iter().cloned() {
+            let key = format!("whisper_vwav:{}", model);
+            match crate::bench::measure(pid, key.as_str(), config, cancelled, || node.test_whisper_vwav(model, fixtures, timeouts.vwav(model))) {
+                Some(measurement) => stats.record("whisper_vwav", key, measurement),
+                None => break,
             }
-            log::info!("{}: VWAV Medium test complete in {:?} miliseconds", node.pid, whisper_vwav_medium);
         }
 
-        // Test VWAV Large
-        let mut whisper_vwav_large: Option<i64> = None;
-        if whisper_vwav_medium.is_some() {
-            let whisper_vwav_large_test = node.test_whisper_vwav("large".to_string());
-            match whisper_vwav_large_test {
-                Ok(time_elapsed) => {
-                    whisper_vwav_large = time_elapsed;
-                },
-                Err(e) => {
-                    log::error!("{}: Error running VWAV Large test: {:?}", node.pid, e);
-                }
+        // LLAMA
+        for model in config.llama_models.iter().cloned() {
+            let key = format!("llama:{}", model);
+            match crate::bench::measure(pid, key.as_str(), config, cancelled, || node.test_llama(model, fixtures, timeouts.llama(model))) {
+                Some(measurement) => stats.record("llama", key, measurement),
+                None => break,
             }
-            log::info!("{}: VWAV Large test complete in {:?} miliseconds", node.pid, whisper_vwav_large);
         }
 
-        // Calculate average VWAV score
-        let mut vwav_score = 0;
-        match whisper_vwav_tiny {
-            Some(count) => {
-                vwav_score = count;
-            },
+        // SRGAN
+        match crate::bench::measure(pid, "srgan", config, cancelled, || node.test_srgan(fixtures, timeouts.srgan())) {
+            Some(measurement) => stats.record("srgan", format!("srgan"), measurement),
             None => {}
         }
-        match whisper_vwav_base {
-            Some(count) => {
-                vwav_score  = vwav_score + count / 2;
-            },
-            None => {}
-        }
-        match whisper_vwav_medium {
-            Some(count) => {
-                vwav_score  = vwav_score + count / 2;
-            },
+
+        // YOLOv7
+        match crate::bench::measure(pid, "yolo:v7", config, cancelled, || node.test_yolov7(fixtures, timeouts.yolo())) {
+            Some(measurement) => stats.record("yolo", format!("yolo:v7"), measurement),
             None => {}
         }
-        match whisper_vwav_large {
-            Some(count) => {
-                vwav_score  = vwav_score + count / 2;
-            },
+
+        // TTS
+        match crate::bench::measure(pid, "tts", config, cancelled, || node.test_tts(fixtures, timeouts.tts())) {
+            Some(measurement) => stats.record("tts", format!("tts"), measurement),
             None => {}
         }
 
-        let mut final_vwav_score: Option<i64> = None;
-        if vwav_score > 0 {
-            final_vwav_score = Some(vwav_score);
-        }
+        return stats;
+    }
+}
 
-        // Test LLAMA 7B
-        let mut llama_7b: Option<i64> = None;
-        let llama_7b_test = node.test_llama("7B".to_string());
-        match llama_7b_test {
-            Ok(time_elapsed) => {
-                llama_7b = time_elapsed;
-            },
-            Err(e) => {
-                log::error!("{}: Error running Llama 7B test: {:?}", node.pid, e);
-            }
-        }
-        log::info!("{}: LLAMA 7B test complete in {:?} miliseconds", node.pid, llama_7b);
-
-        // // Test LLAMA 13B
-        // let mut llama_13b: Option<i64> = None;
-        // if llama_7b.is_some(){
-        //     let llama_13b_test = node.test_llama("13B".to_string());
-        //     match llama_13b_test {
-        //         Ok(time_elapsed) => {
-        //             llama_13b = time_elapsed;
-        //         },
-        //         Err(e) => {
-        //             log::error!("{}: Error running Llama 13B test: {:?}", node.pid, e);
-        //         }
-        //     }
-        //     log::info!("{}: LLAMA 13B test complete in {:?} miliseconds", node.pid, llama_13b);    
-        // }
-
-        // // Test LLAMA 30B
-        // let mut llama_30b: Option<i64> = None;
-        // if llama_13b.is_some(){
-        //     let llama_30b_test = node.test_llama("30B".to_string());
-        //     match llama_30b_test {
-        //         Ok(time_elapsed) => {
-        //             llama_30b = time_elapsed;
-        //         },
-        //         Err(e) => {
-        //             log::error!("{}: Error running Llama 30B test: {:?}", node.pid, e);
-        //         }
-        //     }
-        //     log::info!("{}: LLAMA 30B test complete in {:?} miliseconds", node.pid, llama_30b);
-        // }
-
-        // // Test LLAMA 65B
-        // let mut llama_65b: Option<i64> = None;
-        // if llama_30b.is_some(){
-        //     let llama_65b_test = node.test_llama("65B".to_string());
-        //     match llama_65b_test {
-        //         Ok(time_elapsed) => {
-        //             llama_65b = time_elapsed;
-        //         },
-        //         Err(e) => {
-        //             log::error!("{}: Error running Llama 65B test: {:?}", node.pid, e);
-        //         }
-        //     }
-        //     log::info!("{}: LLAMA 65B test complete in {:?} miliseconds", node.pid, llama_65b);
-        // }
-
-        // Calculate average llama score
-        let mut llama_score = 0;
-        match llama_7b {
-            Some(llama_7bx) => {
-                llama_score  = llama_7bx;
-            },
-            None => {}
-        }
-        // match llama_13b {
-        //     Some(llama_13bx) => {
-        //         llama_score  = llama_score + llama_13bx / 2;
-        //     },
-        //     None => {}
-        // }
-        // match llama_30b {
-        //     Some(llama_30bx) => {
-        //         llama_score  = llama_score + llama_30bx / 2;
-        //     },
-        //     None => {}
-        // }
-        // match llama_65b {
-        //     Some(llama_65bx) => {
-        //         llama_score  = llama_score + llama_65bx / 2;
-        //     },
-        //     None => {}
-        // }
-
-        let mut final_llama_score: Option<i64> = None;
-        if llama_score > 0 {
-            final_llama_score = Some(llama_score);
-        }
+// What stats look like on the wire and on disk, including the fixed fields written before
+// stats were keyed by service and model (older clients.json files and older peers)
+#[derive(Deserialize)]
+struct LegacyStats {
+    #[serde(default)]
+    benchmarks: BTreeMap<String, crate::bench::Measurement>,
+    #[serde(default)]
+    scores: BTreeMap<String, i64>,
+    #[serde(default)]
+    fixtures: Option<String>,
+    #[serde(default)]
+    benchmarked_at: Option<i64>,
+    #[serde(default)]
+    benchmarked_version: Option<String>,
+    tts_score: Option<i64>,
+    llama_7b: Option<i64>,
+    llama_13b: Option<i64>,
+    llama_30b: Option<i64>,
+    llama_65b: Option<i64>,
+    llama_score: Option<i64>,
+    nst_score: Option<i64>,
+    srgan_score: Option<i64>,
+    whisper_stt_tiny: Option<i64>,
+    whisper_stt_base: Option<i64>,
+    whisper_stt_medium: Option<i64>,
+    whisper_stt_large: Option<i64>,
+    whisper_stt_score: Option<i64>,
+    whisper_vwav_tiny: Option<i64>,
+    whisper_vwav_base: Option<i64>,
+    whisper_vwav_medium: Option<i64>,
+    whisper_vwav_large: Option<i64>,
+    whisper_vwav_score: Option<i64>,
+}
+impl From<LegacyStats> for ThalamusNodeStats {
+    fn from(legacy: LegacyStats) -> ThalamusNodeStats {
+        let mut stats = ThalamusNodeStats {
+            benchmarks: legacy.benchmarks,
+            scores: legacy.scores,
+            fixtures: legacy.fixtures,
+            benchmarked_at: legacy.benchmarked_at,
+            benchmarked_version: legacy.benchmarked_version,
+        };
 
-        // Test SRGAN
-        let mut srgan: Option<i64> = None;
-        let srgan_test = node.test_srgan();
-        match srgan_test {
-            Ok(time_elapsed) => {
-                srgan = time_elapsed;
-            },
-            Err(e) => {
-                log::error!("{}: Error running SRGAN test: {:?}", node.pid, e);
+        // Old single-run timings become one-sample measurements
+        let fixed = [
+            ("whisper:tiny", legacy.whisper_stt_tiny),
+            ("whisper:base", legacy.whisper_stt_base),
+            ("whisper:medium", legacy.whisper_stt_medium),
+            ("whisper:large", legacy.whisper_stt_large),
+            ("whisper_vwav:tiny", legacy.whisper_vwav_tiny),
+            ("whisper_vwav:base", legacy.whisper_vwav_base),
+            ("whisper_vwav:medium", legacy.whisper_vwav_medium),
+            ("whisper_vwav:large", legacy.whisper_vwav_large),
+            ("llama:7B", legacy.llama_7b),
+            ("llama:13B", legacy.llama_13b),
+            ("llama:30B", legacy.llama_30b),
+            ("llama:65B", legacy.llama_65b),
+            ("srgan", legacy.srgan_score),
+            ("tts", legacy.tts_score),
+            ("nst", legacy.nst_score),
+        ];
+        for (key, value) in fixed {
+            match value {
+                Some(value) if !stats.benchmarks.contains_key(key) => {
+                    match crate::bench::Measurement::from_samples(vec![value], 0) {
+                        Some(measurement) => { stats.benchmarks.insert(key.to_string(), measurement); },
+                        None => {}
+                    }
+                },
+                _ => {}
             }
         }
-        log::info!("{}: SRGAN test complete in {:?} miliseconds", node.pid, srgan);
-
-        // Test TTS
-        let mut tts: Option<i64> = None;
-        let tts_test = node.test_tts();
-        match tts_test {
-            Ok(time_elapsed) => {
-                tts = time_elapsed;
-            },
-            Err(e) => {
-                log::error!("{}: Error running TTS test: {:?}", node.pid, e);
+        let scores = [
+            ("whisper", legacy.whisper_stt_score),
+            ("whisper_vwav", legacy.whisper_vwav_score),
+            ("llama", legacy.llama_score),
+            ("srgan", legacy.srgan_score),
+            ("tts", legacy.tts_score),
+            ("nst", legacy.nst_score),
+        ];
+        for (service, value) in scores {
+            match value {
+                Some(value) if !stats.scores.contains_key(service) => { stats.scores.insert(service.to_string(), value); },
+                _ => {}
             }
         }
-        log::info!("{}: TTS test complete in {:?} miliseconds", node.pid, tts);
-
-        
-
-        // Return stats
-        return ThalamusNodeStats { 
-            whisper_stt_tiny: whisper_stt_tiny,
-            whisper_stt_base: whisper_stt_base,
-            whisper_stt_medium: whisper_stt_medium,
-            whisper_stt_large: whisper_stt_large,
-            whisper_stt_score: final_stt_score,
-            llama_7b: llama_7b,
-            llama_13b: None,
-            llama_30b: None,
-            llama_65b: None,
-            llama_score: final_llama_score,
-            whisper_vwav_tiny: whisper_vwav_tiny,
-            whisper_vwav_base: whisper_vwav_base,
-            whisper_vwav_medium: whisper_vwav_medium,
-            whisper_vwav_large: whisper_vwav_large,
-            whisper_vwav_score: final_vwav_score, 
-            srgan_score: srgan,
-            tts_score: tts,
-            nst_score: None
-        };
+        return stats;
     }
 }
 
@@ -1133,6 +1070,42 @@ impl ThalamusNodeStats {
 pub struct ThalamusNodeCapability {
     pub tag: String,
 }
+impl ThalamusNodeCapability {
+    /// Capabilities this node can serve, based on the models and tools installed
+    pub fn local() -> Vec<ThalamusNodeCapability> {
+        let mut tags: Vec<String> = Vec::new();
+
+        // Installed models this machine doesn't have the memory for aren't offered
+        let hardware = crate::thalamus::hardware::Hardware { memory_total: crate::thalamus::hardware::memory().0, ..Default::default() };
+
+        for model in WhisperModel::ALL {
+            let tag = format!("whisper:{}", model);
+            if model.is_installed() && hardware.fits(tag.as_str()) {
+                tags.push(tag);
+            }
+        }
+        for model in LlamaModel::ALL {
+            let tag = format!("llama:{}", model);
+            if model.is_installed() && hardware.fits(tag.as_str()) {
+                tags.push(tag);
+            }
+        }
+        if std::path::Path::new("/opt/thalamus/bin/srgan").exists() {
+            tags.push(format!("srgan"));
+        }
+        if std::path::Path::new("/opt/thalamus/bin/yolov7").exists() {
+            tags.push(format!("yolo:v7"));
+        }
+        if std::path::Path::new("/opt/thalamus/models/vgg16.ot").exists() {
+            tags.push(format!("nst"));
+        }
+
+        // tts falls back to opensam when opentts isn't running
+        tags.push(format!("tts"));
+
+        return tags.into_iter().map(|tag| ThalamusNodeCapability { tag }).collect();
+    }
+}
 
 /// Auxilary Struct for API Version replies
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -1141,10 +1114,5 @@ pub struct VersionReply {
     pub pid: String,
 }
 
-/// Auxilary Struct for API STT replies
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct STTReply {
-    pub text: String,
-    pub time: f64,
-    pub response_type: Option<String>,
-}
\ No newline at end of file
+/// Auxilary Struct for API STT replies, shared with the whisper service
+pub use crate::thalamus::services::whisper::STTReply;
\ No newline at end of file
@@ -0,0 +1,374 @@
+// ████████ ██   ██  █████  ██       █████  ███    ███ ██    ██ ███████ 
+//    ██    ██   ██ ██   ██ ██      ██   ██ ████  ████ ██    ██ ██      
+//    ██    ███████ ███████ ██      ███████ ██ ████ ██ ██    ██ ███████ 
+//    ██    ██   ██ ██   ██ ██      ██   ██ ██  ██  ██ ██    ██      ██ 
+//    ██    ██   ██ ██   ██ ███████ ██   ██ ██      ██  ██████  ███████                                                                             
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+
+// Structured logging
+// Lines are written as logfmt, json or plain text to stdout and /opt/thalamus/output.log.
+// The log is rotated by size and at midnight (UTC), keeping the last few files as
+// output.log.1, output.log.2, ... Lines logged while serving a request carry its id.
+
+use std::cell::RefCell;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+
+pub const LOG_PATH: &str = "/opt/thalamus/output.log";
+
+thread_local! {
+    static REQUEST_ID: RefCell<Option<String>> = RefCell::new(None);
+}
+
+/// A fresh request id
+pub fn request_id() -> String {
+    thread_rng().sample_iter(&Alphanumeric).take(16).map(char::from).collect()
+}
+
+/// The caller's X-Request-Id when it's a plain token (at most 64 of [A-Za-z0-9._-]), otherwise a fresh id.
+/// Ids end up in log lines and reply headers, so nothing else gets through.
+pub fn accept_request_id(header: Option<&str>) -> String {
+    match header {
+        Some(id) if !id.is_empty() && id.len() <= 64 && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_' || c == '-') => id.to_string(),
+        _ => request_id(),
+    }
+}
+
+/// The id of the request being served on this thread, if any
+pub fn current() -> Option<String> {
+    REQUEST_ID.with(|id| id.borrow().clone())
+}
+
+/// Tags every line logged on this thread with a request id until dropped
+pub struct Scope {
+    previous: Option<String>,
+}
+impl Drop for Scope {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        REQUEST_ID.with(|id| *id.borrow_mut() = previous);
+    }
+}
+
+pub fn scope(request_id: String) -> Scope {
+    let previous = REQUEST_ID.with(|id| id.borrow_mut().replace(request_id));
+    Scope { previous: previous }
+}
+
+/// Logs the stderr of a finished child process (whisper, llama, srgan, ...) line by line
+pub fn child_output(tool: &str, output: &[u8]) {
+    let target = format!("thalamus::child::{}", tool);
+    for line in String::from_utf8_lossy(output).lines() {
+        let line = line.trim();
+        if !line.is_empty() {
+            log::info!(target: target.as_str(), "{}", line);
+        }
+    }
+}
+
+/// Level filter like "info,thalamus::p2p=debug,libp2p=warn"
+#[derive(Debug, Clone)]
+pub struct Filter {
+    default: LevelFilter,
+    /// (module prefix, level), longest prefix first
+    modules: Vec<(String, LevelFilter)>,
+}
+impl Filter {
+    pub fn parse(spec: &str) -> Result<Filter, String> {
+        let mut filter = Filter { default: LevelFilter::Info, modules: Vec::new() };
+        for part in spec.split(',').map(|p| p.trim()).filter(|p| !p.is_empty()) {
+            match part.split_once('=') {
+                Some((module, level)) => {
+                    let level = level.trim().parse::<LevelFilter>().map_err(|_| format!("unknown log level: {}", level))?;
+                    filter.modules.push((module.trim().to_string(), level));
+                },
+                None => {
+                    filter.default = part.parse::<LevelFilter>().map_err(|_| format!("unknown log level: {}", part))?;
+                },
+            }
+        }
+        filter.modules.sort_by(|a, b| b.0.len().cmp(&a.0.len()));
+        return Ok(filter);
+    }
+
+    fn level(&self, target: &str) -> LevelFilter {
+        for (module, level) in self.modules.iter() {
+            if target == module || target.starts_with(format!("{}::", module).as_str()) {
+                return *level;
+            }
+        }
+        return self.default;
+    }
+
+    fn max(&self) -> LevelFilter {
+        self.modules.iter().map(|(_, level)| *level).fold(self.default, std::cmp::max)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Json,
+    Logfmt,
+    Text,
+}
+impl Format {
+    pub fn parse(format: &str) -> Result<Format, String> {
+        match format {
+            "json" => Ok(Format::Json),
+            "logfmt" => Ok(Format::Logfmt),
+            "text" => Ok(Format::Text),
+            _ => Err(format!("unknown log format: {}", format)),
+        }
+    }
+}
+
+struct Output {
+    file: Option<File>,
+    size: u64,
+    day: u64,
+}
+
+struct Logger {
+    filter: Filter,
+    format: Format,
+    path: String,
+    max_size: u64,
+    keep: usize,
+    output: Mutex<Output>,
+}
+impl Logger {
+    fn rotate(&self, output: &mut Output) {
+        output.file = None;
+        for i in (1..self.keep).rev() {
+            let _ = std::fs::rename(format!("{}.{}", self.path, i), format!("{}.{}", self.path, i + 1));
+        }
+        if self.keep > 0 {
+            let _ = std::fs::rename(self.path.as_str(), format!("{}.1", self.path));
+        } else {
+            let _ = std::fs::remove_file(self.path.as_str());
+        }
+        output.file = OpenOptions::new().create(true).append(true).open(self.path.as_str()).ok();
+        output.size = 0;
+    }
+
+    fn line(&self, record: &Record, now: SystemTime) -> String {
+        let timestamp = rfc3339(now);
+        let request_id = current();
+        match self.format {
+            Format::Json => {
+                let mut line = serde_json::json!({
+                    "ts": timestamp,
+                    "level": record.level().to_string().to_lowercase(),
+                    "target": record.target(),
+                    "msg": format!("{}", record.args()),
+                });
+                match request_id {
+                    Some(id) => { line["request_id"] = serde_json::Value::String(id); },
+                    None => {}
+                }
+                return format!("{}", line);
+            },
+            Format::Logfmt => {
+                let mut line = format!("ts={} level={} target={}", timestamp, record.level().to_string().to_lowercase(), record.target());
+                match request_id {
+                    Some(id) => line.push_str(format!(" request_id={}", logfmt_value(id.as_str())).as_str()),
+                    None => {}
+                }
+                line.push_str(format!(" msg={}", logfmt_value(format!("{}", record.args()).as_str())).as_str());
+                return line;
+            },
+            Format::Text => {
+                return match request_id {
+                    Some(id) => format!("{} {:<5} [{}] [{}] {}", timestamp, record.level(), record.target(), id, record.args()),
+                    None => format!("{} {:<5} [{}] {}", timestamp, record.level(), record.target(), record.args()),
+                };
+            },
+        }
+    }
+}
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.filter.level(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let now = SystemTime::now();
+        let line = self.line(record, now);
+
+        if record.level() <= Level::Warn {
+            eprintln!("{}", line);
+        } else {
+            println!("{}", line);
+        }
+
+        let mut output = self.output.lock().unwrap();
+        let day = now.duration_since(UNIX_EPOCH).map(|d| d.as_secs() / 86400).unwrap_or(0);
+        if output.size + line.len() as u64 + 1 > self.max_size || day != output.day {
+            self.rotate(&mut output);
+            output.day = day;
+        }
+        let written = match output.file.as_mut() {
+            Some(file) => writeln!(file, "{}", line).is_ok(),
+            None => false,
+        };
+        if written {
+            output.size += line.len() as u64 + 1;
+        }
+    }
+
+    fn flush(&self) {
+        let mut output = self.output.lock().unwrap();
+        match output.file.as_mut() {
+            Some(file) => { let _ = file.flush(); },
+            None => {}
+        }
+    }
+}
+
+/// Installs the global logger. Appends to the existing log; nothing from the last run is lost.
+pub fn init(args: &crate::Args) -> Result<(), String> {
+    let filter = Filter::parse(args.log_level.as_str())?;
+    let format = Format::parse(args.log_format.as_str())?;
+
+    let file = OpenOptions::new().create(true).append(true).open(LOG_PATH).map_err(|e| format!("{}: {}", LOG_PATH, e))?;
+    let metadata = file.metadata().map_err(|e| format!("{}: {}", LOG_PATH, e))?;
+    let modified = metadata.modified().unwrap_or(SystemTime::now());
+
+    let max = filter.max();
+    let logger = Logger {
+        filter: filter,
+        format: format,
+        path: LOG_PATH.to_string(),
+        max_size: args.log_max_size.max(1) * 1024 * 1024,
+        keep: args.log_keep,
+        output: Mutex::new(Output {
+            file: Some(file),
+            size: metadata.len(),
+            // A log last written yesterday is rotated on the first line
+            day: modified.duration_since(UNIX_EPOCH).map(|d| d.as_secs() / 86400).unwrap_or(0),
+        }),
+    };
+    log::set_boxed_logger(Box::new(logger)).map_err(|e| format!("{}", e))?;
+    log::set_max_level(max);
+    return Ok(());
+}
+
+// Quotes a logfmt value when it has spaces, quotes or an equals sign
+fn logfmt_value(value: &str) -> String {
+    if !value.is_empty() && !value.contains(|c: char| c.is_whitespace() || c == '"' || c == '=') {
+        return value.to_string();
+    }
+    return format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n").replace('\r', "\\r"));
+}
+
+// UTC timestamp with milliseconds, e.g. 2023-06-14T09:12:33.021Z
+fn rfc3339(time: SystemTime) -> String {
+    let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since.as_secs();
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+
+    // Civil date from days since the epoch (Howard Hinnant's algorithm)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z", year, month, day, rem / 3600, (rem % 3600) / 60, rem % 60, since.subsec_millis())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn logger(format: Format) -> Logger {
+        return Logger {
+            filter: Filter::parse("info").unwrap(),
+            format: format,
+            path: format!("/dev/null"),
+            max_size: 0,
+            keep: 0,
+            output: Mutex::new(Output { file: None, size: 0, day: 0 }),
+        };
+    }
+
+    fn line(format: Format, msg: &str) -> String {
+        let now = UNIX_EPOCH + Duration::from_millis(1686733953021);
+        return logger(format).line(&Record::builder().args(format_args!("{}", msg)).level(Level::Warn).target("thalamus::http").build(), now);
+    }
+
+    #[test]
+    fn keeps_plain_request_ids() {
+        for id in ["abc123", "req-42_retry.1", "A", "x".repeat(64).as_str()] {
+            assert_eq!(accept_request_id(Some(id)), id);
+        }
+    }
+
+    #[test]
+    fn replaces_unsafe_request_ids() {
+        let long = "x".repeat(65);
+        for id in ["", "two words", "say \"hi\"", "it's", "line\nbreak", "cr\rlf", "tab\there", "a=b", "ünïcode", long.as_str()] {
+            let accepted = accept_request_id(Some(id));
+            assert_ne!(accepted, id);
+            assert_eq!(accepted.len(), 16);
+            assert!(accepted.chars().all(|c| c.is_ascii_alphanumeric()));
+        }
+        assert_eq!(accept_request_id(None).len(), 16);
+    }
+
+    #[test]
+    fn quotes_logfmt_values_only_when_needed() {
+        assert_eq!(logfmt_value("plain"), "plain");
+        assert_eq!(logfmt_value(""), "\"\"");
+        assert_eq!(logfmt_value("two words"), "\"two words\"");
+        assert_eq!(logfmt_value("a=b"), "\"a=b\"");
+        assert_eq!(logfmt_value("say \"hi\""), "\"say \\\"hi\\\"\"");
+        assert_eq!(logfmt_value("C:\\tmp \n"), "\"C:\\\\tmp \\n\"");
+    }
+
+    #[test]
+    fn escapes_quotes_and_equals_in_logfmt_lines() {
+        let scope = scope(format!("req-1"));
+        let line = line(Format::Logfmt, "key=\"value\" ok=1");
+        std::mem::drop(scope);
+        assert_eq!(line, "ts=2023-06-14T09:12:33.021Z level=warn target=thalamus::http request_id=req-1 msg=\"key=\\\"value\\\" ok=1\"");
+        assert!(!line.contains('\n'));
+    }
+
+    #[test]
+    fn leaves_request_id_out_of_lines_outside_a_request() {
+        let line = line(Format::Logfmt, "idle");
+        assert_eq!(line, "ts=2023-06-14T09:12:33.021Z level=warn target=thalamus::http msg=idle");
+    }
+
+    #[test]
+    fn writes_json_lines() {
+        let line: serde_json::Value = serde_json::from_str(line(Format::Json, "say \"hi\"\nbye").as_str()).unwrap();
+        assert_eq!(line["msg"], "say \"hi\"\nbye");
+        assert_eq!(line["level"], "warn");
+        assert!(line.get("request_id").is_none());
+    }
+
+    #[test]
+    fn formats_utc_timestamps() {
+        assert_eq!(rfc3339(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        assert_eq!(rfc3339(UNIX_EPOCH + Duration::from_secs(951782400)), "2000-02-29T00:00:00.000Z");
+    }
+}
@@ -21,9 +21,9 @@
 // - Automatic updates
 
 // TODO: Jobs
-// - Clear local jobs and inform p2p network to clear them on server boot
-// - Update p2p network with new jobs as they are created and completed
-// - Use job to wrap calculate_stats, nodex, llama, stt, etc.
+// - Clear local jobs and inform p2p network to clear them on server boot (DONE)
+// - Update p2p network with new jobs as they are created and completed (DONE)
+// - Use job to wrap calculate_stats, nodex, llama, stt, etc. (DONE)
 
 // Feature List
 // - TTS speech synthesis using OpenTTS
@@ -50,25 +50,35 @@ use std::sync::Mutex;
 use local_ip_address::list_afinet_netifas;
 const VERSION: Option<&'static str> = option_env!("CARGO_PKG_VERSION");
 use clap::Parser;
-use std::io::Write;
-
-pub fn init_log(path: String) -> Result<(), std::io::Error>{
-    let mut output = std::fs::File::create(path.as_str())?;
-    write!(output, "")?;
-    Ok(())
-}
 
 
 #[tokio::main]
 async fn main() {
 
+    let args = thalamus::Args::parse();
+
+    // Command-line client calls don't need root or the server
+    match args.command.clone() {
+        Some(command) => std::process::exit(thalamus::cli::run(command, args.clone()).await),
+        None => {}
+    }
+
     // Escelate to sudo, setup logging, etc.
     clearscreen::clear().unwrap();
     sudo::with_env(&["LIBTORCH", "LD_LIBRARY_PATH", "PG_DBNAME", "PG_USER", "PG_PASS", "PG_ADDRESS"]).unwrap();
-    // simple_logger::SimpleLogger::new().with_colors(true).with_level(log::LevelFilter::Info).with_timestamps(true).init().unwrap();
 
-    init_log("/opt/thalamus/output.log".to_string()).unwrap();
-    simple_logger::SimpleLogger::new().with_colors(true).with_output_file("/opt/thalamus/output.log".to_string()).init().unwrap();
+    match thalamus::logging::init(&args) {
+        Ok(_) => {},
+        Err(e) => {
+            eprintln!("Unable to start logging: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    match &args.otlp_endpoint {
+        Some(endpoint) => thalamus::trace::init(endpoint.as_str()),
+        None => {}
+    }
 
 
 
@@ -85,7 +95,6 @@ async fn main() {
         None => println!("Version: Unknown"),
     };
 
-    let args = thalamus::Args::parse();
     println!("{:?}", args);
 
  
@@ -113,14 +122,48 @@ async fn main() {
     thalamus::thalamus::services::tts::init(args.clone());
 
     // Setup Thalamus Client
-    let thalamus = Arc::new(Mutex::new(thalamus::ThalamusClient::load(0).unwrap()));
+    match thalamus::storage::init(args.storage.as_str()) {
+        Ok(_) => {},
+        Err(e) => log::error!("Unable to open {} storage, using json: {}", args.storage, e),
+    }
+    // Pick up jobs that were queued or running when the node last stopped
+    thalamus::thalamus::jobs::init();
+    thalamus::thalamus::artifacts::init(&args);
+
+    // Refuse to start on state we can't read rather than replace it
+    let client = match thalamus::ThalamusClient::load(0) {
+        Ok(client) => client,
+        Err(e) => {
+            log::error!("Unable to load node state, not starting: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let thalamus = Arc::new(Mutex::new(client));
+    thalamus::reconcile_jobs(Arc::clone(&thalamus));
 
-    let thalamus_async = Arc::new(futures::lock::Mutex::new(thalamus::ThalamusClient::load(0).unwrap()));
+    let thalamus_async = Arc::new(futures::lock::Mutex::new(thalamus.lock().unwrap().clone()));
     
-    // Initialize the p2p server
-    // let p2p_server = task::spawn(async {
-    //     thalamus::p2p::init_p2p_server().await.unwrap();
-    // });
+    // Re-benchmark nodes on a schedule and after version changes
+    if args.benchmark_interval > 0 {
+        thalamus::benchmark_scheduler(Arc::clone(&thalamus), std::time::Duration::from_secs(args.benchmark_interval * 3600));
+    }
+
+    // Initialize the p2p node
+    let p2p_thc = Arc::clone(&thalamus);
+    let p2p_args = args.clone();
+    let _p2p_server = task::spawn(async move {
+        match std::env::current_exe() {
+            Ok(exe_path) => {
+                if format!("{}", exe_path.display()).as_str() == "/opt/thalamus/bin/thalamus"{
+                    match thalamus::p2p::init_p2p_node(p2p_thc, p2p_args).await {
+                        Ok(_) => {},
+                        Err(e) => log::error!("p2p_node_error: {}", e),
+                    }
+                }
+            },
+            Err(e) => log::error!("failed to get current exe path: {e}"),
+        }
+    });
 
 
     // let thalamus_discovery_thc = Arc::clone(&thalamus);
@@ -200,16 +243,21 @@ async fn main() {
                 let main_sub_thc = Arc::clone(&main_thc);
                 if current_exe_path.as_str() == "/opt/thalamus/bin/thalamus"{
                     let server = Server::new(format!("0.0.0.0:{}", www_port).as_str(), move |request| {
-                        match thalamus::thalamus::http::handle(request, Arc::clone(&main_sub_thc)){
-                            Ok(request) => {
-                                log::info!("HTTP: {:?}", request);
-                                return request;
+                        let started = std::time::Instant::now();
+                        let request_id = thalamus::logging::accept_request_id(request.header("X-Request-Id"));
+                        let _scope = thalamus::logging::scope(request_id.to_string());
+                        let response = match thalamus::thalamus::http::handle(request, Arc::clone(&main_sub_thc)){
+                            Ok(response) => {
+                                log::info!("HTTP: {:?}", response);
+                                response
                             },
                             Err(err) => {
                                 log::error!("HTTP_ERROR: {}", err);
-                                return Response::empty_404();
+                                thalamus::thalamus::http::ErrorReply::internal(format!("{}", err))
                             }
-                        }
+                        };
+                        thalamus::thalamus::metrics::http_request(request.url().as_str(), request.method(), response.status_code, started.elapsed());
+                        return response.with_additional_header("X-Request-Id", request_id);
                     }).unwrap().pool_size(max_threads.into());
                 
                     loop {
@@ -8,6 +8,18 @@
 // Licensed under GPLv3....see LICENSE file.
 
 use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::sync::OnceLock;
+
+pub mod infer;
+pub mod jobs;
+
+use crate::client::ClientError;
 
 use libp2p::futures::StreamExt;
 // use std::io::Result;
@@ -19,308 +31,863 @@ use libp2p::futures::StreamExt;
 use libp2p::{
     core::transport::upgrade::Version,
     multiaddr::Protocol,
-    identify, identity, noise, ping, rendezvous,
-    swarm::{keep_alive, NetworkBehaviour, SwarmBuilder, SwarmEvent},
+    dcutr, gossipsub, identify, identity, kad, noise, ping, relay, rendezvous, request_response,
+    swarm::{behaviour::toggle::Toggle, keep_alive, AddressScore, NetworkBehaviour, SwarmBuilder, SwarmEvent},
     tcp, yamux, PeerId, Transport, Multiaddr,
 };
 use std::time::Duration;
 
-pub async fn init_p2p_server() -> Result<(), Box<dyn Error>> {
-    // env_logger::init();
+// store application version as a const
+const VERSION: Option<&'static str> = option_env!("CARGO_PKG_VERSION");
 
-    let key_pair = identity::Keypair::generate_ed25519();
+const NAMESPACE: &str = "thalamus";
 
-    log::info!("identity generated");
+// Persistent node identity, generated on first boot
+const KEY_PATH: &str = "/opt/thalamus/p2p.key";
 
-    let mut swarm = SwarmBuilder::with_tokio_executor(
-        tcp::tokio::Transport::default()
-            .upgrade(Version::V1Lazy)
-            .authenticate(noise::Config::new(&key_pair).unwrap())
-            .multiplex(yamux::Config::default())
-            .boxed(),
-        ServerBehaviour {
-            identify: identify::Behaviour::new(identify::Config::new(
-                "rendezvous-example/1.0.0".to_string(),
-                key_pair.public(),
-            )),
-            rendezvous: rendezvous::server::Behaviour::new(rendezvous::server::Config::default()),
-            ping: ping::Behaviour::new(ping::Config::new().with_interval(Duration::from_secs(1))),
-            keep_alive: keep_alive::Behaviour,
+// Relay v2 hop protocol, advertised over identify by nodes running with --relay
+const RELAY_HOP_PROTOCOL: &str = "/libp2p/circuit/relay/0.2.0/hop";
+
+// Relays we reserve a circuit slot on when we can't be dialed directly
+const MAX_RELAYS: usize = 2;
+
+// Commands from the rest of the process into the running swarm
+static P2P_COMMANDS: OnceLock<tokio::sync::mpsc::UnboundedSender<P2pCommand>> = OnceLock::new();
+
+// The swarm's runtime, which drives the timers for blocking callers' deadlines
+static P2P_RUNTIME: OnceLock<tokio::runtime::Handle> = OnceLock::new();
+
+pub enum P2pCommand {
+    Infer {
+        peer: PeerId,
+        envelope: infer::InferEnvelope,
+        reply: tokio::sync::oneshot::Sender<Result<infer::InferResponse, ClientError>>,
+    },
+    FindProviders {
+        capability: String,
+        reply: tokio::sync::oneshot::Sender<Vec<PeerId>>,
+    },
+    PublishJob {
+        job: crate::ThalamusNodeJob,
+        finished: bool,
+    },
+}
+
+// Queues an inference job on the swarm, handing back the receiver for its reply
+fn send_infer(peer_id: &str, request: infer::InferRequest, span: &crate::trace::Span) -> Result<tokio::sync::oneshot::Receiver<Result<infer::InferResponse, ClientError>>, ClientError> {
+    let peer = match peer_id.parse::<PeerId>() {
+        Ok(peer) => peer,
+        Err(e) => return Err(ClientError::Transport(format!("invalid peer id {}: {}", peer_id, e))),
+    };
+    let commands = P2P_COMMANDS.get().ok_or(ClientError::Transport(format!("p2p node is not running")))?;
+
+    let (reply, response) = tokio::sync::oneshot::channel();
+    let envelope = infer::InferEnvelope { traceparent: Some(span.traceparent()), request: request };
+    match commands.send(P2pCommand::Infer { peer, envelope, reply }) {
+        Ok(_) => Ok(response),
+        Err(_) => Err(ClientError::Transport(format!("p2p node has stopped"))),
+    }
+}
+
+// Blocking on a oneshot from inside a tokio runtime panics, so the sync calls refuse instead
+fn blocking_allowed(call: &str) -> Result<(), ClientError> {
+    match tokio::runtime::Handle::try_current() {
+        Ok(_) => Err(ClientError::Io(std::io::Error::new(std::io::ErrorKind::Unsupported, format!("p2p::{} blocks and can't be called from inside a tokio runtime, use p2p::async_{}", call, call)))),
+        Err(_) => Ok(()),
+    }
+}
+
+/// Sends an inference job to a peer over /thalamus/infer and waits up to timeout for the reply (blocking).
+/// Fails when called from inside a tokio runtime; use async_infer there.
+pub fn infer(peer_id: &str, request: infer::InferRequest, timeout: Duration) -> Result<infer::InferResponse, ClientError> {
+    blocking_allowed("infer")?;
+    let mut span = crate::trace::client(format!("p2p infer {}", request.kind()), peer_id);
+    let receiver = send_infer(peer_id, request, &span)?;
+    let runtime = P2P_RUNTIME.get().ok_or(ClientError::Transport(format!("p2p node is not running")))?;
+    let response = match runtime.block_on(tokio::time::timeout(timeout, receiver)) {
+        Ok(Ok(response)) => response,
+        Ok(Err(_)) => Err(ClientError::Transport(format!("p2p node dropped the request"))),
+        Err(_) => Err(ClientError::Timeout),
+    };
+    match &response {
+        Ok(_) => {},
+        Err(e) => span.error(e),
+    }
+    return response;
+}
+
+/// Sends an inference job to a peer over /thalamus/infer and awaits the reply.
+/// Dropping the future abandons the job; the reply is discarded when it arrives.
+pub async fn async_infer(peer_id: &str, request: infer::InferRequest) -> Result<infer::InferResponse, ClientError> {
+    let mut span = crate::trace::client(format!("p2p infer {}", request.kind()), peer_id);
+    let response = match send_infer(peer_id, request, &span)?.await {
+        Ok(response) => response,
+        Err(_) => Err(ClientError::Transport(format!("p2p node dropped the request"))),
+    };
+    match &response {
+        Ok(_) => {},
+        Err(e) => span.error(e),
+    }
+    return response;
+}
+
+/// Announces a local job's progress to peers; does nothing when the p2p node isn't running
+pub fn publish_job(job: crate::ThalamusNodeJob, finished: bool) {
+    match P2P_COMMANDS.get() {
+        Some(commands) => {
+            let _ = commands.send(P2pCommand::PublishJob { job, finished });
         },
-        PeerId::from(key_pair.public()),
-    )
-    .build();
+        None => {}
+    }
+}
 
-    log::warn!("SERVER_ID: {}", swarm.local_peer_id());
+// Queues a provider lookup on the swarm, handing back the receiver for its result
+fn send_find_providers(capability: &str) -> Result<tokio::sync::oneshot::Receiver<Vec<PeerId>>, ClientError> {
+    let commands = P2P_COMMANDS.get().ok_or(ClientError::Transport(format!("p2p node is not running")))?;
 
-    let _ = swarm.listen_on("/ip4/0.0.0.0/tcp/62649".parse().unwrap());
+    let (reply, response) = tokio::sync::oneshot::channel();
+    match commands.send(P2pCommand::FindProviders { capability: capability.to_string(), reply }) {
+        Ok(_) => Ok(response),
+        Err(_) => Err(ClientError::Transport(format!("p2p node has stopped"))),
+    }
+}
 
-    while let Some(event) = swarm.next().await {
-        match event {
-            SwarmEvent::ConnectionEstablished { peer_id, .. } => {
-                log::warn!("Connected to {}", peer_id);
-            }
-            SwarmEvent::ConnectionClosed { peer_id, .. } => {
-                log::warn!("Disconnected from {}", peer_id);
-            }
-            SwarmEvent::Behaviour(ServerBehaviourEvent::Rendezvous(
-                rendezvous::server::Event::PeerRegistered { peer, registration },
-            )) => {
-                log::warn!(
-                    "Peer {} registered for namespace '{}'",
-                    peer,
-                    registration.namespace
-                );
-            }
-            SwarmEvent::Behaviour(ServerBehaviourEvent::Ping(ping::Event {
-                peer,
-                result: Ok(rtt),
-                ..
-            })) => {
-                match rtt {
-                    libp2p::ping::Success::Ping{rtt: stt} => {
-                        log::warn!("Server Ping to {} in {:?}", peer, stt);
-                    },
-                    libp2p::ping::Success::Pong{} => {
-                        log::warn!("Server Pong from {}", peer);
-                    }
-                }
-                
-            }
-            SwarmEvent::Behaviour(ServerBehaviourEvent::Rendezvous(
-                rendezvous::server::Event::DiscoverServed {
-                    enquirer,
-                    registrations,
-                },
-            )) => {
-                log::warn!(
-                    "Served peer {} with {} registrations",
-                    enquirer,
-                    registrations.len()
-                );
-            }
-            other => {
-                log::debug!("Unhandled {:?}", other);
-            }
-        }
+/// Looks up the peers providing a capability (e.g. whisper:medium) in the DHT (blocking).
+/// Fails when called from inside a tokio runtime; use async_find_providers there.
+pub fn find_providers(capability: &str) -> Result<Vec<String>, ClientError> {
+    blocking_allowed("find_providers")?;
+    match send_find_providers(capability)?.blocking_recv() {
+        Ok(providers) => Ok(providers.iter().map(|peer| peer.to_string()).collect()),
+        Err(_) => Err(ClientError::Transport(format!("p2p node dropped the lookup"))),
     }
+}
 
-    Ok(())
+/// Looks up the peers providing a capability (e.g. whisper:medium) in the DHT
+pub async fn async_find_providers(capability: &str) -> Result<Vec<String>, ClientError> {
+    match send_find_providers(capability)?.await {
+        Ok(providers) => Ok(providers.iter().map(|peer| peer.to_string()).collect()),
+        Err(_) => Err(ClientError::Transport(format!("p2p node dropped the lookup"))),
+    }
 }
 
-const NAMESPACE: &str = "rendezvous";
+/// Splits the trailing /p2p/<peer id> off a bootstrap address
+fn split_peer_id(address: &Multiaddr) -> Option<PeerId> {
+    match address.iter().last() {
+        Some(Protocol::P2p(hash)) => PeerId::from_multihash(hash).ok(),
+        _ => None,
+    }
+}
 
-pub async fn init_p2p_client(server_ip_address: String) -> Result<(), Box<dyn Error>> {
-    // env_logger::init();
+/// Loads the node keypair from the data dir, generating and storing a new one if missing
+pub fn load_keypair() -> Result<identity::Keypair, Box<dyn Error>> {
+    if Path::new(KEY_PATH).exists() {
+        // Older nodes wrote the key world readable; it is our identity, so keep it private
+        let mode = std::fs::metadata(KEY_PATH)?.permissions().mode();
+        if mode & 0o077 != 0 {
+            log::warn!("Restricting permissions on {} (was {:o})", KEY_PATH, mode & 0o777);
+            std::fs::set_permissions(KEY_PATH, std::fs::Permissions::from_mode(0o600))?;
+        }
+        let bytes = std::fs::read(KEY_PATH)?;
+        return Ok(identity::Keypair::from_protobuf_encoding(&bytes)?);
+    }
 
     let key_pair = identity::Keypair::generate_ed25519();
-    let rendezvous_point_address = format!("/ip4/{}/tcp/62649", server_ip_address).as_str().parse::<Multiaddr>().unwrap();
-    // let rendezvous_point = "12D3KooWDpJ7As7BWAwRMfu1VU2WCqNjvq387JEYKDBj4kx6nXTN".parse().unwrap();
+    let mut file = std::fs::OpenOptions::new().write(true).create_new(true).mode(0o600).open(KEY_PATH)?;
+    file.write_all(&key_pair.to_protobuf_encoding()?)?;
+    file.sync_all()?;
+    log::warn!("Generated new p2p identity {} in {}", PeerId::from(key_pair.public()), KEY_PATH);
+    Ok(key_pair)
+}
+
+/// Runs the p2p node: a rendezvous point for other nodes, and a rendezvous client
+/// registering with (and discovering peers from) every configured rendezvous point.
+pub async fn init_p2p_node(thalamus: Arc<Mutex<crate::ThalamusClient>>, args: crate::Args) -> Result<(), Box<dyn Error>> {
+
+    let key_pair = load_keypair()?;
+
+    // Peers read our pid and http port from the agent version: thalamus/{version}/{pid}/{www_port}
+    let pid = std::fs::read_to_string("/opt/thalamus/pid")?.trim().to_string();
+    let agent_version = format!("thalamus/{}/{}/{}", VERSION.unwrap_or("unknown"), pid, args.www_port);
+
+    let local_peer_id = PeerId::from(key_pair.public());
+    let mut kad_config = kad::KademliaConfig::default();
+    kad_config.set_protocol_names(vec![std::borrow::Cow::Borrowed(b"/thalamus/kad/1.0.0")]);
+
+    // Relayed connections ride over the relay client transport; port reuse lets dcutr hole punch
+    let (relay_transport, relay_client) = relay::client::new(local_peer_id);
+
+    // Relays are opt-in, and need limits large enough for inference jobs to pass through
+    let relay_server = match args.relay {
+        true => {
+            let mut relay_config = relay::Config::default();
+            relay_config.max_circuit_duration = Duration::from_secs(600);
+            relay_config.max_circuit_bytes = (infer::MAX_REQUEST_SIZE + infer::MAX_RESPONSE_SIZE) as u64;
+            Some(relay::Behaviour::new(local_peer_id, relay_config))
+        },
+        false => None,
+    };
 
     let mut swarm = SwarmBuilder::with_tokio_executor(
-        tcp::tokio::Transport::default()
+        relay_transport
+            .or_transport(tcp::tokio::Transport::new(tcp::Config::default().port_reuse(true)))
             .upgrade(Version::V1Lazy)
-            .authenticate(noise::Config::new(&key_pair).unwrap())
+            .authenticate(noise::Config::new(&key_pair)?)
             .multiplex(yamux::Config::default())
             .boxed(),
-        MyBehaviour {
+        ThalamusBehaviour {
             identify: identify::Behaviour::new(identify::Config::new(
-                "rendezvous-example/1.0.0".to_string(),
+                "thalamus/1.0.0".to_string(),
                 key_pair.public(),
-            )),
+            ).with_agent_version(agent_version)),
+            rendezvous_server: rendezvous::server::Behaviour::new(rendezvous::server::Config::default()),
             rendezvous: rendezvous::client::Behaviour::new(key_pair.clone()),
-            ping: ping::Behaviour::new(ping::Config::new().with_interval(Duration::from_secs(1))),
+            ping: ping::Behaviour::new(ping::Config::new().with_interval(Duration::from_secs(10))),
+            infer: request_response::Behaviour::new(
+                infer::InferCodec,
+                vec![
+                    (infer::InferProtocol::V2, request_response::ProtocolSupport::Full),
+                    (infer::InferProtocol::V1, request_response::ProtocolSupport::Full),
+                ],
+                request_response::Config::default().set_request_timeout(Duration::from_secs(600)).clone(),
+            ),
+            kademlia: kad::Kademlia::with_config(local_peer_id, kad::store::MemoryStore::new(local_peer_id), kad_config),
+            relay_client,
+            relay: Toggle::from(relay_server),
+            dcutr: dcutr::Behaviour::new(local_peer_id),
+            gossipsub: gossipsub::Behaviour::new(
+                gossipsub::MessageAuthenticity::Signed(key_pair.clone()),
+                gossipsub::Config::default(),
+            )?,
             keep_alive: keep_alive::Behaviour,
         },
-        PeerId::from(key_pair.public()),
+        local_peer_id,
     )
     .build();
 
-    let external_address = format!("/ip4/{}/tcp/62649", server_ip_address).as_str().parse::<Multiaddr>().unwrap();
-    swarm.add_external_address(external_address, libp2p::swarm::AddressScore::Infinite);
-
     log::warn!("Local peer id: {}", swarm.local_peer_id());
 
-    swarm.dial(rendezvous_point_address.clone()).unwrap();
+    let jobs_topic = gossipsub::IdentTopic::new(jobs::TOPIC);
+    swarm.behaviour_mut().gossipsub.subscribe(&jobs_topic)?;
+    let mut jobs_tick = tokio::time::interval(Duration::from_secs(60));
+
+    swarm.listen_on(format!("/ip4/0.0.0.0/tcp/{}", args.p2p_port).parse()?)?;
+
+    // Registrations need a dialable address, so advertise our LAN address
+    match local_ip_address::local_ip() {
+        Ok(ip) => {
+            let external_address = format!("/ip4/{}/tcp/{}", ip, args.p2p_port).parse::<Multiaddr>()?;
+            swarm.add_external_address(external_address, AddressScore::Infinite);
+        },
+        Err(e) => log::error!("p2p_local_ip_error: {}", e),
+    }
+
+    let mut rendezvous_points: Vec<Multiaddr> = Vec::new();
+    for point in args.rendezvous.clone() {
+        match point.parse::<Multiaddr>() {
+            Ok(address) => {
+                match swarm.dial(address.clone()) {
+                    Ok(_) => rendezvous_points.push(address),
+                    Err(e) => log::error!("Failed to dial rendezvous point {}: {}", address, e),
+                }
+            },
+            Err(e) => log::error!("Invalid rendezvous point {}: {}", point, e),
+        }
+    }
+
+    // Static bootstrap peers seed the DHT so lookups reach past our own subnet
+    for bootstrap in args.bootstrap.clone() {
+        match bootstrap.parse::<Multiaddr>() {
+            Ok(address) => {
+                match split_peer_id(&address) {
+                    Some(peer_id) => {
+                        swarm.behaviour_mut().kademlia.add_address(&peer_id, address.clone());
+                        match swarm.dial(address.clone()) {
+                            Ok(_) => {},
+                            Err(e) => log::error!("Failed to dial bootstrap peer {}: {}", address, e),
+                        }
+                    },
+                    None => log::error!("Bootstrap peer {} is missing its /p2p/<peer id>", address),
+                }
+            },
+            Err(e) => log::error!("Invalid bootstrap peer {}: {}", bootstrap, e),
+        }
+    }
+    match swarm.behaviour_mut().kademlia.bootstrap() {
+        Ok(_) => {},
+        Err(_) => log::warn!("No bootstrap peers configured, the DHT will fill from discovered peers"),
+    }
+
+    let mut provided: Vec<String> = Vec::new();
+    let mut provide_tick = tokio::time::interval(Duration::from_secs(600));
+    let mut pending_providers: HashMap<kad::QueryId, (String, HashSet<PeerId>, tokio::sync::oneshot::Sender<Vec<PeerId>>)> = HashMap::new();
+
+    let mut relays: HashSet<PeerId> = HashSet::new();
+    let mut relayed_peers: HashSet<PeerId> = HashSet::new();
 
+    let mut rendezvous_peers: HashMap<PeerId, Multiaddr> = HashMap::new();
+    let mut cookies: HashMap<PeerId, rendezvous::Cookie> = HashMap::new();
     let mut discover_tick = tokio::time::interval(Duration::from_secs(30));
-    let mut cookie = None;
+
+    let (command_sender, mut commands) = tokio::sync::mpsc::unbounded_channel::<P2pCommand>();
+    match P2P_COMMANDS.set(command_sender) {
+        Ok(_) => {},
+        Err(_) => return Err("p2p node is already running".into()),
+    }
+    let _ = P2P_RUNTIME.set(tokio::runtime::Handle::current());
+
+    // Inbound jobs run on the blocking pool and hand their responses back here
+    let (response_sender, mut responses) = tokio::sync::mpsc::unbounded_channel::<(request_response::ResponseChannel<infer::InferResponse>, infer::InferResponse)>();
+    let mut pending_requests: HashMap<request_response::RequestId, tokio::sync::oneshot::Sender<Result<infer::InferResponse, ClientError>>> = HashMap::new();
 
     loop {
         tokio::select! {
-                event = swarm.select_next_some() => match event {
-                    SwarmEvent::ConnectionEstablished { peer_id, .. } => {
+            event = swarm.select_next_some() => match event {
+                SwarmEvent::NewListenAddr { address, .. } => {
+                    log::warn!("p2p listening on {}", address);
+
+                    // Our circuit address is how NATed peers get dialed, so publish it
+                    if address.iter().any(|p| p == Protocol::P2pCircuit) {
+                        swarm.add_external_address(address, AddressScore::Infinite);
+                    }
+                }
+                SwarmEvent::ConnectionEstablished { peer_id, endpoint, num_established, .. } => {
+                    log::info!("Connected to {}", peer_id);
+
+                    if endpoint.get_remote_address().iter().any(|p| p == Protocol::P2pCircuit) {
+                        if num_established.get() == 1 {
+                            relayed_peers.insert(peer_id);
+                            set_relayed(Arc::clone(&thalamus), peer_id, true);
+                        }
+                    } else if relayed_peers.remove(&peer_id) {
+                        set_relayed(Arc::clone(&thalamus), peer_id, false);
+                    }
+
+                    if endpoint.is_dialer() && rendezvous_points.contains(endpoint.get_remote_address()) && !rendezvous_peers.contains_key(&peer_id) {
                         log::warn!(
-                            "Connected to rendezvous point, discovering nodes in '{}' namespace ...",
+                            "Connected to rendezvous point {}, registering in '{}' namespace ...",
+                            peer_id,
                             NAMESPACE
                         );
+                        rendezvous_peers.insert(peer_id, endpoint.get_remote_address().clone());
+
+                        swarm.behaviour_mut().rendezvous.register(
+                            rendezvous::Namespace::from_static(NAMESPACE),
+                            peer_id,
+                            None,
+                        );
 
                         swarm.behaviour_mut().rendezvous.discover(
-                            Some(rendezvous::Namespace::new(NAMESPACE.to_string()).unwrap()),
+                            Some(rendezvous::Namespace::from_static(NAMESPACE)),
                             None,
                             None,
                             peer_id,
                         );
+                    }
+                }
+                SwarmEvent::ConnectionClosed { peer_id, num_established, .. } => {
+                    log::info!("Disconnected from {}", peer_id);
+                    if num_established == 0 {
+                        rendezvous_peers.remove(&peer_id);
+                        relayed_peers.remove(&peer_id);
+                        if relays.remove(&peer_id) {
+                            log::warn!("Lost relay {}", peer_id);
+                        }
+                        unlink_peer(Arc::clone(&thalamus), peer_id);
+                    }
+                }
+                SwarmEvent::Behaviour(ThalamusBehaviourEvent::Rendezvous(rendezvous::client::Event::Discovered {
+                    registrations,
+                    cookie,
+                    rendezvous_node,
+                })) => {
+                    cookies.insert(rendezvous_node, cookie);
+
+                    for registration in registrations {
+                        let peer = registration.record.peer_id();
+                        if peer == *swarm.local_peer_id() || swarm.is_connected(&peer) {
+                            continue;
+                        }
 
-                        swarm.behaviour_mut().rendezvous.register(
-                            rendezvous::Namespace::from_static("rendezvous"),
-                            peer_id,
-                            None,
-                        );
+                        for address in registration.record.addresses() {
+                            log::warn!("Discovered peer {} at {}", peer, address);
 
-                        log::warn!("Connection established with rendezvous point {}", peer_id);
-                    }
-                    SwarmEvent::Behaviour(MyBehaviourEvent::Rendezvous(rendezvous::client::Event::Discovered {
-                        registrations,
-                        cookie: new_cookie,
-                        ..
-                    })) => {
-                        cookie.replace(new_cookie);
-
-                        for registration in registrations {
-                            for address in registration.record.addresses() {
-                                let peer = registration.record.peer_id();
-                                log::warn!("Discovered peer {} at {}", peer, address);
-
-                                let p2p_suffix = Protocol::P2p(*peer.as_ref());
-                                let address_with_p2p =
-                                    if !address.ends_with(&Multiaddr::empty().with(p2p_suffix.clone())) {
-                                        address.clone().with(p2p_suffix)
-                                    } else {
-                                        address.clone()
-                                    };
-
-                                swarm.dial(address_with_p2p).unwrap();
+                            let p2p_suffix = Protocol::P2p(*peer.as_ref());
+                            let address_with_p2p =
+                                if !address.ends_with(&Multiaddr::empty().with(p2p_suffix.clone())) {
+                                    address.clone().with(p2p_suffix)
+                                } else {
+                                    address.clone()
+                                };
+
+                            match swarm.dial(address_with_p2p) {
+                                Ok(_) => {},
+                                Err(e) => log::error!("Failed to dial peer {}: {}", peer, e),
                             }
                         }
                     }
-                    SwarmEvent::Behaviour(MyBehaviourEvent::Identify(identify::Event::Received {
-                        peer_id, info, ..
-                    })) => {
-                        log::warn!("{peer_id:?}: {info:?}");
-                 
+                }
+                SwarmEvent::Behaviour(ThalamusBehaviourEvent::Rendezvous(
+                    rendezvous::client::Event::Registered {
+                        namespace,
+                        ttl,
+                        rendezvous_node,
+                    },
+                )) => {
+                    log::info!(
+                        "Registered for namespace '{}' at rendezvous point {} for the next {} seconds",
+                        namespace,
+                        rendezvous_node,
+                        ttl
+                    );
+                }
+                SwarmEvent::Behaviour(ThalamusBehaviourEvent::Rendezvous(
+                    rendezvous::client::Event::RegisterFailed(error),
+                )) => {
+                    log::error!("Failed to register {}", error);
+                }
+                SwarmEvent::Behaviour(ThalamusBehaviourEvent::Rendezvous(
+                    rendezvous::client::Event::DiscoverFailed { rendezvous_node, error, .. },
+                )) => {
+                    log::error!("Failed to discover peers at {}: {:?}", rendezvous_node, error);
+                }
+                SwarmEvent::Behaviour(ThalamusBehaviourEvent::RendezvousServer(
+                    rendezvous::server::Event::PeerRegistered { peer, registration },
+                )) => {
+                    log::info!(
+                        "Peer {} registered for namespace '{}'",
+                        peer,
+                        registration.namespace
+                    );
+                }
+                SwarmEvent::Behaviour(ThalamusBehaviourEvent::Identify(identify::Event::Received {
+                    peer_id, info, ..
+                })) => {
+                    for address in info.listen_addrs.iter() {
+                        swarm.behaviour_mut().infer.add_address(&peer_id, address.clone());
+                        if info.protocols.iter().any(|p| p == "/thalamus/kad/1.0.0") {
+                            swarm.behaviour_mut().kademlia.add_address(&peer_id, address.clone());
+                        }
                     }
-                    SwarmEvent::Behaviour(MyBehaviourEvent::Identify(identify::Event::Sent {
-                        peer_id, ..
-                    })) => {
-                        log::warn!("Sent identify info to {peer_id:?}");
-                        swarm.behaviour_mut().rendezvous.register(
-                            rendezvous::Namespace::from_static("rendezvous"),
-                            peer_id,
-                            None,
-                        );
+
+                    // Reserve a slot on relays so peers can reach us from behind NAT
+                    if !args.relay && relays.len() < MAX_RELAYS && !relays.contains(&peer_id) && info.protocols.iter().any(|p| p == RELAY_HOP_PROTOCOL) {
+                        let relay_address = info.listen_addrs.iter().find(|a| {
+                            !a.iter().any(|p| p == Protocol::P2pCircuit || matches!(p, Protocol::Ip4(ip) if ip.is_loopback()))
+                        });
+                        match relay_address {
+                            Some(address) => {
+                                let circuit_address = address.clone().with(Protocol::P2p(*peer_id.as_ref())).with(Protocol::P2pCircuit);
+                                match swarm.listen_on(circuit_address.clone()) {
+                                    Ok(_) => {
+                                        relays.insert(peer_id);
+                                        log::warn!("Listening through relay {}", circuit_address);
+                                    },
+                                    Err(e) => log::error!("Failed to listen through relay {}: {}", peer_id, e),
+                                }
+                            },
+                            None => log::warn!("Relay {} has no usable address", peer_id),
+                        }
                     }
-    
-                    SwarmEvent::Behaviour(MyBehaviourEvent::Rendezvous(
-                        rendezvous::client::Event::Registered {
-                            namespace,
-                            ttl,
-                            rendezvous_node,
+
+                    link_peer(Arc::clone(&thalamus), peer_id, info, relayed_peers.contains(&peer_id));
+                }
+                SwarmEvent::Behaviour(ThalamusBehaviourEvent::Infer(request_response::Event::Message {
+                    peer,
+                    message: request_response::Message::Request { request, channel, .. },
+                })) => {
+                    log::info!("Inference job from {}", peer);
+                    let response_sender = response_sender.clone();
+                    tokio::task::spawn_blocking(move || {
+                        let response = infer::handle(request);
+                        match response_sender.send((channel, response)) {
+                            Ok(()) => {}, // everything good
+                            Err(_) => {}, // swarm has stopped, don't panic
+                        }
+                    });
+                }
+                SwarmEvent::Behaviour(ThalamusBehaviourEvent::Infer(request_response::Event::Message {
+                    message: request_response::Message::Response { request_id, response },
+                    ..
+                })) => {
+                    match pending_requests.remove(&request_id) {
+                        Some(reply) => {
+                            let _ = reply.send(Ok(response));
                         },
-                    )) => {
-                        log::warn!(
-                            "Registered for namespace '{}' at rendezvous point {} for the next {} seconds",
-                            namespace,
-                            rendezvous_node,
-                            ttl
-                        );
+                        None => log::warn!("Unexpected inference response {:?}", request_id),
+                    }
+                }
+                SwarmEvent::Behaviour(ThalamusBehaviourEvent::Infer(request_response::Event::OutboundFailure {
+                    peer, request_id, error,
+                })) => {
+                    log::error!("Inference request to {} failed: {}", peer, error);
+                    match pending_requests.remove(&request_id) {
+                        Some(reply) => {
+                            let error = match error {
+                                request_response::OutboundFailure::Timeout => ClientError::Timeout,
+                                other => ClientError::Transport(format!("p2p request to {} failed: {}", peer, other)),
+                            };
+                            let _ = reply.send(Err(error));
+                        },
+                        None => {}
+                    }
+                }
+                SwarmEvent::Behaviour(ThalamusBehaviourEvent::Gossipsub(gossipsub::Event::Subscribed {
+                    peer_id, topic,
+                })) => {
+                    // New listeners start from our current jobs rather than waiting for the next snapshot
+                    if topic == jobs_topic.hash() {
+                        log::debug!("{} subscribed to job events", peer_id);
+                        let event = jobs::JobEvent::Snapshot { pid: pid.to_string(), jobs: crate::thalamus::jobs::node_jobs() };
+                        publish(&mut swarm, &jobs_topic, &event);
                     }
-                    SwarmEvent::Behaviour(MyBehaviourEvent::Rendezvous(
-                        rendezvous::client::Event::RegisterFailed(error),
-                    )) => {
-                        log::error!("Failed to register {}", error);
+                }
+                SwarmEvent::Behaviour(ThalamusBehaviourEvent::Gossipsub(gossipsub::Event::Message {
+                    propagation_source, message, ..
+                })) => {
+                    match (message.source, serde_json::from_slice::<jobs::JobEvent>(&message.data)) {
+                        (Some(source), Ok(event)) => jobs::apply(Arc::clone(&thalamus), &source, event),
+                        (None, Ok(event)) => log::warn!("Dropping unsigned job event for {} via {}", event.pid(), propagation_source),
+                        (_, Err(e)) => log::warn!("Bad job event via {}: {}", propagation_source, e),
+                    }
+                }
+                SwarmEvent::Behaviour(ThalamusBehaviourEvent::Kademlia(kad::KademliaEvent::OutboundQueryProgressed {
+                    id, result: kad::QueryResult::GetProviders(result), step, ..
+                })) => {
+                    match result {
+                        Ok(kad::GetProvidersOk::FoundProviders { providers, .. }) => {
+                            match pending_providers.get_mut(&id) {
+                                Some((_, found, _)) => found.extend(providers),
+                                None => {}
+                            }
+                        },
+                        Ok(kad::GetProvidersOk::FinishedWithNoAdditionalRecord { .. }) => {},
+                        Err(e) => log::warn!("Provider lookup failed: {}", e),
                     }
 
-                    SwarmEvent::Behaviour(MyBehaviourEvent::Ping(ping::Event {
-                        peer,
-                        result: Ok(rtt),
-                        ..
-                    })) => {
-                        match rtt {
-                            libp2p::ping::Success::Ping{rtt: stt} => {
-                                log::warn!("Ping to {} in {:?}", peer, stt);
+                    if step.last {
+                        match pending_providers.remove(&id) {
+                            Some((capability, found, reply)) => {
+                                let providers: Vec<PeerId> = found.into_iter().collect();
+                                record_capability(Arc::clone(&thalamus), capability.as_str(), &providers);
+
+                                // Dial providers we have not met so identify can link them
+                                for provider in providers.iter() {
+                                    if *provider != *swarm.local_peer_id() && !swarm.is_connected(provider) {
+                                        match swarm.dial(*provider) {
+                                            Ok(_) => {},
+                                            Err(e) => log::warn!("Failed to dial provider {}: {}", provider, e),
+                                        }
+                                    }
+                                }
+
+                                let _ = reply.send(providers);
                             },
-                            libp2p::ping::Success::Pong{} => {
-
-                            }
+                            None => {}
                         }
-                        
                     }
-                    other => {
-                        log::debug!("Unhandled {:?}", other);
+                }
+                SwarmEvent::Behaviour(ThalamusBehaviourEvent::Kademlia(kad::KademliaEvent::OutboundQueryProgressed {
+                    result: kad::QueryResult::StartProviding(Err(e)), ..
+                })) => {
+                    log::warn!("Failed to publish provider record: {}", e);
+                }
+                SwarmEvent::Behaviour(ThalamusBehaviourEvent::Kademlia(kad::KademliaEvent::RoutingUpdated {
+                    peer, is_new_peer: true, ..
+                })) => {
+                    log::info!("DHT routing table added {}", peer);
+                }
+                SwarmEvent::Behaviour(ThalamusBehaviourEvent::RelayClient(relay::client::Event::ReservationReqAccepted {
+                    relay_peer_id, renewal, ..
+                })) => {
+                    if !renewal {
+                        log::warn!("Relay {} accepted our reservation", relay_peer_id);
                     }
+                }
+                SwarmEvent::Behaviour(ThalamusBehaviourEvent::RelayClient(relay::client::Event::ReservationReqFailed {
+                    relay_peer_id, error, ..
+                })) => {
+                    log::error!("Relay {} refused our reservation: {:?}", relay_peer_id, error);
+                    relays.remove(&relay_peer_id);
+                }
+                SwarmEvent::Behaviour(ThalamusBehaviourEvent::Relay(relay::Event::ReservationReqAccepted {
+                    src_peer_id, renewed: false,
+                })) => {
+                    log::info!("Relaying for {}", src_peer_id);
+                }
+                SwarmEvent::Behaviour(ThalamusBehaviourEvent::Dcutr(dcutr::Event::DirectConnectionUpgradeSucceeded {
+                    remote_peer_id,
+                })) => {
+                    log::warn!("Hole punched a direct connection to {}", remote_peer_id);
+                    relayed_peers.remove(&remote_peer_id);
+                    set_relayed(Arc::clone(&thalamus), remote_peer_id, false);
+                }
+                SwarmEvent::Behaviour(ThalamusBehaviourEvent::Dcutr(dcutr::Event::DirectConnectionUpgradeFailed {
+                    remote_peer_id, error,
+                })) => {
+                    log::warn!("Hole punching to {} failed, staying relayed: {}", remote_peer_id, error);
+                }
+                SwarmEvent::Behaviour(ThalamusBehaviourEvent::Infer(request_response::Event::InboundFailure {
+                    peer, error, ..
+                })) => {
+                    log::error!("Inference job from {} failed: {}", peer, error);
+                }
+                SwarmEvent::Behaviour(ThalamusBehaviourEvent::Ping(ping::Event {
+                    peer,
+                    result: Ok(libp2p::ping::Success::Ping { rtt }),
+                    ..
+                })) => {
+                    log::debug!("Ping to {} in {:?}", peer, rtt);
+                }
+                other => {
+                    log::debug!("Unhandled {:?}", other);
+                }
+            },
+            Some(command) = commands.recv() => match command {
+                P2pCommand::Infer { peer, envelope, reply } => {
+                    let request_id = swarm.behaviour_mut().infer.send_request(&peer, envelope);
+                    pending_requests.insert(request_id, reply);
+                }
+                P2pCommand::FindProviders { capability, reply } => {
+                    let query_id = swarm.behaviour_mut().kademlia.get_providers(kad::RecordKey::new(&capability));
+                    pending_providers.insert(query_id, (capability, HashSet::new(), reply));
+                }
+                P2pCommand::PublishJob { job, finished } => {
+                    let event = match finished {
+                        true => jobs::JobEvent::Finished { pid: pid.to_string(), job: job },
+                        false => jobs::JobEvent::Updated { pid: pid.to_string(), job: job },
+                    };
+                    publish(&mut swarm, &jobs_topic, &event);
+                }
             },
-            _ = discover_tick.tick(), if cookie.is_some() => {}
-                // swarm.behaviour_mut().rendezvous.discover(
-                //     Some(rendezvous::Namespace::new(NAMESPACE.to_string()).unwrap()),
-                //     cookie.clone(),
-                //     None,
-                //     rendezvous_point)
+            _ = jobs_tick.tick() => {
+                let event = jobs::JobEvent::Snapshot { pid: pid.to_string(), jobs: crate::thalamus::jobs::node_jobs() };
+                publish(&mut swarm, &jobs_topic, &event);
+            },
+            _ = provide_tick.tick() => {
+                // Publish what we can run, and withdraw what we no longer can
+                let capabilities: Vec<String> = crate::ThalamusNodeCapability::local().iter().map(|c| c.tag.to_string()).collect();
+                for capability in provided.iter() {
+                    if !capabilities.contains(capability) {
+                        swarm.behaviour_mut().kademlia.stop_providing(&kad::RecordKey::new(capability));
+                    }
+                }
+                for capability in capabilities.iter() {
+                    match swarm.behaviour_mut().kademlia.start_providing(kad::RecordKey::new(capability)) {
+                        Ok(_) => {},
+                        Err(e) => log::error!("Failed to provide {}: {}", capability, e),
+                    }
+                }
+                provided = capabilities;
+            },
+            Some((channel, response)) = responses.recv() => {
+                match swarm.behaviour_mut().infer.send_response(channel, response) {
+                    Ok(_) => {},
+                    Err(_) => log::error!("Failed to send inference response: connection closed"),
+                }
+            },
+            _ = discover_tick.tick() => {
+                for rendezvous_node in rendezvous_peers.keys().cloned().collect::<Vec<PeerId>>() {
+                    swarm.behaviour_mut().rendezvous.discover(
+                        Some(rendezvous::Namespace::from_static(NAMESPACE)),
+                        cookies.get(&rendezvous_node).cloned(),
+                        None,
+                        rendezvous_node,
+                    );
+                }
+
+                // Redial rendezvous points we lost
+                for address in rendezvous_points.clone() {
+                    if !rendezvous_peers.values().any(|a| *a == address) {
+                        match swarm.dial(address.clone()) {
+                            Ok(_) => {},
+                            Err(e) => log::error!("Failed to dial rendezvous point {}: {}", address, e),
+                        }
+                    }
+                }
+            }
         }
     }
 }
 
-pub async fn exp() -> Result<(), Box<dyn Error>> {
-    let local_key = identity::Keypair::generate_ed25519();
-    let local_peer_id = PeerId::from(local_key.public());
-    log::warn!("Local peer id: {local_peer_id:?}");
-
-    let transport = tcp::async_io::Transport::default()
-        .upgrade(Version::V1Lazy)
-        .authenticate(noise::Config::new(&local_key).unwrap())
-        .multiplex(yamux::Config::default())
-        .boxed();
-
-    // Create a identify network behaviour.
-    let behaviour = identify::Behaviour::new(identify::Config::new(
-        "/ipfs/id/1.0.0".to_string(),
-        local_key.public(),
-    ));
-
-    let mut swarm =
-        SwarmBuilder::with_async_std_executor(transport, behaviour, local_peer_id).build();
-
-    // Tell the swarm to listen on all interfaces and a random, OS-assigned
-    // port.
-    swarm.listen_on("/ip4/0.0.0.0/tcp/0".parse()?)?;
-
-    // Dial the peer identified by the multi-address given as the second
-    // command-line argument, if any.
-    if let Some(addr) = std::env::args().nth(1) {
-        let remote: Multiaddr = addr.parse()?;
-        swarm.dial(remote)?;
-        log::warn!("Dialed {addr}")
+// Sends a job event to subscribed peers; with no peers yet it is dropped, and the next snapshot covers it
+fn publish(swarm: &mut libp2p::Swarm<ThalamusBehaviour>, topic: &gossipsub::IdentTopic, event: &jobs::JobEvent){
+    let data = match serde_json::to_vec(event) {
+        Ok(data) => data,
+        Err(e) => return log::error!("Unable to encode job event: {}", e),
+    };
+    match swarm.behaviour_mut().gossipsub.publish(topic.clone(), data) {
+        Ok(_) => {},
+        Err(gossipsub::PublishError::InsufficientPeers) => {},
+        Err(e) => log::warn!("Failed to publish job event: {}", e),
     }
+}
 
-    loop {
-        match swarm.select_next_some().await {
-            SwarmEvent::NewListenAddr { address, .. } => log::warn!("Listening on {address:?}"),
-            // Prints peer id identify info is being sent to.
-            SwarmEvent::Behaviour(identify::Event::Sent { peer_id, .. }) => {
-                log::warn!("Sent identify info to {peer_id:?}")
+/// Links an identified thalamus peer to its http endpoint in the node list
+fn link_peer(thalamus: Arc<Mutex<crate::ThalamusClient>>, peer_id: PeerId, info: identify::Info, relayed: bool){
+
+    // thalamus/{version}/{pid}/{www_port}
+    let parts: Vec<&str> = info.agent_version.split('/').collect();
+    if parts.len() != 4 || parts[0] != "thalamus" {
+        log::debug!("Ignoring non-thalamus peer {}: {}", peer_id, info.agent_version);
+        return;
+    }
+    let version = parts[1].to_string();
+    let pid = parts[2].to_string();
+    let port = match parts[3].parse::<u16>() {
+        Ok(port) => port,
+        Err(e) => {
+            log::error!("Invalid http port from peer {}: {}", peer_id, e);
+            return;
+        }
+    };
+
+    let mut ip_address: Option<String> = None;
+    for address in info.listen_addrs.iter() {
+        // Circuit addresses carry the relay's ip, not the peer's
+        if address.iter().any(|p| p == Protocol::P2pCircuit) {
+            continue;
+        }
+        for protocol in address.iter() {
+            match protocol {
+                Protocol::Ip4(ip) => {
+                    if ip_address.is_none() && !ip.is_loopback() && !ip.is_unspecified() {
+                        ip_address = Some(ip.to_string());
+                    }
+                },
+                _ => {}
             }
-            // Prints out the info received via the identify event
-            SwarmEvent::Behaviour(identify::Event::Received { info, .. }) => {
-                log::warn!("Received {info:?}")
+        }
+    }
+    let ipx = match ip_address {
+        Some(ipx) => ipx,
+        None => {
+            log::warn!("Peer {} has no routable ipv4 address", peer_id);
+            return;
+        }
+    };
+
+    let mut thalamus_x = thalamus.lock().unwrap();
+    let existing_index = thalamus_x.nodes.iter().position(|r| r.pid == pid);
+
+    // The pid is self-reported, so a record stays bound to the first peer that linked it;
+    // anyone else claiming that pid could otherwise redirect the node's traffic and jobs
+    match existing_index.and_then(|index| thalamus_x.nodes[index].peer_id.clone()) {
+        Some(bound) if bound != peer_id.to_string() => {
+            log::warn!("Peer {} claims pid {}, which is bound to peer {}; ignoring it", peer_id, pid, bound);
+            return;
+        },
+        _ => {}
+    }
+    let is_new = existing_index.is_none();
+    match existing_index {
+        Some(index) => {
+            thalamus_x.nodes[index].peer_id = Some(peer_id.to_string());
+            thalamus_x.nodes[index].relayed = relayed;
+            thalamus_x.nodes[index].ip_address = ipx.clone();
+            thalamus_x.nodes[index].port = port;
+            thalamus_x.nodes[index].version = version.clone();
+            thalamus_x.nodes[index].is_online = true;
+            thalamus_x.nodes[index].last_ping = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+        },
+        None => {
+            let mut thalamus_node = crate::ThalamusNode::new(pid.to_string(), version.to_string(), ipx.clone(), port);
+            thalamus_node.peer_id = Some(peer_id.to_string());
+            thalamus_node.relayed = relayed;
+            log::info!("NEW_P2P_NODE: {:?}", thalamus_node.clone());
+            thalamus_x.nodes.push(thalamus_node);
+        }
+    }
+    thalamus_x.save();
+    std::mem::drop(thalamus_x);
+
+    // Nodes behind NAT answer over p2p but not over http
+    let node_thc = Arc::clone(&thalamus);
+    std::thread::spawn(move || {
+        let reachable = crate::fetch_version(ipx.as_str(), port).is_ok();
+
+        let mut thalamus_x = node_thc.lock().unwrap();
+        for node in &mut thalamus_x.nodes {
+            if node.pid == pid {
+                node.p2p_only = !reachable;
             }
-            _ => {}
+        }
+        thalamus_x.save();
+        std::mem::drop(thalamus_x);
+
+        if !reachable {
+            log::warn!("Node {} is only reachable over p2p", pid);
+        }
+
+        if is_new {
+            crate::calc_stats(Arc::clone(&node_thc), pid, version, ipx, port);
+        }
+    });
+}
+
+/// Records a capability on the nodes behind the given providers
+fn record_capability(thalamus: Arc<Mutex<crate::ThalamusClient>>, capability: &str, providers: &Vec<PeerId>){
+    let providers: Vec<String> = providers.iter().map(|p| p.to_string()).collect();
+    let mut thalamus_x = thalamus.lock().unwrap();
+    for node in &mut thalamus_x.nodes {
+        match &node.peer_id {
+            Some(peer_id) => {
+                if !providers.contains(peer_id) {
+                    continue;
+                }
+            },
+            None => continue,
+        }
+
+        let mut capabilities = node.capablities.clone().unwrap_or(Vec::new());
+        if !capabilities.iter().any(|c| c.tag == capability) {
+            capabilities.push(crate::ThalamusNodeCapability{ tag: capability.to_string() });
+            node.capablities = Some(capabilities);
         }
     }
+    thalamus_x.save();
+    std::mem::drop(thalamus_x);
 }
 
+/// Records whether the node behind a peer is reached directly or through a relay
+fn set_relayed(thalamus: Arc<Mutex<crate::ThalamusClient>>, peer_id: PeerId, relayed: bool){
+    let mut thalamus_x = thalamus.lock().unwrap();
+    let peer = peer_id.to_string();
+    for node in &mut thalamus_x.nodes {
+        if node.peer_id.as_deref() == Some(peer.as_str()) && node.relayed != relayed {
+            node.relayed = relayed;
+            log::info!("NODE_ROUTE: {} {}", node.pid, if relayed { "relayed" } else { "direct" });
+        }
+    }
+    thalamus_x.save();
+    std::mem::drop(thalamus_x);
+}
 
-#[derive(NetworkBehaviour)]
-struct ServerBehaviour {
-    identify: identify::Behaviour,
-    rendezvous: rendezvous::server::Behaviour,
-    ping: ping::Behaviour,
-    keep_alive: keep_alive::Behaviour,
+/// Flags the node behind a disconnected peer as offline
+fn unlink_peer(thalamus: Arc<Mutex<crate::ThalamusClient>>, peer_id: PeerId){
+    let mut thalamus_x = thalamus.lock().unwrap();
+    let peer = peer_id.to_string();
+    for node in &mut thalamus_x.nodes {
+        if node.peer_id.as_deref() == Some(peer.as_str()) {
+            node.is_online = false;
+            jobs::clear(node);
+            log::warn!("NODE_OFFLINE: {:?}", node.pid);
+        }
+    }
+    thalamus_x.save();
+    std::mem::drop(thalamus_x);
 }
 
 #[derive(NetworkBehaviour)]
-struct MyBehaviour {
+struct ThalamusBehaviour {
     identify: identify::Behaviour,
+    rendezvous_server: rendezvous::server::Behaviour,
     rendezvous: rendezvous::client::Behaviour,
     ping: ping::Behaviour,
+    infer: request_response::Behaviour<infer::InferCodec>,
+    kademlia: kad::Kademlia<kad::store::MemoryStore>,
+    relay_client: relay::client::Behaviour,
+    relay: Toggle<relay::Behaviour>,
+    dcutr: dcutr::Behaviour,
+    gossipsub: gossipsub::Behaviour,
     keep_alive: keep_alive::Behaviour,
 }
@@ -0,0 +1,444 @@
+// ████████ ██   ██  █████  ██       █████  ███    ███ ██    ██ ███████ 
+//    ██    ██   ██ ██   ██ ██      ██   ██ ████  ████ ██    ██ ██      
+//    ██    ███████ ███████ ██      ███████ ██ ████ ██ ██    ██ ███████ 
+//    ██    ██   ██ ██   ██ ██      ██   ██ ██  ██  ██ ██    ██      ██ 
+//    ██    ██   ██ ██   ██ ███████ ██   ██ ██      ██  ██████  ███████                                                                             
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+// Inference over libp2p: /thalamus/infer/2, falling back to /thalamus/infer/1
+// Carries whisper, llama, tts and image jobs to peers we can only reach through the swarm.
+// Version 2 wraps each job with the caller's traceparent; version 1 peers get the bare job.
+
+use std::error::Error;
+use std::io;
+
+use async_trait::async_trait;
+use libp2p::core::upgrade::{read_length_prefixed, write_length_prefixed};
+use libp2p::futures::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use libp2p::request_response::{self, ProtocolName};
+use serde::{Serialize, Deserialize};
+
+use crate::client::ClientError;
+use crate::thalamus::services::llama::LlamaModel;
+use crate::thalamus::services::whisper::{WhisperFormat, WhisperModel};
+
+// Any peer can open an infer stream, so frames are capped before we allocate for them.
+// Requests carry a few minutes of wav audio or a single image.
+pub const MAX_REQUEST_SIZE: usize = 32 * 1024 * 1024;
+
+// Replies only come from peers we asked, but srgan upscales and vwav output still need room
+pub const MAX_RESPONSE_SIZE: usize = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone)]
+pub enum InferProtocol {
+    V1,
+    V2,
+}
+
+impl ProtocolName for InferProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        match self {
+            InferProtocol::V1 => b"/thalamus/infer/1",
+            InferProtocol::V2 => b"/thalamus/infer/2",
+        }
+    }
+}
+
+/// An inference job with the trace it belongs to
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InferEnvelope {
+    pub traceparent: Option<String>,
+    pub request: InferRequest,
+}
+
+/// An inference job sent to a peer
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum InferRequest {
+    Whisper { model: WhisperModel, language: Option<String>, format: WhisperFormat, speech: Vec<u8> },
+    WhisperVwav { model: WhisperModel, language: Option<String>, speech: Vec<u8> },
+    Llama { model: LlamaModel, prompt: String },
+    Tts { text: String, primary: String, fallback: String },
+    Srgan { filename: String, image: Vec<u8> },
+    Yolov7 { image: Vec<u8> },
+    Hardware,
+}
+
+impl InferRequest {
+    /// Short name of the job for logs and traces
+    pub fn kind(&self) -> &'static str {
+        match self {
+            InferRequest::Whisper { .. } => "whisper",
+            InferRequest::WhisperVwav { .. } => "whisper_vwav",
+            InferRequest::Llama { .. } => "llama",
+            InferRequest::Tts { .. } => "tts",
+            InferRequest::Srgan { .. } => "srgan",
+            InferRequest::Yolov7 { .. } => "yolo",
+            InferRequest::Hardware => "hardware",
+        }
+    }
+}
+
+/// The result of an inference job, mirroring the http reply bodies
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum InferResponse {
+    Json(String),
+    Text(String),
+    Bytes(Vec<u8>),
+    Error(String),
+    NotCapable(String),
+}
+impl InferResponse {
+    pub fn into_bytes(self) -> Result<Vec<u8>, ClientError> {
+        match self {
+            InferResponse::Bytes(bytes) => Ok(bytes),
+            other => Err(other.into_error()),
+        }
+    }
+
+    pub fn into_text(self) -> Result<String, ClientError> {
+        match self {
+            InferResponse::Text(text) => Ok(text),
+            InferResponse::Json(text) => Ok(text),
+            other => Err(other.into_error()),
+        }
+    }
+
+    pub fn into_json<T: serde::de::DeserializeOwned>(self) -> Result<T, ClientError> {
+        match self {
+            InferResponse::Json(json) => Ok(serde_json::from_str(&json)?),
+            other => Err(other.into_error()),
+        }
+    }
+
+    // Failed jobs read like their http counterparts
+    fn into_error(self) -> ClientError {
+        match self {
+            InferResponse::Error(e) => ClientError::Status {
+                status: 500,
+                error: Some(crate::thalamus::http::ErrorReply { error: e, kind: "internal".to_string() }),
+            },
+            InferResponse::NotCapable(e) => ClientError::NotCapable(e),
+            other => ClientError::Decode(format!("unexpected infer response: {:?}", other)),
+        }
+    }
+}
+
+/// Length prefixed bincode frames
+#[derive(Debug, Clone)]
+pub struct InferCodec;
+
+#[async_trait]
+impl request_response::Codec for InferCodec {
+    type Protocol = InferProtocol;
+    type Request = InferEnvelope;
+    type Response = InferResponse;
+
+    async fn read_request<T>(&mut self, protocol: &InferProtocol, io: &mut T) -> io::Result<Self::Request>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let data = read_length_prefixed(io, MAX_REQUEST_SIZE).await?;
+        match protocol {
+            InferProtocol::V1 => {
+                let request = bincode::deserialize(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                Ok(InferEnvelope { traceparent: None, request: request })
+            },
+            InferProtocol::V2 => bincode::deserialize(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        }
+    }
+
+    async fn read_response<T>(&mut self, _: &InferProtocol, io: &mut T) -> io::Result<Self::Response>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let data = read_length_prefixed(io, MAX_RESPONSE_SIZE).await?;
+        bincode::deserialize(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    async fn write_request<T>(&mut self, protocol: &InferProtocol, io: &mut T, envelope: InferEnvelope) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let data = match protocol {
+            InferProtocol::V1 => bincode::serialize(&envelope.request),
+            InferProtocol::V2 => bincode::serialize(&envelope),
+        }.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        oversized(data.len(), MAX_REQUEST_SIZE)?;
+        write_length_prefixed(io, data).await?;
+        io.close().await
+    }
+
+    async fn write_response<T>(&mut self, _: &InferProtocol, io: &mut T, response: InferResponse) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let data = bincode::serialize(&response).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        oversized(data.len(), MAX_RESPONSE_SIZE)?;
+        write_length_prefixed(io, data).await?;
+        io.close().await
+    }
+}
+
+// The remote end would refuse the frame anyway, so fail before sending it
+fn oversized(len: usize, max: usize) -> io::Result<()> {
+    if len > max {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("infer message of {} bytes exceeds the {} byte limit", len, max)));
+    }
+    return Ok(());
+}
+
+/// Runs an inbound inference job against the local services (blocking)
+pub fn handle(envelope: InferEnvelope) -> InferResponse {
+    let request_id = crate::logging::request_id();
+    let _scope = crate::logging::scope(request_id.to_string());
+    let mut span = crate::trace::accept(envelope.traceparent.as_deref(), format!("p2p infer {}", envelope.request.kind()));
+    span.attr("request.id", request_id);
+    match run(envelope.request) {
+        Ok(response) => {
+            match &response {
+                InferResponse::Error(e) => span.error(e),
+                _ => {}
+            }
+            response
+        },
+        Err(e) => {
+            log::error!("p2p_infer_error: {}", e);
+            span.error(&e);
+            InferResponse::Error(format!("{}", e))
+        }
+    }
+}
+
+fn run(request: InferRequest) -> Result<InferResponse, Box<dyn Error>> {
+    // Same admission checks as the http routes, then the job waits in the local queue
+    let (oid, spec) = match request {
+        InferRequest::Whisper { model, language, format, speech } => {
+            if !model.is_installed() {
+                return Ok(InferResponse::NotCapable(format!("whisper model {} is not installed", model)));
+            }
+            match crate::thalamus::hardware::admission(format!("whisper:{}", model).as_str()) {
+                Ok(_) => {},
+                Err(e) => return Ok(InferResponse::NotCapable(e)),
+            }
+            let oid = crate::thalamus::jobs::prepare()?;
+            let tmp_file_path = crate::thalamus::jobs::path(oid.as_str(), "input.wav");
+            crate::trace::write_file(tmp_file_path.as_str(), &speech)?;
+            (oid, crate::thalamus::jobs::Spec::Whisper { model: model, language: language, format: format, input: tmp_file_path })
+        },
+        InferRequest::WhisperVwav { model, language, speech } => {
+            if !model.is_installed() {
+                return Ok(InferResponse::NotCapable(format!("whisper model {} is not installed", model)));
+            }
+            match crate::thalamus::hardware::admission(format!("whisper:{}", model).as_str()) {
+                Ok(_) => {},
+                Err(e) => return Ok(InferResponse::NotCapable(e)),
+            }
+            let oid = crate::thalamus::jobs::prepare()?;
+            let tmp_file_path = crate::thalamus::jobs::path(oid.as_str(), "input.wav");
+            crate::trace::write_file(tmp_file_path.as_str(), &speech)?;
+            (oid, crate::thalamus::jobs::Spec::WhisperVwav { model: model, language: language, input: tmp_file_path })
+        },
+        InferRequest::Llama { model, prompt } => {
+            if !model.is_installed() {
+                return Ok(InferResponse::NotCapable(format!("llama model {} is not installed", model)));
+            }
+            match crate::thalamus::hardware::admission(format!("llama:{}", model).as_str()) {
+                Ok(_) => {},
+                Err(e) => return Ok(InferResponse::NotCapable(e)),
+            }
+            (crate::thalamus::jobs::prepare()?, crate::thalamus::jobs::Spec::Llama { model: model, prompt: prompt })
+        },
+        InferRequest::Tts { text, primary, fallback } => {
+            (crate::thalamus::jobs::prepare()?, crate::thalamus::jobs::Spec::Tts { text: text, primary: primary, fallback: fallback })
+        },
+        InferRequest::Srgan { filename, image } => {
+            let filename = match std::path::Path::new(&filename).file_name() {
+                Some(name) => name.to_string_lossy().to_string(),
+                None => format!("input.jpg"),
+            };
+            let oid = crate::thalamus::jobs::prepare()?;
+            let tmp_file_path = crate::thalamus::jobs::path(oid.as_str(), filename.as_str());
+            crate::trace::write_file(tmp_file_path.as_str(), &image)?;
+            (oid, crate::thalamus::jobs::Spec::Srgan { filename: filename, input: tmp_file_path })
+        },
+        InferRequest::Yolov7 { image } => {
+            let oid = crate::thalamus::jobs::prepare()?;
+            let tmp_file_path = crate::thalamus::jobs::path(oid.as_str(), "input.jpg");
+            crate::trace::write_file(tmp_file_path.as_str(), &image)?;
+            (oid, crate::thalamus::jobs::Spec::Yolov7 { input: tmp_file_path })
+        },
+        InferRequest::Hardware => {
+            return Ok(InferResponse::Json(serde_json::to_string(&crate::thalamus::hardware::Hardware::local())?));
+        },
+    };
+
+    let job = match crate::thalamus::jobs::run(oid.to_string(), spec) {
+        Some(job) => job,
+        None => return Ok(InferResponse::Error(format!("job {} was lost", oid))),
+    };
+    if job.status != crate::thalamus::jobs::Status::Completed {
+        return Ok(InferResponse::Error(job.error.clone().unwrap_or(format!("job {} {}", job.oid, job.status.as_str()))));
+    }
+
+    let bytes = crate::thalamus::jobs::output(&job)?;
+    match job.spec {
+        crate::thalamus::jobs::Spec::Whisper { .. } | crate::thalamus::jobs::Spec::Yolov7 { .. } => {
+            return Ok(InferResponse::Json(String::from_utf8_lossy(&bytes).to_string()));
+        },
+        crate::thalamus::jobs::Spec::Llama { .. } => {
+            return Ok(InferResponse::Text(String::from_utf8_lossy(&bytes).to_string()));
+        },
+        _ => return Ok(InferResponse::Bytes(bytes)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p::futures::executor::block_on;
+    use libp2p::futures::io::Cursor;
+    use libp2p::request_response::Codec;
+
+    fn whisper() -> InferRequest {
+        return InferRequest::Whisper {
+            model: WhisperModel::Tiny,
+            language: Some(format!("en")),
+            format: WhisperFormat::Srt,
+            speech: vec![1, 2, 3, 4],
+        };
+    }
+
+    fn envelope() -> InferEnvelope {
+        return InferEnvelope {
+            traceparent: Some(format!("00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01")),
+            request: whisper(),
+        };
+    }
+
+    fn send_request(protocol: &InferProtocol, envelope: InferEnvelope) -> io::Result<Vec<u8>> {
+        let mut io = Cursor::new(Vec::new());
+        block_on(InferCodec.write_request(protocol, &mut io, envelope))?;
+        return Ok(io.into_inner());
+    }
+
+    fn send_response(response: InferResponse) -> io::Result<Vec<u8>> {
+        let mut io = Cursor::new(Vec::new());
+        block_on(InferCodec.write_response(&InferProtocol::V2, &mut io, response))?;
+        return Ok(io.into_inner());
+    }
+
+    fn receive_request(protocol: &InferProtocol, data: Vec<u8>) -> io::Result<InferEnvelope> {
+        return block_on(InferCodec.read_request(protocol, &mut Cursor::new(data)));
+    }
+
+    fn receive_response(data: Vec<u8>) -> io::Result<InferResponse> {
+        return block_on(InferCodec.read_response(&InferProtocol::V2, &mut Cursor::new(data)));
+    }
+
+    // Just the unsigned varint length prefix of a frame, with no body behind it
+    fn prefix(mut len: usize) -> Vec<u8> {
+        let mut data = Vec::new();
+        while len >= 0x80 {
+            data.push((len as u8 & 0x7f) | 0x80);
+            len >>= 7;
+        }
+        data.push(len as u8);
+        return data;
+    }
+
+    #[test]
+    fn round_trips_v2_requests_with_their_traceparent() {
+        let data = send_request(&InferProtocol::V2, envelope()).unwrap();
+        let received = receive_request(&InferProtocol::V2, data).unwrap();
+        assert_eq!(received.traceparent, envelope().traceparent);
+        match received.request {
+            InferRequest::Whisper { model, language, format, speech } => {
+                assert_eq!(model, WhisperModel::Tiny);
+                assert_eq!(language.as_deref(), Some("en"));
+                assert_eq!(format, WhisperFormat::Srt);
+                assert_eq!(speech, vec![1, 2, 3, 4]);
+            },
+            other => panic!("unexpected request {:?}", other),
+        }
+    }
+
+    #[test]
+    fn v1_peers_get_the_bare_request() {
+        let data = send_request(&InferProtocol::V1, envelope()).unwrap();
+
+        // A version 1 peer gets exactly the frame it always did
+        let bare = bincode::serialize(&whisper()).unwrap();
+        assert_eq!(data, [prefix(bare.len()), bare].concat());
+
+        let received = receive_request(&InferProtocol::V1, data).unwrap();
+        assert!(received.traceparent.is_none());
+        assert_eq!(received.request.kind(), "whisper");
+    }
+
+    #[test]
+    fn protocol_names_carry_the_version() {
+        assert_eq!(InferProtocol::V2.protocol_name(), b"/thalamus/infer/2");
+        assert_eq!(InferProtocol::V1.protocol_name(), b"/thalamus/infer/1");
+    }
+
+    #[test]
+    fn round_trips_every_response() {
+        let responses = vec![
+            InferResponse::Json(format!("{{\"ok\":true}}")),
+            InferResponse::Text(format!("hello")),
+            InferResponse::Bytes(vec![0, 255, 7]),
+            InferResponse::Error(format!("boom")),
+            InferResponse::NotCapable(format!("no gpu")),
+        ];
+        for response in responses {
+            let expected = format!("{:?}", response);
+            let received = receive_response(send_response(response).unwrap()).unwrap();
+            assert_eq!(format!("{:?}", received), expected);
+        }
+    }
+
+    #[test]
+    fn responses_unwrap_like_http_replies() {
+        assert_eq!(InferResponse::Bytes(vec![9]).into_bytes().unwrap(), vec![9]);
+        assert_eq!(InferResponse::Json(format!("[1]")).into_text().unwrap(), "[1]");
+        assert_eq!(InferResponse::Json(format!("[1,2]")).into_json::<Vec<u32>>().unwrap(), vec![1, 2]);
+        match InferResponse::NotCapable(format!("no gpu")).into_bytes() {
+            Err(ClientError::NotCapable(e)) => assert_eq!(e, "no gpu"),
+            other => panic!("unexpected result {:?}", other),
+        }
+        match InferResponse::Error(format!("boom")).into_text() {
+            Err(ClientError::Status { status, .. }) => assert_eq!(status, 500),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_oversized_requests_before_reading_them() {
+        // An honest oversized frame would fail with UnexpectedEof once we tried to fill it
+        let e = receive_request(&InferProtocol::V2, prefix(MAX_REQUEST_SIZE + 1)).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_oversized_responses_before_reading_them() {
+        let e = receive_response(prefix(MAX_RESPONSE_SIZE + 1)).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn refuses_to_send_oversized_requests() {
+        let envelope = InferEnvelope { traceparent: None, request: InferRequest::Yolov7 { image: vec![0; MAX_REQUEST_SIZE] } };
+        let e = send_request(&InferProtocol::V2, envelope).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn caps_each_direction_separately() {
+        assert!(oversized(MAX_REQUEST_SIZE, MAX_REQUEST_SIZE).is_ok());
+        assert!(oversized(MAX_REQUEST_SIZE + 1, MAX_REQUEST_SIZE).is_err());
+        assert!(oversized(MAX_REQUEST_SIZE + 1, MAX_RESPONSE_SIZE).is_ok());
+        assert_eq!(oversized(MAX_RESPONSE_SIZE + 1, MAX_RESPONSE_SIZE).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+}
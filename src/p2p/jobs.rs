@@ -0,0 +1,83 @@
+// ████████ ██   ██  █████  ██       █████  ███    ███ ██    ██ ███████ 
+//    ██    ██   ██ ██   ██ ██      ██   ██ ████  ████ ██    ██ ██      
+//    ██    ███████ ███████ ██      ███████ ██ ████ ██ ██    ██ ███████ 
+//    ██    ██   ██ ██   ██ ██      ██   ██ ██  ██  ██ ██    ██      ██ 
+//    ██    ██   ██ ██   ██ ███████ ██   ██ ██      ██  ██████  ███████                                                                             
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+
+// Job lifecycle events over gossipsub: topic thalamus/jobs/1
+// Each node announces its scheduler jobs as they are queued, start and finish, and
+// republishes a snapshot every minute so peers that missed events, or just booted, catch up.
+// Peers keep the announced jobs on the node's record, which is what /api/nodex shows.
+
+use std::sync::{Arc, Mutex};
+
+use serde::{Serialize, Deserialize};
+
+use crate::ThalamusNodeJob;
+
+pub const TOPIC: &str = "thalamus/jobs/1";
+
+// Benchmarks are run by the node that keeps the record, not announced by the node itself
+const BENCHMARK_JOB: &str = "calculate_stats";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum JobEvent {
+    /// Every unfinished job on the node; replaces what peers knew
+    Snapshot { pid: String, jobs: Vec<ThalamusNodeJob> },
+    /// A job was queued or started
+    Updated { pid: String, job: ThalamusNodeJob },
+    /// A job completed, failed or was cancelled
+    Finished { pid: String, job: ThalamusNodeJob },
+}
+impl JobEvent {
+    pub fn pid(&self) -> &str {
+        match self {
+            JobEvent::Snapshot { pid, .. } | JobEvent::Updated { pid, .. } | JobEvent::Finished { pid, .. } => pid.as_str(),
+        }
+    }
+}
+
+/// Applies an event from a peer to that peer's node record
+pub fn apply(thalamus: Arc<Mutex<crate::ThalamusClient>>, source: &libp2p::PeerId, event: JobEvent) {
+    let mut thalamus_x = thalamus.lock().unwrap();
+    let node = match thalamus_x.nodes.iter_mut().find(|n| n.pid == event.pid()) {
+        Some(node) => node,
+        None => {
+            log::debug!("Job event from unknown node {}", event.pid());
+            return;
+        }
+    };
+
+    // Only a node may announce its own jobs
+    if node.peer_id.as_deref() != Some(source.to_string().as_str()) {
+        log::warn!("Dropping job event for {} published by {}", event.pid(), source);
+        return;
+    }
+    match event {
+        JobEvent::Snapshot { jobs, .. } => {
+            node.jobs.retain(|j| j.job_identifier == BENCHMARK_JOB);
+            node.jobs.extend(jobs);
+        },
+        JobEvent::Updated { job, .. } => {
+            match node.jobs.iter_mut().find(|j| j.oid == job.oid) {
+                Some(existing) => *existing = job,
+                None => node.jobs.push(job),
+            }
+        },
+        JobEvent::Finished { job, .. } => {
+            node.jobs.retain(|j| j.oid != job.oid);
+        },
+    }
+    thalamus_x.save();
+    std::mem::drop(thalamus_x);
+}
+
+/// Forgets the announced jobs of a node we can no longer hear from
+pub fn clear(node: &mut crate::ThalamusNode) {
+    node.jobs.retain(|j| j.job_identifier == BENCHMARK_JOB);
+}
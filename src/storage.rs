@@ -0,0 +1,123 @@
+// ████████ ██   ██  █████  ██       █████  ███    ███ ██    ██ ███████ 
+//    ██    ██   ██ ██   ██ ██      ██   ██ ████  ████ ██    ██ ██      
+//    ██    ███████ ███████ ██      ███████ ██ ████ ██ ██    ██ ███████ 
+//    ██    ██   ██ ██   ██ ██      ██   ██ ██  ██  ██ ██    ██      ██ 
+//    ██    ██   ██ ██   ██ ███████ ██   ██ ██      ██  ██████  ███████                                                                             
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+
+// State store behind ThalamusClient
+// The json backend keeps the original clients.json layout, the sqlite backend keeps nodes,
+// job history and benchmark runs in tables so history can be queried without loading it all.
+pub mod json;
+pub mod sqlite;
+
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Serialize, Deserialize};
+
+use crate::{ThalamusClient, ThalamusNodeJob, ThalamusNodeStats};
+
+use error_chain::error_chain;
+error_chain! {
+    foreign_links {
+        Io(std::io::Error);
+        Json(serde_json::Error);
+        Sqlite(rusqlite::Error);
+    }
+}
+
+/// Current ThalamusClient schema, bump it and add a migration when the layout changes
+pub const SCHEMA_VERSION: u64 = 2;
+
+/// A job as kept in job history
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JobRecord {
+    pub pid: String,
+    pub oid: String,
+    pub job_identifier: String,
+    pub status: Option<String>,
+    pub started_at: i64,
+    pub finished_at: Option<i64>,
+}
+impl JobRecord {
+    pub fn new(pid: String, job: &ThalamusNodeJob, status: &str) -> JobRecord {
+        JobRecord {
+            pid: pid,
+            oid: job.oid.clone(),
+            job_identifier: job.job_identifier.clone(),
+            status: Some(status.to_string()),
+            started_at: job.started_at,
+            finished_at: Some(SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)),
+        }
+    }
+}
+
+/// One benchmark run of a node
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BenchmarkRecord {
+    pub pid: String,
+    pub version: String,
+    pub stats: ThalamusNodeStats,
+    pub ran_at: i64,
+}
+
+/// Where ThalamusClient state and history are kept
+pub trait Store: Send + Sync {
+    /// The saved client, or None when nothing has been saved yet
+    fn load(&self) -> Result<Option<ThalamusClient>>;
+
+    /// Queues the client to be written; errors are logged by the backend
+    fn save(&self, client: &ThalamusClient);
+
+    /// Writes any queued save right away
+    fn flush(&self) -> Result<()>;
+
+    fn record_job(&self, job: &JobRecord) -> Result<()>;
+
+    /// Most recent jobs first, optionally for one node
+    fn jobs(&self, pid: Option<&str>, limit: usize) -> Result<Vec<JobRecord>>;
+
+    fn record_benchmark(&self, benchmark: &BenchmarkRecord) -> Result<()>;
+
+    /// Most recent benchmark runs of a node first
+    fn benchmarks(&self, pid: &str, limit: usize) -> Result<Vec<BenchmarkRecord>>;
+}
+
+static STORE: OnceLock<Box<dyn Store>> = OnceLock::new();
+
+/// Whether a load or save might succeed if tried again: an interrupted read or a busy database
+pub fn transient(e: &Error) -> bool {
+    match e.kind() {
+        ErrorKind::Io(e) => match e.kind() {
+            std::io::ErrorKind::Interrupted | std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut => true,
+            _ => false,
+        },
+        ErrorKind::Sqlite(rusqlite::Error::SqliteFailure(e, _)) => match e.code {
+            rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked => true,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Selects the backend ("json" or "sqlite"), must run before the first ThalamusClient::load
+pub fn init(backend: &str) -> Result<()> {
+    let store: Box<dyn Store> = match backend {
+        "json" => Box::new(json::JsonStore),
+        "sqlite" => Box::new(sqlite::SqliteStore::open(sqlite::SQLITE_PATH)?),
+        _ => return Err(format!("unknown storage backend: {}", backend).into()),
+    };
+    if STORE.set(store).is_err() {
+        log::warn!("Storage backend already initialized, ignoring {}", backend);
+    }
+    Ok(())
+}
+
+/// The selected backend, json unless init chose otherwise
+pub fn store() -> &'static dyn Store {
+    STORE.get_or_init(|| Box::new(json::JsonStore)).as_ref()
+}
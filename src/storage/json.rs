@@ -0,0 +1,512 @@
+// ████████ ██   ██  █████  ██       █████  ███    ███ ██    ██ ███████ 
+//    ██    ██   ██ ██   ██ ██      ██   ██ ████  ████ ██    ██ ██      
+//    ██    ███████ ███████ ██      ███████ ██ ████ ██ ██    ██ ███████ 
+//    ██    ██   ██ ██   ██ ██      ██   ██ ██  ██  ██ ██    ██      ██ 
+//    ██    ██   ██ ██   ██ ███████ ██   ██ ██      ██  ██████  ███████                                                                             
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+
+// JSON backend: the whole ThalamusClient in clients.json, history in append-only .jsonl files
+//
+// Crash-safe persistence for clients.json
+// Writes go to a temp file that is fsynced and renamed over the real one, under an advisory
+// lock so two thalamus processes never interleave. Saves are debounced: bursts of save()
+// calls coalesce into one write of the latest state.
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::sync::{Condvar, Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use super::{BenchmarkRecord, JobRecord, Store, SCHEMA_VERSION};
+use crate::ThalamusClient;
+
+pub const CLIENTS_PATH: &str = "/opt/thalamus/clients.json";
+pub const CLIENTS_BACKUP_PATH: &str = "/opt/thalamus/clients.bak.json";
+const LOCK_PATH: &str = "/opt/thalamus/clients.json.lock";
+pub const JOBS_PATH: &str = "/opt/thalamus/jobs.jsonl";
+pub const BENCHMARKS_PATH: &str = "/opt/thalamus/benchmarks.jsonl";
+
+// Quiet period before a pending save is written, and the longest a save may wait
+const DEBOUNCE: Duration = Duration::from_millis(500);
+const MAX_DELAY: Duration = Duration::from_secs(2);
+
+/// Advisory lock on clients.json, released on drop
+pub struct FileLock {
+    file: File,
+}
+impl FileLock {
+    pub fn exclusive() -> io::Result<FileLock> {
+        FileLock::acquire(libc::LOCK_EX)
+    }
+
+    pub fn shared() -> io::Result<FileLock> {
+        FileLock::acquire(libc::LOCK_SH)
+    }
+
+    fn acquire(operation: libc::c_int) -> io::Result<FileLock> {
+        let file = match OpenOptions::new().create(true).write(true).open(LOCK_PATH) {
+            Ok(file) => file,
+            // Readers without write access (e.g. the CLI as a normal user) can still share-lock
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied && operation == libc::LOCK_SH => File::open(LOCK_PATH)?,
+            Err(e) => return Err(e),
+        };
+        if unsafe { libc::flock(file.as_raw_fd(), operation) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(FileLock { file: file })
+    }
+}
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        unsafe { libc::flock(self.file.as_raw_fd(), libc::LOCK_UN) };
+    }
+}
+
+/// Writes a file by renaming a fsynced temp file over it
+pub fn atomic_write(path: &str, data: &[u8]) -> io::Result<()> {
+    let tmp_path = format!("{}.tmp", path);
+    let mut file = File::create(tmp_path.as_str())?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    std::fs::rename(tmp_path.as_str(), path)?;
+
+    // Persist the rename itself
+    match Path::new(path).parent() {
+        Some(dir) => File::open(dir)?.sync_all()?,
+        None => {}
+    }
+    Ok(())
+}
+
+/// Writes clients.json now, keeping the previous good copy as clients.bak.json
+pub fn write_clients(json: &str) -> io::Result<()> {
+    let _lock = FileLock::exclusive()?;
+    write_with_backup(CLIENTS_PATH, CLIENTS_BACKUP_PATH, json)
+}
+
+// A corrupt previous file is dropped rather than overwriting the last good backup
+fn write_with_backup(path: &str, backup_path: &str, json: &str) -> io::Result<()> {
+    if Path::new(path).exists() {
+        let previous = std::fs::read(path)?;
+        if serde_json::from_slice::<serde_json::Value>(&previous).is_ok() {
+            atomic_write(backup_path, &previous)?;
+        }
+    }
+    atomic_write(path, json.as_bytes())
+}
+
+/// Reads clients.json (or the backup when it's missing or corrupt), migrated to SCHEMA_VERSION
+pub fn read_clients() -> io::Result<Option<serde_json::Value>> {
+    let _lock = FileLock::shared()?;
+    read_first(&[CLIENTS_PATH, CLIENTS_BACKUP_PATH])
+}
+
+// The first of the paths that exists and parses, migrated. When none of them parse, the
+// unparsable ones are moved aside (clients.json.corrupt-<ts>) so the fresh state written in
+// their place can't rotate them away.
+fn read_first(paths: &[&str]) -> io::Result<Option<serde_json::Value>> {
+    let mut corrupt = Vec::new();
+    for path in paths {
+        if !Path::new(path).exists() {
+            continue;
+        }
+        match serde_json::from_slice::<serde_json::Value>(&std::fs::read(path)?) {
+            Ok(value) => return Ok(Some(migrate(value)?)),
+            Err(e) => {
+                log::error!("Unable to parse {}: {}", path, e);
+                corrupt.push(path);
+            }
+        }
+    }
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    for path in corrupt {
+        let aside = format!("{}.corrupt-{}", path, now);
+        match std::fs::rename(path, aside.as_str()) {
+            Ok(_) => log::warn!("Moved unparsable {} to {}", path, aside),
+            // Another process got to it first
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {},
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(None)
+}
+
+/// Brings an older clients.json up to SCHEMA_VERSION, one version at a time
+pub fn migrate(mut value: serde_json::Value) -> io::Result<serde_json::Value> {
+    let mut version = value.get("schema_version").and_then(|v| v.as_u64()).unwrap_or(0);
+    if version > SCHEMA_VERSION {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("clients.json schema {} is newer than this build ({})", version, SCHEMA_VERSION)));
+    }
+    while version < SCHEMA_VERSION {
+        value = match version {
+            0 => v0_to_v1(value)?,
+            1 => v1_to_v2(value)?,
+            _ => value,
+        };
+        version += 1;
+        log::warn!("Migrated clients.json to schema {}", version);
+    }
+    match value.as_object_mut() {
+        Some(object) => { object.insert("schema_version".to_string(), serde_json::Value::from(SCHEMA_VERSION)); },
+        None => return Err(invalid("clients.json is not an object")),
+    }
+    Ok(value)
+}
+
+// v0 -> v1: files from before versioning; fills in the node fields added since
+fn v0_to_v1(mut value: serde_json::Value) -> io::Result<serde_json::Value> {
+    for node in nodes_mut(&mut value)? {
+        node.entry("peer_id").or_insert(serde_json::Value::Null);
+        node.entry("p2p_only").or_insert(serde_json::Value::Bool(false));
+        node.entry("relayed").or_insert(serde_json::Value::Bool(false));
+    }
+    Ok(value)
+}
+
+// v1 -> v2: node stats move from fixed fields to results keyed by service and model.
+// ThalamusNodeStats reads either layout (old peers still send the fixed fields) and writes the new one.
+fn v1_to_v2(mut value: serde_json::Value) -> io::Result<serde_json::Value> {
+    for node in nodes_mut(&mut value)? {
+        match node.get_mut("stats") {
+            Some(stats) => {
+                let migrated: crate::ThalamusNodeStats = serde_json::from_value(stats.take())?;
+                *stats = serde_json::to_value(migrated)?;
+            },
+            None => {}
+        }
+    }
+    Ok(value)
+}
+
+// The node records of a clients.json, adding an empty list when it has none
+fn nodes_mut(value: &mut serde_json::Value) -> io::Result<Vec<&mut serde_json::Map<String, serde_json::Value>>> {
+    let object = value.as_object_mut().ok_or(invalid("clients.json is not an object"))?;
+    let nodes = object.entry("nodes").or_insert(serde_json::Value::Array(Vec::new()));
+    let nodes = nodes.as_array_mut().ok_or(invalid("clients.json nodes is not a list"))?;
+    let mut records = Vec::new();
+    for node in nodes.iter_mut() {
+        records.push(node.as_object_mut().ok_or(invalid("clients.json node is not an object"))?);
+    }
+    Ok(records)
+}
+
+fn invalid(error: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error.to_string())
+}
+
+struct Pending {
+    json: Option<String>,
+    first_at: Option<Instant>,
+    last_at: Option<Instant>,
+}
+
+static PENDING: OnceLock<(Mutex<Pending>, Condvar)> = OnceLock::new();
+
+// Held while taking and writing a save, so an older state never lands after a newer one
+static WRITE: Mutex<()> = Mutex::new(());
+
+fn pending() -> &'static (Mutex<Pending>, Condvar) {
+    PENDING.get_or_init(|| {
+        std::thread::spawn(writer);
+        (Mutex::new(Pending { json: None, first_at: None, last_at: None }), Condvar::new())
+    })
+}
+
+/// Queues clients.json for a debounced write
+pub fn save_clients(json: String) {
+    let (lock, condvar) = pending();
+    let mut pending = lock.lock().unwrap();
+    let now = Instant::now();
+    pending.json = Some(json);
+    pending.first_at = Some(pending.first_at.unwrap_or(now));
+    pending.last_at = Some(now);
+    std::mem::drop(pending);
+    condvar.notify_one();
+}
+
+fn take_pending() -> Option<String> {
+    let (lock, _) = pending();
+    let mut pending = lock.lock().unwrap();
+    pending.first_at = None;
+    pending.last_at = None;
+    pending.json.take()
+}
+
+/// Writes any queued save right away
+pub fn flush_clients() -> io::Result<()> {
+    let _write = WRITE.lock().unwrap();
+    match take_pending() {
+        Some(json) => write_clients(json.as_str()),
+        None => Ok(()),
+    }
+}
+
+// Waits for a quiet period (or MAX_DELAY) after the last save, then writes the latest state
+fn writer() {
+    let (lock, condvar) = pending();
+    loop {
+        let mut pending = lock.lock().unwrap();
+        while pending.json.is_none() {
+            pending = condvar.wait(pending).unwrap();
+        }
+
+        let (first_at, last_at) = match (pending.first_at, pending.last_at) {
+            (Some(first_at), Some(last_at)) => (first_at, last_at),
+            _ => (Instant::now(), Instant::now()),
+        };
+        let due = std::cmp::min(last_at + DEBOUNCE, first_at + MAX_DELAY);
+        let now = Instant::now();
+        if now < due {
+            let _ = condvar.wait_timeout(pending, due - now).unwrap();
+            continue;
+        }
+
+        std::mem::drop(pending);
+
+        let _write = WRITE.lock().unwrap();
+        match take_pending() {
+            Some(json) => match write_clients(json.as_str()) {
+                Ok(_) => {},
+                Err(e) => log::error!("Unable to write {}: {}", CLIENTS_PATH, e),
+            },
+            None => {}
+        }
+    }
+}
+
+/// The original clients.json store
+pub struct JsonStore;
+
+impl Store for JsonStore {
+    fn load(&self) -> super::Result<Option<ThalamusClient>> {
+        match read_clients()? {
+            Some(value) => Ok(Some(serde_json::from_value(value)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn save(&self, client: &ThalamusClient) {
+        match serde_json::to_string(client) {
+            Ok(j) => save_clients(j),
+            Err(e) => log::error!("Unable to serialize clients: {}", e),
+        }
+    }
+
+    fn flush(&self) -> super::Result<()> {
+        flush_clients()?;
+        Ok(())
+    }
+
+    fn record_job(&self, job: &JobRecord) -> super::Result<()> {
+        append_line(JOBS_PATH, job)
+    }
+
+    fn jobs(&self, pid: Option<&str>, limit: usize) -> super::Result<Vec<JobRecord>> {
+        let jobs: Vec<JobRecord> = read_lines(JOBS_PATH)?;
+        let mut jobs: Vec<JobRecord> = jobs.into_iter().filter(|job| pid.map_or(true, |pid| job.pid == pid)).collect();
+        jobs.sort_by(|a, b| b.started_at.cmp(&a.started_at));
+        jobs.truncate(limit);
+        Ok(jobs)
+    }
+
+    fn record_benchmark(&self, benchmark: &BenchmarkRecord) -> super::Result<()> {
+        append_line(BENCHMARKS_PATH, benchmark)
+    }
+
+    fn benchmarks(&self, pid: &str, limit: usize) -> super::Result<Vec<BenchmarkRecord>> {
+        let benchmarks: Vec<BenchmarkRecord> = read_lines(BENCHMARKS_PATH)?;
+        let mut benchmarks: Vec<BenchmarkRecord> = benchmarks.into_iter().filter(|b| b.pid == pid).collect();
+        benchmarks.sort_by(|a, b| b.ran_at.cmp(&a.ran_at));
+        benchmarks.truncate(limit);
+        Ok(benchmarks)
+    }
+}
+
+// One JSON document per line; O_APPEND keeps concurrent single-line writes whole
+fn append_line<T: serde::Serialize>(path: &str, record: &T) -> super::Result<()> {
+    let mut line = serde_json::to_string(record)?;
+    line.push('\n');
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+// Skips lines that don't parse, e.g. one cut short by a crash
+fn read_lines<T: serde::de::DeserializeOwned>(path: &str) -> super::Result<Vec<T>> {
+    if !Path::new(path).exists() {
+        return Ok(Vec::new());
+    }
+    let data = std::fs::read_to_string(path)?;
+    let mut records = Vec::new();
+    for line in data.lines() {
+        match serde_json::from_str::<T>(line) {
+            Ok(record) => records.push(record),
+            Err(e) => log::warn!("Skipping bad line in {}: {}", path, e),
+        }
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // clients.json as written before schema versioning: fixed stats fields, no p2p fields
+    const V0_CLIENTS: &str = r#"{"nodes":[{"pid":"abc","ip_address":"192.168.1.2","version":"0.0.13","port":8050,"jobs":[],"capablities":null,"last_ping":1690000000,"is_online":true,"stats":{"tts_score":900,"llama_7b":12000,"llama_13b":null,"llama_30b":null,"llama_65b":null,"llama_score":12000,"nst_score":null,"srgan_score":4000,"whisper_stt_tiny":800,"whisper_stt_base":1500,"whisper_stt_medium":null,"whisper_stt_large":null,"whisper_stt_score":800,"whisper_vwav_tiny":null,"whisper_vwav_base":null,"whisper_vwav_medium":null,"whisper_vwav_large":null,"whisper_vwav_score":null}}]}"#;
+
+    // A scratch directory per test, removed on drop
+    struct Scratch(std::path::PathBuf);
+    impl Scratch {
+        fn new(name: &str) -> Scratch {
+            let dir = std::env::temp_dir().join(format!("thalamus-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            Scratch(dir)
+        }
+
+        fn path(&self, name: &str) -> String {
+            self.0.join(name).to_string_lossy().to_string()
+        }
+    }
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn migrates_v0_clients() {
+        let value = migrate(serde_json::from_str(V0_CLIENTS).unwrap()).unwrap();
+        assert_eq!(value["schema_version"], SCHEMA_VERSION);
+
+        let node = &value["nodes"][0];
+        assert!(node["peer_id"].is_null());
+        assert_eq!(node["p2p_only"], false);
+        assert_eq!(node["relayed"], false);
+        assert!(node["stats"].get("whisper_stt_base").is_none());
+        assert_eq!(node["stats"]["benchmarks"]["whisper:base"]["median"], 1500);
+        assert_eq!(node["stats"]["scores"]["whisper"], 800);
+
+        let client: ThalamusClient = serde_json::from_value(value).unwrap();
+        assert_eq!(client.schema_version, SCHEMA_VERSION);
+        assert_eq!(client.nodes[0].pid, "abc");
+        assert_eq!(client.nodes[0].stats.median("llama:7B"), Some(12000));
+        assert_eq!(client.nodes[0].stats.median("srgan"), Some(4000));
+        assert_eq!(client.nodes[0].stats.score("tts"), Some(900));
+    }
+
+    #[test]
+    fn migrates_v1_clients() {
+        let mut value: serde_json::Value = serde_json::from_str(V0_CLIENTS).unwrap();
+        value["schema_version"] = serde_json::Value::from(1);
+        value["nodes"][0]["peer_id"] = serde_json::Value::from("12D3KooWabc");
+        value["nodes"][0]["p2p_only"] = serde_json::Value::from(true);
+
+        let value = migrate(value).unwrap();
+        let node = &value["nodes"][0];
+        assert_eq!(node["peer_id"], "12D3KooWabc");
+        assert_eq!(node["p2p_only"], true);
+        assert_eq!(node["stats"]["benchmarks"]["whisper:tiny"]["median"], 800);
+        assert!(node["stats"].get("whisper_stt_tiny").is_none());
+    }
+
+    #[test]
+    fn migration_is_idempotent() {
+        let once = migrate(serde_json::from_str(V0_CLIENTS).unwrap()).unwrap();
+        let twice = migrate(once.clone()).unwrap();
+        assert_eq!(once, twice);
+    }
+
+    #[test]
+    fn migrates_files_without_nodes() {
+        let value = migrate(serde_json::json!({})).unwrap();
+        let client: ThalamusClient = serde_json::from_value(value).unwrap();
+        assert!(client.nodes.is_empty());
+    }
+
+    #[test]
+    fn rejects_newer_and_malformed_schemas() {
+        assert!(migrate(serde_json::json!({ "schema_version": SCHEMA_VERSION + 1, "nodes": [] })).is_err());
+        assert!(migrate(serde_json::json!([])).is_err());
+        assert!(migrate(serde_json::json!({ "nodes": {} })).is_err());
+    }
+
+    #[test]
+    fn atomic_write_replaces_the_file() {
+        let scratch = Scratch::new("atomic-write");
+        let path = scratch.path("clients.json");
+        std::fs::write(path.as_str(), "old").unwrap();
+
+        atomic_write(path.as_str(), b"new").unwrap();
+        assert_eq!(std::fs::read_to_string(path.as_str()).unwrap(), "new");
+        assert!(!Path::new(format!("{}.tmp", path).as_str()).exists());
+    }
+
+    #[test]
+    fn reads_the_backup_when_clients_json_is_corrupt() {
+        let scratch = Scratch::new("corrupt-main");
+        let path = scratch.path("clients.json");
+        let backup_path = scratch.path("clients.bak.json");
+        // Cut short, as by a crash mid-write without the temp file and rename
+        std::fs::write(path.as_str(), &V0_CLIENTS[..40]).unwrap();
+        std::fs::write(backup_path.as_str(), V0_CLIENTS).unwrap();
+
+        let value = read_first(&[path.as_str(), backup_path.as_str()]).unwrap().unwrap();
+        assert_eq!(value["nodes"][0]["pid"], "abc");
+        assert_eq!(value["schema_version"], SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn reads_the_backup_when_clients_json_is_missing() {
+        let scratch = Scratch::new("missing-main");
+        let path = scratch.path("clients.json");
+        let backup_path = scratch.path("clients.bak.json");
+        std::fs::write(backup_path.as_str(), V0_CLIENTS).unwrap();
+
+        let value = read_first(&[path.as_str(), backup_path.as_str()]).unwrap().unwrap();
+        assert_eq!(value["nodes"][0]["pid"], "abc");
+    }
+
+    #[test]
+    fn reads_nothing_when_every_copy_is_unusable() {
+        let scratch = Scratch::new("unusable");
+        let path = scratch.path("clients.json");
+        let backup_path = scratch.path("clients.bak.json");
+        std::fs::write(path.as_str(), "{").unwrap();
+
+        assert!(read_first(&[path.as_str(), backup_path.as_str()]).unwrap().is_none());
+
+        // The unparsable copy is kept aside, not left for a fresh save to replace
+        assert!(!Path::new(path.as_str()).exists());
+        let aside: Vec<String> = std::fs::read_dir(&scratch.0).unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .filter(|name| name.starts_with("clients.json.corrupt-"))
+            .collect();
+        assert_eq!(aside.len(), 1);
+        assert_eq!(std::fs::read_to_string(scratch.path(aside[0].as_str())).unwrap(), "{");
+    }
+
+    #[test]
+    fn keeps_the_previous_good_copy_as_backup() {
+        let scratch = Scratch::new("backup");
+        let path = scratch.path("clients.json");
+        let backup_path = scratch.path("clients.bak.json");
+
+        write_with_backup(path.as_str(), backup_path.as_str(), r#"{"nodes":[],"schema_version":1}"#).unwrap();
+        write_with_backup(path.as_str(), backup_path.as_str(), r#"{"nodes":[],"schema_version":2}"#).unwrap();
+        assert_eq!(std::fs::read_to_string(backup_path.as_str()).unwrap(), r#"{"nodes":[],"schema_version":1}"#);
+        assert_eq!(std::fs::read_to_string(path.as_str()).unwrap(), r#"{"nodes":[],"schema_version":2}"#);
+
+        // A corrupt clients.json never replaces the last good backup
+        std::fs::write(path.as_str(), "{\"nodes\":[").unwrap();
+        write_with_backup(path.as_str(), backup_path.as_str(), r#"{"nodes":[],"schema_version":2}"#).unwrap();
+        assert_eq!(std::fs::read_to_string(backup_path.as_str()).unwrap(), r#"{"nodes":[],"schema_version":1}"#);
+    }
+}
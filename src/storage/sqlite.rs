@@ -0,0 +1,310 @@
+// ████████ ██   ██  █████  ██       █████  ███    ███ ██    ██ ███████ 
+//    ██    ██   ██ ██   ██ ██      ██   ██ ████  ████ ██    ██ ██      
+//    ██    ███████ ███████ ██      ███████ ██ ████ ██ ██    ██ ███████ 
+//    ██    ██   ██ ██   ██ ██      ██   ██ ██  ██  ██ ██    ██      ██ 
+//    ██    ██   ██ ██   ██ ███████ ██   ██ ██      ██  ██████  ███████                                                                             
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+
+// SQLite backend: nodes, job history and benchmark runs in their own tables
+// Nodes keep their queryable fields in columns and the full record as JSON, so new node
+// fields don't need a table migration. Saves only rewrite the rows that changed.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+use super::{BenchmarkRecord, JobRecord, Store, SCHEMA_VERSION};
+use crate::{ThalamusClient, ThalamusNode};
+
+pub const SQLITE_PATH: &str = "/opt/thalamus/thalamus.db";
+
+// Table layout, one entry per PRAGMA user_version step
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE nodes (
+        pid TEXT PRIMARY KEY,
+        ip_address TEXT NOT NULL,
+        version TEXT NOT NULL,
+        port INTEGER NOT NULL,
+        peer_id TEXT,
+        is_online INTEGER NOT NULL,
+        last_ping INTEGER NOT NULL,
+        record TEXT NOT NULL
+    );
+    CREATE TABLE jobs (
+        oid TEXT PRIMARY KEY,
+        pid TEXT NOT NULL,
+        job_identifier TEXT NOT NULL,
+        status TEXT,
+        started_at INTEGER NOT NULL,
+        finished_at INTEGER
+    );
+    CREATE INDEX jobs_pid ON jobs (pid, started_at);
+    CREATE TABLE benchmarks (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        pid TEXT NOT NULL,
+        version TEXT NOT NULL,
+        ran_at INTEGER NOT NULL,
+        stats TEXT NOT NULL
+    );
+    CREATE INDEX benchmarks_pid ON benchmarks (pid, ran_at);
+    CREATE TABLE meta (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );",
+];
+
+pub struct SqliteStore {
+    conn: Mutex<Connection>,
+    // Last record written per pid, so unchanged nodes are skipped
+    written: Mutex<HashMap<String, String>>,
+}
+impl SqliteStore {
+    pub fn open(path: &str) -> super::Result<SqliteStore> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch("PRAGMA journal_mode = WAL; PRAGMA synchronous = NORMAL;")?;
+        conn.busy_timeout(std::time::Duration::from_secs(5))?;
+
+        let version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            conn.execute_batch(format!("BEGIN; {} PRAGMA user_version = {}; COMMIT;", migration, i + 1).as_str())?;
+            log::warn!("Migrated {} to schema {}", path, i + 1);
+        }
+
+        Ok(SqliteStore {
+            conn: Mutex::new(conn),
+            written: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn write(&self, client: &ThalamusClient) -> super::Result<()> {
+        let mut records: Vec<(&ThalamusNode, String)> = Vec::new();
+        for node in &client.nodes {
+            records.push((node, serde_json::to_string(node)?));
+        }
+
+        let mut written = self.written.lock().unwrap();
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        for (node, record) in &records {
+            if written.get(&node.pid) == Some(record) {
+                continue;
+            }
+            tx.execute(
+                "INSERT INTO nodes (pid, ip_address, version, port, peer_id, is_online, last_ping, record)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+                 ON CONFLICT (pid) DO UPDATE SET ip_address = ?2, version = ?3, port = ?4, peer_id = ?5,
+                    is_online = ?6, last_ping = ?7, record = ?8",
+                params![node.pid, node.ip_address, node.version, node.port, node.peer_id, node.is_online, node.last_ping, record],
+            )?;
+        }
+        let removed: Vec<String> = written.keys().filter(|pid| !records.iter().any(|(node, _)| &node.pid == *pid)).cloned().collect();
+        for pid in &removed {
+            tx.execute("DELETE FROM nodes WHERE pid = ?1", params![pid])?;
+        }
+        tx.execute(
+            "INSERT INTO meta (key, value) VALUES ('schema_version', ?1) ON CONFLICT (key) DO UPDATE SET value = ?1",
+            params![client.schema_version.to_string()],
+        )?;
+        tx.commit()?;
+
+        for pid in &removed {
+            written.remove(pid);
+        }
+        for (node, record) in records {
+            written.insert(node.pid.clone(), record);
+        }
+        Ok(())
+    }
+}
+
+impl Store for SqliteStore {
+    fn load(&self) -> super::Result<Option<ThalamusClient>> {
+        let conn = self.conn.lock().unwrap();
+        let schema_version: Option<String> = conn.query_row("SELECT value FROM meta WHERE key = 'schema_version'", [], |row| row.get(0)).optional()?;
+        let schema_version = match schema_version {
+            Some(v) => v.parse::<u64>().unwrap_or(0),
+            None => {
+                // Fresh database, carry over an existing clients.json
+                std::mem::drop(conn);
+                let client = super::json::JsonStore.load()?;
+                match &client {
+                    Some(client) => {
+                        log::warn!("Importing {} nodes from {}", client.nodes.len(), super::json::CLIENTS_PATH);
+                        self.write(client)?;
+                    },
+                    None => {}
+                }
+                return Ok(client);
+            }
+        };
+
+        let mut stmt = conn.prepare("SELECT record FROM nodes ORDER BY pid")?;
+        let records = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        let mut nodes = Vec::new();
+        let mut written = HashMap::new();
+        for record in records {
+            let record = record?;
+            let value: serde_json::Value = serde_json::from_str(record.as_str())?;
+            match value.get("pid").and_then(|pid| pid.as_str()) {
+                Some(pid) => { written.insert(pid.to_string(), record.clone()); },
+                None => {}
+            }
+            nodes.push(value);
+        }
+        std::mem::drop(stmt);
+        std::mem::drop(conn);
+
+        // Node records go through the same migrations as clients.json
+        let value = super::json::migrate(serde_json::json!({
+            "schema_version": schema_version,
+            "nodes": nodes,
+        }))?;
+        let client: ThalamusClient = serde_json::from_value(value)?;
+        if schema_version == SCHEMA_VERSION {
+            *self.written.lock().unwrap() = written;
+        }
+        Ok(Some(client))
+    }
+
+    fn save(&self, client: &ThalamusClient) {
+        match self.write(client) {
+            Ok(_) => {},
+            Err(e) => log::error!("Unable to write {}: {}", SQLITE_PATH, e),
+        }
+    }
+
+    fn flush(&self) -> super::Result<()> {
+        Ok(())
+    }
+
+    fn record_job(&self, job: &JobRecord) -> super::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO jobs (oid, pid, job_identifier, status, started_at, finished_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![job.oid, job.pid, job.job_identifier, job.status, job.started_at, job.finished_at],
+        )?;
+        Ok(())
+    }
+
+    fn jobs(&self, pid: Option<&str>, limit: usize) -> super::Result<Vec<JobRecord>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT pid, oid, job_identifier, status, started_at, finished_at FROM jobs
+             WHERE ?1 IS NULL OR pid = ?1 ORDER BY started_at DESC LIMIT ?2",
+        )?;
+        let rows = stmt.query_map(params![pid, limit as i64], |row| {
+            Ok(JobRecord {
+                pid: row.get(0)?,
+                oid: row.get(1)?,
+                job_identifier: row.get(2)?,
+                status: row.get(3)?,
+                started_at: row.get(4)?,
+                finished_at: row.get(5)?,
+            })
+        })?;
+        let mut jobs = Vec::new();
+        for job in rows {
+            jobs.push(job?);
+        }
+        Ok(jobs)
+    }
+
+    fn record_benchmark(&self, benchmark: &BenchmarkRecord) -> super::Result<()> {
+        let stats = serde_json::to_string(&benchmark.stats)?;
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO benchmarks (pid, version, ran_at, stats) VALUES (?1, ?2, ?3, ?4)",
+            params![benchmark.pid, benchmark.version, benchmark.ran_at, stats],
+        )?;
+        Ok(())
+    }
+
+    fn benchmarks(&self, pid: &str, limit: usize) -> super::Result<Vec<BenchmarkRecord>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT pid, version, ran_at, stats FROM benchmarks WHERE pid = ?1 ORDER BY ran_at DESC LIMIT ?2")?;
+        let rows = stmt.query_map(params![pid, limit as i64], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, i64>(2)?, row.get::<_, String>(3)?))
+        })?;
+        let mut benchmarks = Vec::new();
+        for row in rows {
+            let (pid, version, ran_at, stats) = row?;
+            benchmarks.push(BenchmarkRecord {
+                pid: pid,
+                version: version,
+                stats: serde_json::from_str(stats.as_str())?,
+                ran_at: ran_at,
+            });
+        }
+        Ok(benchmarks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A node row saved at schema 1, before stats were keyed by service and model
+    const V1_RECORD: &str = r#"{"pid":"abc","ip_address":"192.168.1.2","version":"0.0.13","port":8050,"jobs":[],"capablities":null,"last_ping":1690000000,"is_online":true,"peer_id":null,"p2p_only":false,"relayed":false,"stats":{"tts_score":900,"llama_7b":12000,"llama_score":12000,"srgan_score":4000,"whisper_stt_base":1500,"whisper_stt_score":1500}}"#;
+
+    fn scratch_db(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("thalamus-{}-{}.db", name, std::process::id()));
+        remove_db(path.to_string_lossy().as_ref());
+        path.to_string_lossy().to_string()
+    }
+
+    fn remove_db(path: &str) {
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path, suffix));
+        }
+    }
+
+    #[test]
+    fn loads_old_node_rows() {
+        let path = scratch_db("sqlite-v1");
+        let store = SqliteStore::open(path.as_str()).unwrap();
+        {
+            let conn = store.conn.lock().unwrap();
+            conn.execute(
+                "INSERT INTO nodes (pid, ip_address, version, port, peer_id, is_online, last_ping, record) VALUES ('abc', '192.168.1.2', '0.0.13', 8050, NULL, 1, 1690000000, ?1)",
+                params![V1_RECORD],
+            ).unwrap();
+            conn.execute("INSERT INTO meta (key, value) VALUES ('schema_version', '1')", []).unwrap();
+        }
+
+        let client = store.load().unwrap().unwrap();
+        assert_eq!(client.schema_version, SCHEMA_VERSION);
+        assert_eq!(client.nodes.len(), 1);
+        assert_eq!(client.nodes[0].stats.median("whisper:base"), Some(1500));
+        assert_eq!(client.nodes[0].stats.median("llama:7B"), Some(12000));
+        assert_eq!(client.nodes[0].stats.score("tts"), Some(900));
+
+        // Migrated rows are written back in the current layout
+        store.write(&client).unwrap();
+        let record: String = store.conn.lock().unwrap().query_row("SELECT record FROM nodes WHERE pid = 'abc'", [], |row| row.get(0)).unwrap();
+        assert!(record.contains("\"whisper:base\""));
+        assert!(!record.contains("whisper_stt_base"));
+
+        std::mem::drop(store);
+        remove_db(path.as_str());
+    }
+
+    #[test]
+    fn reopening_keeps_the_schema() {
+        let path = scratch_db("sqlite-reopen");
+        let store = SqliteStore::open(path.as_str()).unwrap();
+        store.write(&ThalamusClient::new()).unwrap();
+        std::mem::drop(store);
+
+        let store = SqliteStore::open(path.as_str()).unwrap();
+        let version: i64 = store.conn.lock().unwrap().query_row("PRAGMA user_version", [], |row| row.get(0)).unwrap();
+        assert_eq!(version, MIGRATIONS.len() as i64);
+        assert!(store.load().unwrap().unwrap().nodes.is_empty());
+
+        std::mem::drop(store);
+        remove_db(path.as_str());
+    }
+}
@@ -9,4 +9,10 @@
 pub mod http;
 pub mod tools;
 pub mod setup;
-pub mod services;
\ No newline at end of file
+pub mod services;
+pub mod traffic;
+pub mod hardware;
+pub mod metrics;
+pub mod dashboard;
+pub mod jobs;
+pub mod artifacts;
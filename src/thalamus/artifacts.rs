@@ -0,0 +1,261 @@
+// ████████ ██   ██  █████  ██       █████  ███    ███ ██    ██ ███████ 
+//    ██    ██   ██ ██   ██ ██      ██   ██ ████  ████ ██    ██ ██      
+//    ██    ███████ ███████ ██      ███████ ██ ████ ██ ██    ██ ███████ 
+//    ██    ██   ██ ██   ██ ██      ██   ██ ██  ██  ██ ██    ██      ██ 
+//    ██    ██   ██ ██   ██ ███████ ██   ██ ██      ██  ██████  ███████                                                                             
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+
+// Artifact store: /api/artifacts/{id}
+// Job outputs are kept under /opt/thalamus/artifacts, named by the sha256 of their contents
+// so identical outputs are stored once. A sweeper deletes artifacts older than --artifact-ttl
+// and then the oldest ones until the store fits in --artifact-max-size. It also clears
+// scratch files that outlive their jobs: leftovers in /opt/thalamus/tmp, NST's step images
+// in /opt/thalamus/files and job directories the scheduler no longer knows about.
+
+use std::fs::File;
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rouille::Request;
+use rouille::Response;
+use serde::{Serialize, Deserialize};
+
+use crate::thalamus::http::ErrorReply;
+
+pub const ARTIFACTS_DIR: &str = "/opt/thalamus/artifacts";
+
+const SWEEP_INTERVAL: Duration = Duration::from_secs(600);
+
+/// A stored job output
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Artifact {
+    /// sha256 of the contents
+    pub id: String,
+    pub content_type: String,
+    /// Name the job gave the output, for downloads
+    pub filename: String,
+    pub size: u64,
+    pub created_at: i64,
+}
+impl Artifact {
+    pub fn path(&self) -> String {
+        path(self.id.as_str())
+    }
+
+    pub fn url(&self) -> String {
+        format!("/api/artifacts/{}", self.id)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Limits {
+    ttl: Duration,
+    max_size: u64,
+}
+
+static LIMITS: OnceLock<Limits> = OnceLock::new();
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+}
+
+pub fn path(id: &str) -> String {
+    format!("{}/{}", ARTIFACTS_DIR, id)
+}
+
+fn meta_path(id: &str) -> String {
+    format!("{}/{}.json", ARTIFACTS_DIR, id)
+}
+
+// Ids are lowercase sha256 hex; anything else can't name an artifact
+fn valid(id: &str) -> bool {
+    id.len() == 64 && id.chars().all(|c| c.is_ascii_hexdigit() && !c.is_ascii_uppercase())
+}
+
+/// Creates the store and starts the sweeper
+pub fn init(args: &crate::Args) {
+    match std::fs::create_dir_all(ARTIFACTS_DIR) {
+        Ok(_) => {},
+        Err(e) => log::error!("Unable to create {}: {}", ARTIFACTS_DIR, e),
+    }
+    let limits = *LIMITS.get_or_init(|| Limits {
+        ttl: Duration::from_secs(args.artifact_ttl * 3600),
+        max_size: args.artifact_max_size * 1024 * 1024,
+    });
+
+    let spawned = std::thread::Builder::new().name("artifact_sweeper".to_string()).spawn(move || {
+        loop {
+            sweep(limits);
+            std::thread::sleep(SWEEP_INTERVAL);
+        }
+    });
+    match spawned {
+        Ok(_) => {},
+        Err(e) => log::error!("Unable to start the artifact sweeper: {}", e),
+    }
+}
+
+/// Moves a finished job's output into the store
+pub fn put(source: &str, content_type: &str) -> std::io::Result<Artifact> {
+    let id = match crate::thalamus::tools::hash_check(source) {
+        Ok(id) => id,
+        Err(e) => return Err(std::io::Error::new(std::io::ErrorKind::Other, format!("{}", e))),
+    };
+
+    match get(id.as_str()) {
+        Some(artifact) => {
+            std::fs::remove_file(source)?;
+            return Ok(artifact);
+        },
+        None => {}
+    }
+
+    let artifact = Artifact {
+        id: id.to_string(),
+        content_type: content_type.to_string(),
+        filename: std::path::Path::new(source).file_name().map(|f| f.to_string_lossy().to_string()).unwrap_or(id.to_string()),
+        size: std::fs::metadata(source)?.len(),
+        created_at: now(),
+    };
+    match std::fs::rename(source, artifact.path()) {
+        Ok(_) => {},
+        Err(_) => {
+            std::fs::copy(source, artifact.path())?;
+            std::fs::remove_file(source)?;
+        }
+    }
+    let meta = serde_json::to_vec(&artifact).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    crate::storage::json::atomic_write(meta_path(id.as_str()).as_str(), &meta)?;
+    return Ok(artifact);
+}
+
+/// The artifact, if it is still in the store
+pub fn get(id: &str) -> Option<Artifact> {
+    if !valid(id) || !std::path::Path::new(path(id).as_str()).exists() {
+        return None;
+    }
+    let data = std::fs::read_to_string(meta_path(id)).ok()?;
+    return serde_json::from_str(data.as_str()).ok();
+}
+
+/// Every stored artifact
+pub fn list() -> Vec<Artifact> {
+    let entries = match std::fs::read_dir(ARTIFACTS_DIR) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut artifacts = Vec::new();
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        match name.strip_suffix(".json") {
+            Some(id) => match get(id) {
+                Some(artifact) => artifacts.push(artifact),
+                None => {}
+            },
+            None => {}
+        }
+    }
+    return artifacts;
+}
+
+/// An artifact as an http download
+pub fn response(artifact: &Artifact) -> Response {
+    match File::open(artifact.path()) {
+        Ok(file) => Response::from_file(artifact.content_type.to_string(), file)
+            .with_additional_header("ETag", format!("\"{}\"", artifact.id))
+            .with_additional_header("Cache-Control", "public, max-age=31536000, immutable")
+            .with_additional_header("Content-Disposition", format!("inline; filename=\"{}\"", artifact.filename.replace('"', ""))),
+        Err(e) => ErrorReply::gone(format!("artifact {} is gone: {}", artifact.id, e)),
+    }
+}
+
+/// /api/artifacts/{id}
+pub fn handle(request: &Request) -> Result<Response, crate::thalamus::http::Error> {
+    let url = request.url();
+    let id = url.trim_start_matches("/api/artifacts/");
+    match get(id) {
+        Some(artifact) => {
+            if request.header("If-None-Match") == Some(format!("\"{}\"", artifact.id).as_str()) {
+                return Ok(Response::text("").with_status_code(304));
+            }
+            return Ok(response(&artifact));
+        },
+        None => return Ok(ErrorReply::gone(format!("artifact {} expired or never existed", id))),
+    }
+}
+
+/// Stored artifacts and their total size in bytes
+pub fn usage() -> (usize, u64) {
+    let artifacts = list();
+    return (artifacts.len(), artifacts.iter().map(|a| a.size).sum());
+}
+
+fn remove(artifact: &Artifact) {
+    for file in [artifact.path(), meta_path(artifact.id.as_str())] {
+        match std::fs::remove_file(file.as_str()) {
+            Ok(_) => {},
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {},
+            Err(e) => log::error!("Unable to remove {}: {}", file, e),
+        }
+    }
+}
+
+fn sweep(limits: Limits) {
+    let cutoff = now() - limits.ttl.as_secs() as i64;
+
+    // Expired artifacts, then the oldest until the store fits
+    let mut artifacts = list();
+    artifacts.sort_by_key(|a| a.created_at);
+    let mut size: u64 = artifacts.iter().map(|a| a.size).sum();
+    let mut removed = 0;
+    for artifact in artifacts.iter() {
+        if artifact.created_at >= cutoff && size <= limits.max_size {
+            break;
+        }
+        remove(artifact);
+        size = size.saturating_sub(artifact.size);
+        removed += 1;
+    }
+    if removed > 0 {
+        log::info!("Swept {} artifacts, {} bytes left", removed, size);
+    }
+
+    // Scratch files left behind by crashes and by older versions
+    sweep_dir("/opt/thalamus/tmp", cutoff, &|name| name != "srgan");
+    sweep_dir("/opt/thalamus/tmp/srgan", cutoff, &|_| true);
+    sweep_dir("/opt/thalamus/files", cutoff, &|name| name.starts_with("out") && name.ends_with(".jpg"));
+
+    // Job directories of jobs the scheduler has finished or forgotten
+    let active = crate::thalamus::jobs::active();
+    sweep_dir(crate::thalamus::jobs::JOBS_DIR, cutoff, &|name| !active.contains(name) && !name.starts_with("queue.json"));
+}
+
+// Removes entries of a directory older than the cutoff that match the filter
+fn sweep_dir(dir: &str, cutoff: i64, filter: &dyn Fn(&str) -> bool) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !filter(name.as_str()) {
+            continue;
+        }
+        let modified = match entry.metadata().and_then(|m| m.modified()) {
+            Ok(modified) => modified.duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0),
+            Err(_) => continue,
+        };
+        if modified >= cutoff {
+            continue;
+        }
+        let path = entry.path();
+        let removed = if path.is_dir() { std::fs::remove_dir_all(&path) } else { std::fs::remove_file(&path) };
+        match removed {
+            Ok(_) => log::info!("Swept {}", path.display()),
+            Err(e) => log::error!("Unable to remove {}: {}", path.display(), e),
+        }
+    }
+}
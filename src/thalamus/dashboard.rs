@@ -0,0 +1,247 @@
+// ████████ ██   ██  █████  ██       █████  ███    ███ ██    ██ ███████ 
+//    ██    ██   ██ ██   ██ ██      ██   ██ ████  ████ ██    ██ ██      
+//    ██    ███████ ███████ ██      ███████ ██ ████ ██ ██    ██ ███████ 
+//    ██    ██   ██ ██   ██ ██      ██   ██ ██  ██  ██ ██    ██      ██ 
+//    ██    ██   ██ ██   ██ ███████ ██   ██ ██      ██  ██████  ███████                                                                             
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+
+// Web dashboard: /
+// Node and mesh views plus a playground for every service, served from assets compiled
+// into the binary. Playground calls to peers go through /api/mesh/{pid}/api/services/...
+
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use rouille::input::post::BufferedFile;
+use rouille::post_input;
+use rouille::Request;
+use rouille::Response;
+use serde::{Serialize, Deserialize};
+
+use crate::p2p::infer::{InferRequest, InferResponse};
+use crate::thalamus::http::ErrorReply;
+use crate::thalamus::services::llama::LlamaModel;
+use crate::thalamus::services::whisper::{WhisperFormat, WhisperModel};
+
+const VERSION: Option<&'static str> = option_env!("CARGO_PKG_VERSION");
+
+const INDEX_HTML: &str = include_str!("dashboard/index.html");
+const APP_JS: &str = include_str!("dashboard/app.js");
+const STYLE_CSS: &str = include_str!("dashboard/style.css");
+
+/// Serves the dashboard page and its assets
+pub fn asset(url: &str) -> Option<Response> {
+    match url {
+        "/" | "/dashboard" => Some(Response::html(INDEX_HTML)),
+        "/dashboard/app.js" => Some(Response::from_data("application/javascript; charset=utf-8", APP_JS)),
+        "/dashboard/style.css" => Some(Response::from_data("text/css; charset=utf-8", STYLE_CSS)),
+        _ => None,
+    }
+}
+
+/// Everything the node view shows: /api/thalamus/node
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NodeReply {
+    pub pid: String,
+    pub version: String,
+    /// Installed models, whether or not this machine can hold them
+    pub models: Vec<String>,
+    /// What the node offers the mesh
+    pub capabilities: Vec<String>,
+    pub hardware: crate::thalamus::hardware::Hardware,
+    pub jobs: Vec<crate::ThalamusNodeJob>,
+    /// Scheduler jobs, unfinished first
+    pub queue: Vec<crate::thalamus::jobs::Job>,
+    pub traffic: BTreeMap<String, crate::thalamus::traffic::RollingStats>,
+}
+
+pub fn node(thalamus: Arc<Mutex<crate::ThalamusClient>>) -> Result<NodeReply, crate::thalamus::http::Error> {
+    let pid = std::fs::read_to_string("/opt/thalamus/pid")?;
+
+    let mut models: Vec<String> = Vec::new();
+    for model in WhisperModel::ALL {
+        if model.is_installed() {
+            models.push(format!("whisper:{}", model));
+        }
+    }
+    for model in LlamaModel::ALL {
+        if model.is_installed() {
+            models.push(format!("llama:{}", model));
+        }
+    }
+
+    let thalamus_x = thalamus.lock().unwrap();
+    let jobs = match thalamus_x.nodes.iter().find(|n| n.pid == pid) {
+        Some(node) => node.jobs.clone(),
+        None => Vec::new(),
+    };
+    std::mem::drop(thalamus_x);
+
+    return Ok(NodeReply {
+        pid: pid,
+        version: VERSION.unwrap_or("UNKNOWN").to_string(),
+        models: models,
+        capabilities: crate::ThalamusNodeCapability::local().into_iter().map(|c| c.tag).collect(),
+        hardware: crate::thalamus::hardware::Hardware::local(),
+        jobs: jobs,
+        queue: crate::thalamus::jobs::list(),
+        traffic: crate::thalamus::traffic::snapshot(),
+    });
+}
+
+/// Forwards a playground call to a peer: /api/mesh/{pid}/api/services/...
+pub fn forward(request: &Request, thalamus: Arc<Mutex<crate::ThalamusClient>>) -> Result<Response, crate::thalamus::http::Error> {
+    let url = request.url();
+    let (pid, path) = match url.trim_start_matches("/api/mesh/").split_once('/') {
+        Some((pid, path)) => (pid.to_string(), format!("/{}", path)),
+        None => return Ok(Response::empty_404()),
+    };
+    if !path.starts_with("/api/services/") {
+        return Ok(ErrorReply::bad_request(format!("only /api/services calls can be forwarded")));
+    }
+
+    let thalamus_x = thalamus.lock().unwrap();
+    let node = thalamus_x.nodes.iter().find(|n| n.pid == pid).cloned();
+    std::mem::drop(thalamus_x);
+    let node = match node {
+        Some(node) => node,
+        None => return Ok(Response::empty_404()),
+    };
+
+    return match node.via_p2p() {
+        Some(peer_id) => forward_p2p(request, peer_id.as_str(), path.as_str()),
+        None => forward_http(request, &node, path.as_str()),
+    };
+}
+
+// Relays the request as-is, body and content type included
+fn forward_http(request: &Request, node: &crate::ThalamusNode, path: &str) -> Result<Response, crate::thalamus::http::Error> {
+    let mut body = Vec::new();
+    match request.data() {
+        Some(mut data) => { data.read_to_end(&mut body)?; },
+        None => {}
+    }
+
+    let mut url = format!("http://{}:{}{}", node.ip_address, node.port, path);
+    if !request.raw_query_string().is_empty() {
+        url = format!("{}?{}", url, request.raw_query_string());
+    }
+
+    let client = match reqwest::blocking::Client::builder().timeout(crate::client::DEFAULT_TIMEOUT).build() {
+        Ok(client) => client,
+        Err(e) => return Ok(ErrorReply::internal(format!("{}", e))),
+    };
+    let span = crate::trace::client(format!("{} {}", request.method(), path), node.pid.as_str());
+    let mut builder = match request.method() {
+        "GET" => client.get(url),
+        _ => client.post(url),
+    };
+    builder = builder.header("traceparent", span.traceparent()).body(body);
+    match request.header("Content-Type") {
+        Some(content_type) => { builder = builder.header("Content-Type", content_type); },
+        None => {}
+    }
+
+    let response = match builder.send() {
+        Ok(response) => response,
+        Err(e) => return Ok(ErrorReply::internal(format!("node {} is unreachable: {}", node.pid, e))),
+    };
+    let status = response.status().as_u16();
+    let content_type = match response.headers().get("Content-Type").and_then(|v| v.to_str().ok()) {
+        Some(content_type) => content_type.to_string(),
+        None => format!("application/octet-stream"),
+    };
+    let bytes = match response.bytes() {
+        Ok(bytes) => bytes,
+        Err(e) => return Ok(ErrorReply::internal(format!("{}", e))),
+    };
+    return Ok(Response::from_data(content_type, bytes.to_vec()).with_status_code(status));
+}
+
+// Peers without a reachable address get the same job as an inference request
+fn forward_p2p(request: &Request, peer_id: &str, path: &str) -> Result<Response, crate::thalamus::http::Error> {
+    let mut content_type = format!("application/octet-stream");
+
+    let infer_request = match path {
+        "/api/services/whisper" => {
+            let input = post_input!(request, {
+                speech: BufferedFile,
+                method: String,
+                language: Option<String>,
+                format: Option<String>
+            })?;
+            let model = match input.method.parse::<WhisperModel>() {
+                Ok(model) => model,
+                Err(e) => return Ok(ErrorReply::bad_request(e)),
+            };
+            let format = match input.format {
+                Some(format) => match format.parse::<WhisperFormat>() {
+                    Ok(format) => format,
+                    Err(e) => return Ok(ErrorReply::bad_request(e)),
+                },
+                None => WhisperFormat::Txt,
+            };
+            InferRequest::Whisper { model: model, language: input.language, format: format, speech: input.speech.data }
+        },
+        "/api/services/whisper/vwav" => {
+            let input = post_input!(request, {
+                speech: BufferedFile,
+                method: String,
+                language: Option<String>
+            })?;
+            let model = match input.method.parse::<WhisperModel>() {
+                Ok(model) => model,
+                Err(e) => return Ok(ErrorReply::bad_request(e)),
+            };
+            content_type = format!("video/mp4");
+            InferRequest::WhisperVwav { model: model, language: input.language, speech: input.speech.data }
+        },
+        "/api/services/llama" => {
+            let input = post_input!(request, {
+                prompt: String,
+                model: String,
+            })?;
+            let model = match input.model.parse::<LlamaModel>() {
+                Ok(model) => model,
+                Err(e) => return Ok(ErrorReply::bad_request(e)),
+            };
+            InferRequest::Llama { model: model, prompt: input.prompt }
+        },
+        "/api/services/tts" => {
+            let text = request.get_param("text").unwrap_or_default();
+            let primary = request.get_param("primary").unwrap_or(format!("coqui-tts:en_ljspeech"));
+            let fallback = request.get_param("fallback").unwrap_or(format!("opensamfoundation"));
+            content_type = format!("audio/wav");
+            InferRequest::Tts { text: text, primary: primary, fallback: fallback }
+        },
+        "/api/services/image/srgan" => {
+            let input = post_input!(request, {
+                input_file: BufferedFile,
+            })?;
+            let filename = input.input_file.filename.unwrap_or(format!("image.jpg"));
+            content_type = crate::thalamus::tools::find_mimetype(&filename);
+            InferRequest::Srgan { filename: filename, image: input.input_file.data }
+        },
+        "/api/services/image/yolo/v7" => {
+            let input = post_input!(request, {
+                image_file: BufferedFile,
+            })?;
+            InferRequest::Yolov7 { image: input.image_file.data }
+        },
+        _ => return Ok(ErrorReply::bad_request(format!("{} can't be forwarded over p2p", path))),
+    };
+
+    return match crate::p2p::infer(peer_id, infer_request, crate::client::DEFAULT_TIMEOUT) {
+        Ok(InferResponse::Json(json)) => Ok(Response::from_data("application/json", json)),
+        Ok(InferResponse::Text(text)) => Ok(Response::text(text)),
+        Ok(InferResponse::Bytes(bytes)) => Ok(Response::from_data(content_type, bytes)),
+        Ok(InferResponse::Error(e)) => Ok(ErrorReply::internal(e)),
+        Ok(InferResponse::NotCapable(e)) => Ok(ErrorReply::not_capable(e)),
+        Err(e) => Ok(ErrorReply::internal(format!("{}", e))),
+    };
+}
@@ -0,0 +1,280 @@
+// Thalamus dashboard
+// Polls the local node for its own state and the mesh, and runs playground jobs against
+// the local node or, through /api/mesh/{pid}, any peer it knows about.
+
+const REFRESH_MS = 5000;
+
+let local = null;
+let peers = [];
+
+function el(tag, attrs, children) {
+    const node = document.createElement(tag);
+    for (const [key, value] of Object.entries(attrs || {})) {
+        if (key === "class") {
+            node.className = value;
+        } else {
+            node.setAttribute(key, value);
+        }
+    }
+    for (const child of [].concat(children || [])) {
+        node.append(child instanceof Node ? child : document.createTextNode(String(child)));
+    }
+    return node;
+}
+
+function table(target, headers, rows) {
+    const body = rows.length
+        ? rows.map((row) => el("tr", {}, row.map((cell) => el("td", {}, cell))))
+        : [el("tr", {}, el("td", { class: "muted", colspan: headers.length }, "none"))];
+    target.replaceChildren(el("tr", {}, headers.map((h) => el("th", {}, h))), ...body);
+}
+
+function list(target, items) {
+    target.replaceChildren(...(items.length ? items.map((i) => el("li", {}, i)) : [el("li", { class: "muted" }, "none")]));
+}
+
+function bytes(value) {
+    if (value === null || value === undefined) {
+        return "?";
+    }
+    const units = ["B", "KB", "MB", "GB", "TB"];
+    let i = 0;
+    while (value >= 1024 && i < units.length - 1) {
+        value /= 1024;
+        i++;
+    }
+    return value.toFixed(i ? 1 : 0) + " " + units[i];
+}
+
+function ago(seconds) {
+    if (!seconds) {
+        return "never";
+    }
+    const delta = Math.max(0, Math.floor(Date.now() / 1000 - seconds));
+    if (delta < 60) return delta + "s ago";
+    if (delta < 3600) return Math.floor(delta / 60) + "m ago";
+    if (delta < 86400) return Math.floor(delta / 3600) + "h ago";
+    return Math.floor(delta / 86400) + "d ago";
+}
+
+async function json(url) {
+    const response = await fetch(url);
+    if (!response.ok) {
+        throw new Error(url + ": " + response.status);
+    }
+    return response.json();
+}
+
+function renderNode(node) {
+    document.getElementById("identity").textContent = node.pid + " · v" + node.version;
+    list(document.getElementById("models"), node.models);
+    list(document.getElementById("capabilities"), node.capabilities);
+
+    const hw = node.hardware;
+    table(document.getElementById("hardware"), ["", ""], [
+        ["CPU", (hw.cpu_model || "?") + " (" + hw.cores + " cores)"],
+        ["Flags", hw.cpu_flags.join(" ") || "-"],
+        ["Memory", bytes(hw.memory_free) + " free of " + bytes(hw.memory_total)],
+        ["Disk", bytes(hw.disk_free) + " free of " + bytes(hw.disk_total)],
+        ["OS", hw.os + " " + (hw.os_version || "") + " " + hw.arch],
+        ["CUDA", hw.cuda ? hw.cuda_devices + " device(s)" : "no"],
+    ]);
+
+    table(document.getElementById("queue"), ["Job", "Id", "Status", "Position", "Priority", "Queued", "Started", "Output"],
+        node.queue.map((job) => [
+            job.key,
+            job.oid,
+            job.status,
+            job.position || "-",
+            job.priority,
+            ago(job.created_at),
+            ago(job.started_at),
+            job.url ? el("a", { href: job.url, target: "_blank" }, "download") : "-",
+        ]));
+
+    table(document.getElementById("jobs"), ["Job", "Id", "Status", "Progress", "Started"],
+        node.jobs.map((job) => [
+            job.job_identifier,
+            job.oid,
+            job.status || "running",
+            job.progress === null || job.progress === undefined ? "-" : Math.round(job.progress * 100) + "%",
+            ago(job.started_at),
+        ]));
+
+    table(document.getElementById("traffic"), ["Service", "In flight", "Latency", "Per minute", "Errors", "Requests"],
+        Object.entries(node.traffic).map(([key, stats]) => [
+            key,
+            stats.queue_depth,
+            stats.latency_ms === null ? "-" : Math.round(stats.latency_ms) + " ms",
+            stats.throughput.toFixed(1),
+            (stats.error_rate * 100).toFixed(1) + "%",
+            stats.requests,
+        ]));
+}
+
+function renderMesh(nodes) {
+    table(document.getElementById("peers"), ["Node", "Address", "Version", "State", "Route", "Jobs", "Scores", "Hardware", "Benchmarked"],
+        nodes.map((node) => {
+            const route = node.p2p_only ? (node.relayed ? "relay" : "p2p") : "http";
+            const scores = Object.entries(node.stats.scores).map(([service, score]) => service + " " + score + "ms").join(", ");
+            const hw = node.hardware ? (node.hardware.cores + " cores, " + bytes(node.hardware.memory_total)) : "-";
+            const running = node.jobs.filter((job) => job.status === "running").length;
+            const queued = node.jobs.filter((job) => job.status === "queued").length;
+            return [
+                node.pid + (local && node.pid === local.pid ? " (this node)" : ""),
+                node.ip_address + ":" + node.port,
+                node.version,
+                el("span", { class: node.is_online ? "online" : "offline" }, node.is_online ? "online" : "offline"),
+                route,
+                running + " running, " + queued + " queued",
+                scores || "-",
+                hw,
+                ago(node.stats.benchmarked_at),
+            ];
+        }));
+}
+
+function renderTargets() {
+    const select = document.getElementById("target");
+    const selected = select.value;
+    const options = [el("option", { value: "" }, "this node")];
+    for (const node of peers) {
+        if (local && node.pid === local.pid) {
+            continue;
+        }
+        options.push(el("option", { value: node.pid }, node.pid + " (" + node.ip_address + (node.is_online ? "" : ", offline") + ")"));
+    }
+    select.replaceChildren(...options);
+    select.value = selected;
+    renderModels();
+}
+
+// Model pickers follow the selected node's capabilities
+function renderModels() {
+    const pid = document.getElementById("target").value;
+    let capabilities = local ? local.capabilities : [];
+    if (pid) {
+        const node = peers.find((n) => n.pid === pid);
+        capabilities = node && node.capablities ? node.capablities.map((c) => c.tag) : [];
+    }
+    for (const [service, cls] of [["whisper", "whisper-models"], ["llama", "llama-models"]]) {
+        const models = capabilities.filter((c) => c.startsWith(service + ":")).map((c) => c.split(":")[1]);
+        for (const select of document.getElementsByClassName(cls)) {
+            const selected = select.value;
+            select.replaceChildren(...models.map((m) => el("option", {}, m)));
+            if (models.includes(selected)) {
+                select.value = selected;
+            }
+        }
+    }
+}
+
+async function refresh() {
+    try {
+        local = await json("/api/thalamus/node");
+        renderNode(local);
+    } catch (e) {
+        console.error(e);
+    }
+    try {
+        peers = await json("/api/nodex");
+        renderMesh(peers);
+        renderTargets();
+    } catch (e) {
+        console.error(e);
+    }
+}
+
+async function loadVoices() {
+    try {
+        const voices = await json("/api/services/tts/voices");
+        document.getElementById("voices").replaceChildren(...voices.map((v) => el("option", { value: v.tag }, v.tag + " (" + v.locale + ", " + v.gender + ")")));
+    } catch (e) {
+        console.error(e);
+    }
+}
+
+async function showResult(target, response) {
+    const type = response.headers.get("Content-Type") || "";
+    if (!response.ok) {
+        let message = response.status + " " + response.statusText;
+        try {
+            message = (await response.json()).error;
+        } catch (e) {}
+        target.replaceChildren(el("span", { class: "error" }, message));
+        return;
+    }
+    if (type.startsWith("application/json")) {
+        target.replaceChildren(JSON.stringify(await response.json(), null, 2));
+    } else if (type.startsWith("text/")) {
+        target.replaceChildren(await response.text());
+    } else {
+        const url = URL.createObjectURL(await response.blob());
+        if (type.startsWith("audio/")) {
+            target.replaceChildren(el("audio", { src: url, controls: "" }));
+        } else if (type.startsWith("video/")) {
+            target.replaceChildren(el("video", { src: url, controls: "" }));
+        } else if (type.startsWith("image/")) {
+            target.replaceChildren(el("img", { src: url }));
+        } else {
+            target.replaceChildren(el("a", { href: url, download: "result" }, "download result"));
+        }
+    }
+}
+
+async function submit(form) {
+    const pid = document.getElementById("target").value;
+    const base = pid ? "/api/mesh/" + encodeURIComponent(pid) : "";
+    const data = new FormData(form);
+    let path = form.dataset.path;
+    let request = { method: "POST", body: data };
+
+    if (form.dataset.service === "whisper" && data.get("vwav")) {
+        path += "/vwav";
+        data.delete("format");
+    }
+    data.delete("vwav");
+
+    // The tts route reads its fields from the query string
+    if (form.dataset.service === "tts") {
+        path += "?" + new URLSearchParams(data).toString();
+        request = { method: "POST" };
+    }
+
+    const result = form.querySelector(".result");
+    const button = form.querySelector("button");
+    result.replaceChildren(el("span", { class: "muted" }, "running..."));
+    button.disabled = true;
+    const started = performance.now();
+    try {
+        await showResult(result, await fetch(base + path, request));
+        result.append(el("div", { class: "muted" }, ((performance.now() - started) / 1000).toFixed(1) + "s"));
+    } catch (e) {
+        result.replaceChildren(el("span", { class: "error" }, String(e)));
+    } finally {
+        button.disabled = false;
+    }
+}
+
+function show(view) {
+    for (const section of document.getElementsByClassName("view")) {
+        section.classList.toggle("active", section.id === view);
+    }
+    for (const link of document.querySelectorAll("nav a")) {
+        link.classList.toggle("active", link.dataset.view === view);
+    }
+}
+
+window.addEventListener("hashchange", () => show(location.hash.slice(1) || "node"));
+document.getElementById("target").addEventListener("change", renderModels);
+for (const form of document.querySelectorAll("form[data-service]")) {
+    form.addEventListener("submit", (event) => {
+        event.preventDefault();
+        submit(form);
+    });
+}
+
+show(location.hash.slice(1) || "node");
+loadVoices();
+refresh();
+setInterval(refresh, REFRESH_MS);
@@ -0,0 +1,225 @@
+// ████████ ██   ██  █████  ██       █████  ███    ███ ██    ██ ███████ 
+//    ██    ██   ██ ██   ██ ██      ██   ██ ████  ████ ██    ██ ██      
+//    ██    ███████ ███████ ██      ███████ ██ ████ ██ ██    ██ ███████ 
+//    ██    ██   ██ ██   ██ ██      ██   ██ ██  ██  ██ ██    ██      ██ 
+//    ██    ██   ██ ██   ██ ███████ ██   ██ ██      ██  ██████  ███████                                                                             
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+
+// Hardware inventory: /api/thalamus/hardware
+// Used to keep big models off nodes that can't hold them, both when this node admits a
+// request and when a client picks a node.
+
+use std::ffi::CString;
+
+use serde::{Serialize, Deserialize};
+
+use crate::{LlamaModel, WhisperModel};
+
+pub const MODELS_DIR: &str = "/opt/thalamus/models";
+
+const GB: u64 = 1024 * 1024 * 1024;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Hardware {
+    pub cpu_model: Option<String>,
+    /// Logical cores
+    pub cores: usize,
+    /// Instruction set extensions the models care about, e.g. avx2, fma, neon
+    pub cpu_flags: Vec<String>,
+    /// Bytes
+    pub memory_total: Option<u64>,
+    pub memory_free: Option<u64>,
+    /// Bytes on the filesystem holding the models dir
+    pub disk_total: Option<u64>,
+    pub disk_free: Option<u64>,
+    pub os: String,
+    pub os_version: Option<String>,
+    pub arch: String,
+    /// Whether libtorch sees a CUDA device
+    pub cuda: bool,
+    pub cuda_devices: i64,
+}
+impl Hardware {
+    /// Inventory of this machine
+    pub fn local() -> Hardware {
+        let (memory_total, memory_free) = memory();
+        let (disk_total, disk_free) = disk(MODELS_DIR);
+        Hardware {
+            cpu_model: cpu_model(),
+            cores: std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            cpu_flags: cpu_flags(),
+            memory_total: memory_total,
+            memory_free: memory_free,
+            disk_total: disk_total,
+            disk_free: disk_free,
+            os: std::env::consts::OS.to_string(),
+            os_version: os_version(),
+            arch: std::env::consts::ARCH.to_string(),
+            cuda: tch::Cuda::is_available(),
+            cuda_devices: tch::Cuda::device_count(),
+        }
+    }
+
+    /// Whether the machine could ever hold the model behind a capability
+    pub fn fits(&self, capability: &str) -> bool {
+        match (required_memory(capability), self.memory_total) {
+            (Some(required), Some(total)) => total >= required,
+            _ => true,
+        }
+    }
+
+    /// Whether there is room to run it right now
+    pub fn admits(&self, capability: &str) -> bool {
+        match (required_memory(capability), self.memory_free) {
+            (Some(required), Some(free)) => free >= required,
+            _ => true,
+        }
+    }
+}
+
+/// Rough resident memory of a model, in bytes, for capability tags like "llama:30B"
+pub fn required_memory(capability: &str) -> Option<u64> {
+    let (service, model) = capability.split_once(':')?;
+    match service {
+        "llama" => match model.parse::<LlamaModel>().ok()? {
+            LlamaModel::Llama7B => Some(4 * GB),
+            LlamaModel::Llama13B => Some(8 * GB),
+            LlamaModel::Llama30B => Some(20 * GB),
+            LlamaModel::Llama65B => Some(40 * GB),
+        },
+        "whisper" | "whisper_vwav" => match model.parse::<WhisperModel>().ok()? {
+            WhisperModel::Tiny => Some(GB / 2),
+            WhisperModel::Base => Some(GB / 2),
+            WhisperModel::Medium => Some(2 * GB),
+            WhisperModel::Large => Some(4 * GB),
+        },
+        _ => None,
+    }
+}
+
+/// Turns a request away when there isn't enough free memory for the model
+pub fn admission(capability: &str) -> Result<(), String> {
+    let hardware = Hardware { memory_free: memory().1, ..Hardware::default() };
+    if hardware.admits(capability) {
+        return Ok(());
+    }
+    let required = required_memory(capability).unwrap_or(0);
+    return Err(format!(
+        "{} needs {:.1} GB of free memory, {:.1} GB available",
+        capability,
+        required as f64 / GB as f64,
+        hardware.memory_free.unwrap_or(0) as f64 / GB as f64,
+    ));
+}
+
+#[cfg(target_os = "linux")]
+fn cpu_model() -> Option<String> {
+    let cpuinfo = std::fs::read_to_string("/proc/cpuinfo").ok()?;
+    for line in cpuinfo.lines() {
+        // "model name" on x86, "Model" on raspberry pi style arm boards
+        match line.split_once(':') {
+            Some((key, value)) if key.trim() == "model name" || key.trim() == "Model" => return Some(value.trim().to_string()),
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(target_os = "macos")]
+fn cpu_model() -> Option<String> {
+    sysctl("machdep.cpu.brand_string")
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn cpu_model() -> Option<String> {
+    None
+}
+
+fn cpu_flags() -> Vec<String> {
+    let mut flags: Vec<String> = Vec::new();
+    #[cfg(target_arch = "x86_64")] {
+        if is_x86_feature_detected!("sse4.2") { flags.push(format!("sse4_2")); }
+        if is_x86_feature_detected!("avx") { flags.push(format!("avx")); }
+        if is_x86_feature_detected!("avx2") { flags.push(format!("avx2")); }
+        if is_x86_feature_detected!("fma") { flags.push(format!("fma")); }
+        if is_x86_feature_detected!("f16c") { flags.push(format!("f16c")); }
+        if is_x86_feature_detected!("avx512f") { flags.push(format!("avx512f")); }
+    }
+    #[cfg(target_arch = "aarch64")] {
+        if std::arch::is_aarch64_feature_detected!("neon") { flags.push(format!("neon")); }
+        if std::arch::is_aarch64_feature_detected!("fp16") { flags.push(format!("fp16")); }
+        if std::arch::is_aarch64_feature_detected!("dotprod") { flags.push(format!("dotprod")); }
+    }
+    flags
+}
+
+/// (total, free) memory in bytes
+#[cfg(target_os = "linux")]
+pub fn memory() -> (Option<u64>, Option<u64>) {
+    let meminfo = match std::fs::read_to_string("/proc/meminfo") {
+        Ok(meminfo) => meminfo,
+        Err(_) => return (None, None),
+    };
+    let field = |name: &str| -> Option<u64> {
+        let line = meminfo.lines().find(|line| line.starts_with(name))?;
+        let kb = line.split_whitespace().nth(1)?.parse::<u64>().ok()?;
+        Some(kb * 1024)
+    };
+    (field("MemTotal:"), field("MemAvailable:"))
+}
+
+#[cfg(target_os = "macos")]
+pub fn memory() -> (Option<u64>, Option<u64>) {
+    let total = sysctl("hw.memsize").and_then(|m| m.parse::<u64>().ok());
+    let page_size = sysctl("hw.pagesize").and_then(|p| p.parse::<u64>().ok()).unwrap_or(4096);
+    let free_pages = sysctl("vm.page_free_count").and_then(|p| p.parse::<u64>().ok());
+    (total, free_pages.map(|pages| pages * page_size))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn memory() -> (Option<u64>, Option<u64>) {
+    (None, None)
+}
+
+// (total, free) in bytes for the filesystem holding path
+fn disk(path: &str) -> (Option<u64>, Option<u64>) {
+    let path = match CString::new(path) {
+        Ok(path) => path,
+        Err(_) => return (None, None),
+    };
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return (None, None);
+    }
+    let block_size = stat.f_frsize as u64;
+    (Some(stat.f_blocks as u64 * block_size), Some(stat.f_bavail as u64 * block_size))
+}
+
+#[cfg(target_os = "linux")]
+fn os_version() -> Option<String> {
+    let release = std::fs::read_to_string("/etc/os-release").ok()?;
+    let line = release.lines().find(|line| line.starts_with("PRETTY_NAME="))?;
+    Some(line.trim_start_matches("PRETTY_NAME=").trim_matches('"').to_string())
+}
+
+#[cfg(target_os = "macos")]
+fn os_version() -> Option<String> {
+    sysctl("kern.osproductversion")
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn os_version() -> Option<String> {
+    None
+}
+
+#[cfg(target_os = "macos")]
+fn sysctl(name: &str) -> Option<String> {
+    let output = std::process::Command::new("sysctl").arg("-n").arg(name).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
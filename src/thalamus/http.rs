@@ -40,26 +40,171 @@ pub struct VersionHeader {
     pub pid: String,
 }
 
+/// Body of every failed API call
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ErrorReply {
+    pub error: String,
+    /// bad_request, not_capable, conflict, gone or internal
+    pub kind: String,
+}
+impl ErrorReply {
+    fn response(kind: &str, error: String, status: u16) -> Response {
+        log::warn!("{}: {}", kind, error);
+        Response::json(&ErrorReply{ error: error, kind: kind.to_string() }).with_status_code(status)
+    }
+
+    pub fn bad_request(error: String) -> Response {
+        ErrorReply::response("bad_request", error, 400)
+    }
+
+    /// The node doesn't have the model or service installed
+    pub fn not_capable(error: String) -> Response {
+        ErrorReply::response("not_capable", error, 422)
+    }
+
+    /// The request clashes with work already in progress
+    pub fn conflict(error: String) -> Response {
+        ErrorReply::response("conflict", error, 409)
+    }
+
+    /// The thing asked for existed once but has expired
+    pub fn gone(error: String) -> Response {
+        ErrorReply::response("gone", error, 410)
+    }
+
+    pub fn internal(error: String) -> Response {
+        ErrorReply::response("internal", error, 500)
+    }
+}
+
 
 pub fn handle(request: &Request, thalamus: Arc<Mutex<crate::ThalamusClient>>) -> Result<Response> {
+    let mut span = crate::trace::accept(request.header("traceparent"), format!("{} {}", request.method(), crate::thalamus::metrics::route(request.url().as_str())));
+    span.attr("http.method", request.method());
+    span.attr("http.target", request.url());
+    match crate::logging::current() {
+        Some(request_id) => span.attr("request.id", request_id),
+        None => {}
+    }
+
+    let response = route(request, thalamus);
+    match &response {
+        Ok(response) => {
+            span.attr("http.status_code", response.status_code);
+            if response.status_code >= 500 {
+                span.error(format!("status {}", response.status_code));
+            }
+        },
+        Err(e) => span.error(e),
+    }
+    return response;
+}
+
+fn route(request: &Request, thalamus: Arc<Mutex<crate::ThalamusClient>>) -> Result<Response> {
+
+    // Playground calls to peers, before the local service routes below can match them
+    if request.url().starts_with("/api/mesh/"){
+        return crate::thalamus::dashboard::forward(request, Arc::clone(&thalamus));
+    }
+
+    match crate::thalamus::dashboard::asset(request.url().as_str()) {
+        Some(response) => return Ok(response),
+        None => {}
+    }
 
     if request.url().contains("/api/thalamus/version"){
         let pid = std::fs::read_to_string("/opt/thalamus/pid").expect("Unable to read file");
         return Ok(Response::json(&VersionHeader{version: VERSION.ok_or("UNKNOWN")?.to_string(), pid: pid}));
     }
 
+    if request.url() == "/metrics" {
+        return Ok(Response::from_data("text/plain; version=0.0.4", crate::thalamus::metrics::render(Arc::clone(&thalamus))));
+    }
+
+    if request.url() == "/api/thalamus/node" {
+        return Ok(Response::json(&crate::thalamus::dashboard::node(Arc::clone(&thalamus))?));
+    }
+
+    if request.url().contains("/api/thalamus/hardware"){
+        return Ok(Response::json(&crate::thalamus::hardware::Hardware::local()));
+    }
+
     if request.url().contains("/api/services/image"){
         return Ok(crate::thalamus::services::image::handle(request)?);
     }
 
+    // /api/nodex/{pid}/benchmark and /api/nodex/{pid}/stats
+    if request.url().starts_with("/api/nodex/"){
+        let url = request.url();
+        let parts: Vec<&str> = url.trim_start_matches("/api/nodex/").split('/').collect();
+        if parts.len() != 2 {
+            return Ok(Response::empty_404());
+        }
+        let pid = parts[0];
+
+        let thalamus_x = thalamus.lock().unwrap();
+        let node = thalamus_x.nodes.iter().find(|n| n.pid == pid).cloned();
+        std::mem::drop(thalamus_x);
+        let node = match node {
+            Some(node) => node,
+            None => return Ok(Response::empty_404()),
+        };
+
+        if parts[1] == "benchmark" && request.method() == "POST" {
+            return match crate::calc_stats(Arc::clone(&thalamus), node.pid.to_string(), node.version.to_string(), node.ip_address.to_string(), node.port) {
+                Some(job) => Ok(Response::json(&job).with_status_code(202)),
+                None => Ok(ErrorReply::conflict(format!("node {} is already being benchmarked", node.pid))),
+            };
+        }
+
+        if parts[1] == "stats" && request.method() == "GET" {
+            let limit = match request.get_param("limit") {
+                Some(limit) => match limit.parse::<usize>() {
+                    Ok(limit) => limit,
+                    Err(e) => return Ok(ErrorReply::bad_request(format!("bad limit: {}", e))),
+                },
+                None => 50,
+            };
+            let history = match crate::storage::store().benchmarks(node.pid.as_str(), limit) {
+                Ok(history) => history,
+                Err(e) => return Ok(ErrorReply::internal(format!("{}", e))),
+            };
+            return Ok(Response::json(&crate::bench::StatsReply {
+                pid: node.pid.to_string(),
+                stats: node.stats.clone(),
+                trend: crate::bench::trends(&history),
+                history: history,
+            }));
+        }
+
+        return Ok(Response::empty_404());
+    }
+
     if request.url().contains("/api/nodex"){
         let thalamus_x = thalamus.lock().unwrap();
-        let thx_clone = thalamus_x.clone();
+        let mut thx_clone = thalamus_x.clone();
         std::mem::drop(thalamus_x);
+
+        // Publish our live traffic stats and jobs on our own record; peers' jobs come from their announcements
+        let pid = std::fs::read_to_string("/opt/thalamus/pid")?;
+        for node in &mut thx_clone.nodes {
+            if node.pid == pid {
+                node.traffic = crate::thalamus::traffic::snapshot();
+                node.jobs.extend(crate::thalamus::jobs::node_jobs());
+            }
+        }
         
         return Ok(Response::json(&thx_clone.nodes));
     }
 
+    if request.url().starts_with("/api/artifacts/"){
+        return crate::thalamus::artifacts::handle(request);
+    }
+
+    if request.url() == "/api/jobs" || request.url().starts_with("/api/jobs/"){
+        return crate::thalamus::jobs::handle(request, Arc::clone(&thalamus));
+    }
+
     if request.url().contains("/api/services/llama"){
         return Ok(crate::thalamus::services::llama::handle(request)?);
     }
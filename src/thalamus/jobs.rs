@@ -0,0 +1,1197 @@
+// ████████ ██   ██  █████  ██       █████  ███    ███ ██    ██ ███████ 
+//    ██    ██   ██ ██   ██ ██      ██   ██ ████  ████ ██    ██ ██      
+//    ██    ███████ ███████ ██      ███████ ██ ████ ██ ██    ██ ███████ 
+//    ██    ██   ██ ██   ██ ██      ██   ██ ██  ██  ██ ██    ██      ██ 
+//    ██    ██   ██ ██   ██ ███████ ██   ██ ██      ██  ██████  ███████                                                                             
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+
+// Job scheduler: /api/jobs
+// Every model run waits in a queue per service and starts once the service has a free slot.
+// Concurrency per service, default priorities and an optional node-wide cap are read from
+// scheduler.json. The queue is persisted in /opt/thalamus/jobs, with each job's input and
+// output in /opt/thalamus/jobs/{oid}, so queued and interrupted jobs run again after a restart.
+// DELETE /api/jobs/{oid}, or a caller hanging up while it waits, cancels the job: its child
+// processes are killed, NST stops at its next step and the job's directory is removed.
+// Outputs of completed jobs move to the artifact store and the job links to them.
+// Peers hear about every job as it is queued, starts and finishes (see p2p::jobs).
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use rouille::Request;
+use rouille::Response;
+use serde::{Serialize, Deserialize};
+
+use crate::thalamus::http::ErrorReply;
+use crate::thalamus::services::llama::LlamaModel;
+use crate::thalamus::services::whisper::{WhisperFormat, WhisperModel};
+
+pub const JOBS_DIR: &str = "/opt/thalamus/jobs";
+pub const QUEUE_PATH: &str = "/opt/thalamus/jobs/queue.json";
+pub const SCHEDULER_CONFIG_PATH: &str = "/opt/thalamus/scheduler.json";
+
+// Finished jobs kept in the queue file so callers can still fetch their results
+const FINISHED_KEPT: usize = 200;
+
+// How often a waiting http request checks that its caller is still connected
+const DISCONNECT_POLL: Duration = Duration::from_secs(1);
+
+/// Limits for one service's queue
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct QueueConfig {
+    /// Jobs of this service allowed to run at once
+    pub concurrency: usize,
+    /// Priority of jobs that don't ask for one; higher runs first
+    pub priority: i64,
+}
+impl Default for QueueConfig {
+    fn default() -> QueueConfig {
+        QueueConfig { concurrency: 1, priority: 0 }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SchedulerConfig {
+    /// Jobs allowed to run at once across all services (0 for no limit)
+    pub max_running: usize,
+    pub services: BTreeMap<String, QueueConfig>,
+}
+impl Default for SchedulerConfig {
+    fn default() -> SchedulerConfig {
+        let mut services = BTreeMap::new();
+        services.insert(format!("whisper"), QueueConfig { concurrency: 2, priority: 0 });
+        services.insert(format!("llama"), QueueConfig { concurrency: 1, priority: 0 });
+        services.insert(format!("tts"), QueueConfig { concurrency: 4, priority: 10 });
+        services.insert(format!("srgan"), QueueConfig { concurrency: 1, priority: 0 });
+        services.insert(format!("yolo"), QueueConfig { concurrency: 2, priority: 0 });
+        services.insert(format!("nst"), QueueConfig { concurrency: 1, priority: -10 });
+        SchedulerConfig { max_running: 0, services: services }
+    }
+}
+impl SchedulerConfig {
+    /// Reads scheduler.json, falling back to the defaults
+    pub fn load() -> SchedulerConfig {
+        match std::fs::read_to_string(SCHEDULER_CONFIG_PATH) {
+            Ok(data) => match serde_json::from_str(data.as_str()) {
+                Ok(config) => config,
+                Err(e) => {
+                    log::error!("Unable to parse {}, using defaults: {}", SCHEDULER_CONFIG_PATH, e);
+                    SchedulerConfig::default()
+                }
+            },
+            Err(_) => SchedulerConfig::default(),
+        }
+    }
+
+    fn queue(&self, service: &str) -> QueueConfig {
+        self.services.get(service).cloned().unwrap_or_default()
+    }
+}
+
+/// What to run; inputs are paths inside the job's directory
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Spec {
+    Whisper { model: WhisperModel, language: Option<String>, format: WhisperFormat, input: String },
+    WhisperVwav { model: WhisperModel, language: Option<String>, input: String },
+    Llama { model: LlamaModel, prompt: String },
+    Tts { text: String, primary: String, fallback: String },
+    Srgan { filename: String, input: String },
+    Yolov7 { input: String },
+    Nst { style: String, style_name: String, input: String },
+}
+impl Spec {
+    /// The queue the job waits in
+    pub fn service(&self) -> &'static str {
+        match self {
+            Spec::Whisper { .. } | Spec::WhisperVwav { .. } => "whisper",
+            Spec::Llama { .. } => "llama",
+            Spec::Tts { .. } => "tts",
+            Spec::Srgan { .. } => "srgan",
+            Spec::Yolov7 { .. } => "yolo",
+            Spec::Nst { .. } => "nst",
+        }
+    }
+
+    /// Traffic key, e.g. "whisper:base"
+    pub fn key(&self) -> String {
+        match self {
+            Spec::Whisper { model, .. } => format!("whisper:{}", model),
+            Spec::WhisperVwav { model, .. } => format!("whisper_vwav:{}", model),
+            Spec::Llama { model, .. } => format!("llama:{}", model),
+            Spec::Tts { .. } => format!("tts"),
+            Spec::Srgan { .. } => format!("srgan"),
+            Spec::Yolov7 { .. } => format!("yolo:v7"),
+            Spec::Nst { .. } => format!("nst"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Queued,
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+impl Status {
+    pub fn is_finished(&self) -> bool {
+        match self {
+            Status::Completed | Status::Failed | Status::Cancelled => true,
+            _ => false,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Status::Queued => "queued",
+            Status::Running => "running",
+            Status::Completed => "completed",
+            Status::Failed => "failed",
+            Status::Cancelled => "cancelled",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Job {
+    pub oid: String,
+    pub service: String,
+    pub key: String,
+    pub spec: Spec,
+    /// Higher runs first; ties run in submission order
+    pub priority: i64,
+    pub status: Status,
+    /// 1 for the next job to start in its queue; only set while queued
+    #[serde(default)]
+    pub position: Option<usize>,
+    pub output: Option<String>,
+    pub content_type: Option<String>,
+    /// Id of the output in the artifact store, and where to download it
+    #[serde(default)]
+    pub artifact: Option<String>,
+    #[serde(default)]
+    pub url: Option<String>,
+    pub error: Option<String>,
+    /// Request and trace the job was submitted under, so its logs and spans join them
+    #[serde(default)]
+    pub request_id: Option<String>,
+    #[serde(default)]
+    pub traceparent: Option<String>,
+    pub created_at: i64,
+    pub started_at: Option<i64>,
+    pub finished_at: Option<i64>,
+}
+
+struct Scheduler {
+    config: SchedulerConfig,
+    jobs: Vec<Job>,
+    trackers: HashMap<String, crate::thalamus::traffic::Tracker>,
+    controls: HashMap<String, Arc<Control>>,
+}
+
+// What runs a job and stores its output
+type Runner = fn(&Job) -> Result<crate::thalamus::artifacts::Artifact, String>;
+
+/// A scheduler, where its queue is persisted, and the condvar signalled as its jobs finish
+struct Queue {
+    state: Mutex<Scheduler>,
+    finished: Condvar,
+    path: String,
+    runner: Runner,
+}
+
+/// Cancellation state of a running job, shared with the thread running it
+struct Control {
+    cancelled: AtomicBool,
+    // Process groups of the job's children
+    children: Mutex<Vec<u32>>,
+}
+impl Control {
+    fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        for pid in self.children.lock().unwrap().iter() {
+            kill(*pid);
+        }
+    }
+}
+
+static SCHEDULER: OnceLock<Queue> = OnceLock::new();
+
+thread_local! {
+    // The job the current worker thread is running
+    static CURRENT: RefCell<Option<Arc<Control>>> = RefCell::new(None);
+}
+
+fn scheduler() -> &'static Queue {
+    SCHEDULER.get_or_init(|| Queue::new(SchedulerConfig::load(), QUEUE_PATH, run_job))
+}
+
+fn kill(pid: u32) {
+    // Children lead their own process group, so this also takes down anything they started
+    if unsafe { libc::kill(-(pid as libc::pid_t), libc::SIGKILL) } != 0 {
+        log::warn!("Unable to kill process group {}: {}", pid, std::io::Error::last_os_error());
+    }
+}
+
+/// A child process counted against the current job until dropped
+pub struct Watch {
+    pid: u32,
+    control: Option<Arc<Control>>,
+}
+impl Drop for Watch {
+    fn drop(&mut self) {
+        match &self.control {
+            Some(control) => control.children.lock().unwrap().retain(|pid| *pid != self.pid),
+            None => {}
+        }
+    }
+}
+
+/// Ties a child to the job running on this thread, so cancelling the job kills it. The child
+/// should be spawned with process_group(0). Outside a job this does nothing.
+pub fn watch(child: &std::process::Child) -> Watch {
+    let control = CURRENT.with(|current| current.borrow().clone());
+    match &control {
+        Some(control) => {
+            control.children.lock().unwrap().push(child.id());
+            if control.cancelled.load(Ordering::SeqCst) {
+                kill(child.id());
+            }
+        },
+        None => {}
+    }
+    Watch { pid: child.id(), control: control }
+}
+
+/// Whether the job running on this thread has been cancelled; long loops check it between steps
+pub fn cancelled() -> bool {
+    CURRENT.with(|current| match current.borrow().as_ref() {
+        Some(control) => control.cancelled.load(Ordering::SeqCst),
+        None => false,
+    })
+}
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+}
+
+/// Directory holding a job's input and output
+pub fn dir(oid: &str) -> String {
+    format!("{}/{}", JOBS_DIR, oid)
+}
+
+/// A path inside a job's directory
+pub fn path(oid: &str, name: &str) -> String {
+    format!("{}/{}", dir(oid), name)
+}
+
+/// Reserves an id and creates its directory, ready for the job's input
+pub fn prepare() -> std::io::Result<String> {
+    let oid: String = thread_rng().sample_iter(&Alphanumeric).take(15).map(char::from).collect();
+    std::fs::create_dir_all(dir(oid.as_str()))?;
+    return Ok(oid);
+}
+
+/// Loads the persisted queue and restarts whatever was queued or running when the node stopped
+pub fn init() {
+    match std::fs::create_dir_all(JOBS_DIR) {
+        Ok(_) => {},
+        Err(e) => log::error!("Unable to create {}: {}", JOBS_DIR, e),
+    }
+
+    scheduler().restore();
+}
+
+/// Queues a job; priority defaults to its service's
+pub fn submit(oid: String, spec: Spec, priority: Option<i64>) -> Job {
+    return scheduler().submit(oid, spec, priority);
+}
+
+/// Blocks until the job finishes
+pub fn wait(oid: &str) -> Option<Job> {
+    return scheduler().wait(oid);
+}
+
+/// Blocks until the job finishes, cancelling it if the http caller at `remote` hangs up
+pub fn wait_for(oid: &str, remote: &SocketAddr) -> Option<Job> {
+    return scheduler().wait_for(oid, remote);
+}
+
+/// Cancels a job. Queued jobs are dropped at once; running ones are killed and reach
+/// Cancelled once their worker notices. None if the job doesn't exist.
+pub fn cancel(oid: &str) -> Option<Job> {
+    return scheduler().cancel(oid);
+}
+
+impl Queue {
+    fn new(config: SchedulerConfig, path: &str, runner: Runner) -> Queue {
+        Queue {
+            state: Mutex::new(Scheduler { config: config, jobs: Vec::new(), trackers: HashMap::new(), controls: HashMap::new() }),
+            finished: Condvar::new(),
+            path: path.to_string(),
+            runner: runner,
+        }
+    }
+
+    // Loads the persisted queue, requeueing what was queued or running, and starts what it can
+    fn restore(&'static self) {
+        let jobs: Vec<Job> = match std::fs::read_to_string(self.path.as_str()) {
+            Ok(data) => match serde_json::from_str(data.as_str()) {
+                Ok(jobs) => jobs,
+                Err(e) => {
+                    log::error!("Unable to parse {}, starting with an empty queue: {}", self.path, e);
+                    Vec::new()
+                }
+            },
+            Err(_) => Vec::new(),
+        };
+
+        let mut state = self.state.lock().unwrap();
+        state.jobs = jobs;
+        let mut restored = 0;
+        for i in 0..state.jobs.len() {
+            if state.jobs[i].status.is_finished() {
+                continue;
+            }
+            state.jobs[i].status = Status::Queued;
+            state.jobs[i].started_at = None;
+            let (oid, key) = (state.jobs[i].oid.clone(), state.jobs[i].key.clone());
+            state.trackers.insert(oid, crate::thalamus::traffic::begin(key));
+            restored += 1;
+        }
+        if restored > 0 {
+            log::warn!("Restored {} queued jobs", restored);
+        }
+        save(self.path.as_str(), &state.jobs);
+        let unstarted = self.dispatch(&mut state);
+        std::mem::drop(state);
+        self.fail_unstarted(unstarted);
+    }
+
+    fn submit(&'static self, oid: String, spec: Spec, priority: Option<i64>) -> Job {
+        let mut state = self.state.lock().unwrap();
+        let service = spec.service().to_string();
+        let job = Job {
+            oid: oid.clone(),
+            key: spec.key(),
+            priority: priority.unwrap_or(state.config.queue(service.as_str()).priority),
+            service: service,
+            spec: spec,
+            status: Status::Queued,
+            position: None,
+            output: None,
+            content_type: None,
+            artifact: None,
+            url: None,
+            error: None,
+            request_id: crate::logging::current(),
+            traceparent: crate::trace::traceparent(),
+            created_at: now(),
+            started_at: None,
+            finished_at: None,
+        };
+        log::info!("Queued {} job {}", job.key, job.oid);
+        announce(&job);
+        state.trackers.insert(oid.clone(), crate::thalamus::traffic::begin(job.key.clone()));
+        state.jobs.push(job);
+        save(self.path.as_str(), &state.jobs);
+        let unstarted = self.dispatch(&mut state);
+        let job = with_position(&state.jobs, oid.as_str()).unwrap();
+        std::mem::drop(state);
+        self.fail_unstarted(unstarted);
+        return job;
+    }
+
+    fn wait(&self, oid: &str) -> Option<Job> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            match state.jobs.iter().find(|j| j.oid == oid) {
+                Some(job) if job.status.is_finished() => return Some(job.clone()),
+                Some(_) => {},
+                None => return None,
+            }
+            state = self.finished.wait(state).unwrap();
+        }
+    }
+
+    fn wait_for(&self, oid: &str, remote: &SocketAddr) -> Option<Job> {
+        let mut state = self.state.lock().unwrap();
+        let mut hung_up = false;
+        loop {
+            match state.jobs.iter().find(|j| j.oid == oid) {
+                Some(job) if job.status.is_finished() => return Some(job.clone()),
+                Some(_) => {},
+                None => return None,
+            }
+            state = self.finished.wait_timeout(state, DISCONNECT_POLL).unwrap().0;
+            if !hung_up && !connected(remote) {
+                hung_up = true;
+                std::mem::drop(state);
+                log::warn!("{} disconnected, cancelling job {}", remote, oid);
+                self.cancel(oid);
+                state = self.state.lock().unwrap();
+            }
+        }
+    }
+
+    fn cancel(&self, oid: &str) -> Option<Job> {
+        let mut state = self.state.lock().unwrap();
+        let job = state.jobs.iter_mut().find(|j| j.oid == oid)?;
+        match job.status {
+            Status::Queued => {
+                job.status = Status::Cancelled;
+                job.finished_at = Some(now());
+                log::info!("Cancelled queued {} job {}", job.key, job.oid);
+                announce(job);
+                let tracker = state.trackers.remove(oid);
+                save(self.path.as_str(), &state.jobs);
+                let job = with_position(&state.jobs, oid);
+                std::mem::drop(state);
+                self.finished.notify_all();
+                match tracker {
+                    Some(tracker) => tracker.cancelled(),
+                    None => {}
+                }
+                cleanup(oid);
+                return job;
+            },
+            Status::Running => {
+                log::info!("Cancelling running {} job {}", job.key, job.oid);
+                match state.controls.get(oid) {
+                    Some(control) => control.cancel(),
+                    None => {}
+                }
+            },
+            _ => {}
+        }
+        return with_position(&state.jobs, oid);
+    }
+    // Starts queued jobs within the concurrency limits. Returns the jobs whose worker thread
+    // couldn't be spawned, for the caller to fail with fail_unstarted once the lock is released.
+    fn dispatch(&'static self, state: &mut Scheduler) -> Vec<(String, String)> {
+        let mut queued: Vec<usize> = (0..state.jobs.len()).filter(|i| state.jobs[*i].status == Status::Queued).collect();
+        queued.sort_by_key(|i| (-state.jobs[*i].priority, state.jobs[*i].created_at, *i));
+
+        let mut running: HashMap<String, usize> = HashMap::new();
+        for job in state.jobs.iter().filter(|j| j.status == Status::Running) {
+            *running.entry(job.service.clone()).or_default() += 1;
+        }
+        let mut total: usize = running.values().sum();
+
+        let mut started = false;
+        let mut unstarted = Vec::new();
+        for i in queued {
+            if state.config.max_running > 0 && total >= state.config.max_running {
+                break;
+            }
+            let service = state.jobs[i].service.clone();
+            let count = running.entry(service.clone()).or_default();
+            if *count >= state.config.queue(service.as_str()).concurrency.max(1) {
+                continue;
+            }
+            *count += 1;
+            total += 1;
+
+            state.jobs[i].status = Status::Running;
+            state.jobs[i].started_at = Some(now());
+            let control = Arc::new(Control { cancelled: AtomicBool::new(false), children: Mutex::new(Vec::new()) });
+            state.controls.insert(state.jobs[i].oid.clone(), Arc::clone(&control));
+            announce(&state.jobs[i]);
+            match self.start(state.jobs[i].clone(), control) {
+                Ok(_) => {},
+                Err(e) => {
+                    log::error!("Unable to start job {}: {}", state.jobs[i].oid, e);
+                    unstarted.push((state.jobs[i].oid.clone(), format!("unable to start: {}", e)));
+                },
+            }
+            started = true;
+        }
+        if started {
+            save(self.path.as_str(), &state.jobs);
+        }
+        return unstarted;
+    }
+
+    // Fails jobs dispatch couldn't start; finishing them may dispatch (and fail) more
+    fn fail_unstarted(&'static self, mut unstarted: Vec<(String, String)>) {
+        loop {
+            match unstarted.pop() {
+                Some((oid, error)) => unstarted.extend(self.settle(oid.as_str(), Err(error))),
+                None => return,
+            }
+        }
+    }
+
+    fn start(&'static self, job: Job, control: Arc<Control>) -> std::io::Result<()> {
+        let spawned = std::thread::Builder::new().name(format!("job_{}", job.service)).spawn(move || {
+            CURRENT.with(|current| *current.borrow_mut() = Some(control));
+            let _scope = crate::logging::scope(job.request_id.clone().unwrap_or(job.oid.clone()));
+            let mut span = crate::trace::accept(job.traceparent.as_deref(), format!("job {}", job.key));
+            span.attr("job.oid", job.oid.as_str());
+            log::info!("Running {} job {}", job.key, job.oid);
+
+            // A panic in the runner still has to finish the job, or it would hold its slot forever
+            let result = match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| (self.runner)(&job))) {
+                Ok(result) => result,
+                Err(_) => Err(format!("worker panicked")),
+            };
+            match &result {
+                _ if cancelled() => {
+                    log::info!("Cancelled {} job {}", job.key, job.oid);
+                    span.attr("job.cancelled", true);
+                },
+                Ok(_) => log::info!("Finished {} job {}", job.key, job.oid),
+                Err(e) => {
+                    log::error!("{} job {} failed: {}", job.key, job.oid, e);
+                    span.error(e);
+                },
+            }
+            self.finish(job.oid.as_str(), result);
+            CURRENT.with(|current| *current.borrow_mut() = None);
+        });
+        return spawned.map(|_| ());
+    }
+
+    fn finish(&'static self, oid: &str, result: Result<crate::thalamus::artifacts::Artifact, String>) {
+        let unstarted = self.settle(oid, result);
+        self.fail_unstarted(unstarted);
+    }
+
+    // Records a job's outcome and starts whatever can run next, returning the jobs that couldn't start
+    fn settle(&'static self, oid: &str, result: Result<crate::thalamus::artifacts::Artifact, String>) -> Vec<(String, String)> {
+        let mut state = self.state.lock().unwrap();
+        let cancelled = match state.controls.remove(oid) {
+            Some(control) => control.cancelled.load(Ordering::SeqCst),
+            None => false,
+        };
+        match state.jobs.iter_mut().find(|j| j.oid == oid) {
+            Some(job) => {
+                job.finished_at = Some(now());
+                match result {
+                    _ if cancelled => {
+                        job.status = Status::Cancelled;
+                    },
+                    Ok(artifact) => {
+                        job.status = Status::Completed;
+                        job.output = Some(artifact.path());
+                        job.content_type = Some(artifact.content_type.to_string());
+                        job.url = Some(artifact.url());
+                        job.artifact = Some(artifact.id);
+                    },
+                    Err(e) => {
+                        job.status = Status::Failed;
+                        job.error = Some(e);
+                    },
+                }
+                announce(job);
+            },
+            None => {}
+        }
+        let tracker = state.trackers.remove(oid);
+        let completed = state.jobs.iter().any(|j| j.oid == oid && j.status == Status::Completed);
+        prune(&mut state.jobs);
+        save(self.path.as_str(), &state.jobs);
+        let unstarted = self.dispatch(&mut state);
+        std::mem::drop(state);
+        self.finished.notify_all();
+
+        // Trackers record into the traffic stats on drop, so release them outside the queue lock
+        match tracker {
+            Some(tracker) if cancelled => tracker.cancelled(),
+            Some(tracker) if completed => tracker.done(),
+            _ => {}
+        }
+        cleanup(oid);
+        return unstarted;
+    }
+}
+
+// Removes a job's inputs and whatever it wrote next to them; outputs worth keeping have
+// already moved to the artifact store
+fn cleanup(oid: &str) {
+    match std::fs::remove_dir_all(dir(oid)) {
+        Ok(_) => {},
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {},
+        Err(e) => log::error!("Unable to remove {}: {}", dir(oid), e),
+    }
+}
+
+// Whether the http client at `remote` still holds its connection open, from the kernel's
+// socket table. Where that isn't available the client is assumed to still be there.
+fn connected(remote: &SocketAddr) -> bool {
+    #[cfg(target_os = "linux")]{
+        let mut keys: Vec<(&str, String)> = Vec::new();
+        match remote {
+            SocketAddr::V4(addr) => {
+                keys.push(("/proc/net/tcp", format!("{:08X}:{:04X}", u32::from_ne_bytes(addr.ip().octets()), addr.port())));
+                keys.push(("/proc/net/tcp6", format!("{}:{:04X}", proc_ipv6(&addr.ip().to_ipv6_mapped()), addr.port())));
+            },
+            SocketAddr::V6(addr) => {
+                keys.push(("/proc/net/tcp6", format!("{}:{:04X}", proc_ipv6(addr.ip()), addr.port())));
+            },
+        }
+
+        let mut readable = false;
+        for (table, key) in keys.iter() {
+            let data = match std::fs::read_to_string(table) {
+                Ok(data) => data,
+                Err(_) => continue,
+            };
+            readable = true;
+            for line in data.lines().skip(1) {
+                let fields: Vec<&str> = line.split_whitespace().collect();
+                // sl local_address rem_address st ...; 01 is ESTABLISHED
+                if fields.len() > 3 && fields[2] == key.as_str() && fields[3] == "01" {
+                    return true;
+                }
+            }
+        }
+        return !readable;
+    }
+
+    #[cfg(not(target_os = "linux"))]{
+        let _ = remote;
+        return true;
+    }
+}
+
+// An IPv6 address as /proc/net/tcp6 prints it: four 32-bit words in host byte order
+#[cfg(target_os = "linux")]
+fn proc_ipv6(ip: &std::net::Ipv6Addr) -> String {
+    ip.octets().chunks(4).map(|word| format!("{:08X}", u32::from_ne_bytes([word[0], word[1], word[2], word[3]]))).collect()
+}
+
+/// Queues a job and waits for it
+pub fn run(oid: String, spec: Spec) -> Option<Job> {
+    let job = submit(oid, spec, None);
+    return wait(job.oid.as_str());
+}
+
+pub fn get(oid: &str) -> Option<Job> {
+    let state = scheduler().state.lock().unwrap();
+    return with_position(&state.jobs, oid);
+}
+
+/// Every job the scheduler knows about, unfinished first
+pub fn list() -> Vec<Job> {
+    let state = scheduler().state.lock().unwrap();
+    let mut jobs: Vec<Job> = state.jobs.iter().filter_map(|j| with_position(&state.jobs, j.oid.as_str())).collect();
+    jobs.sort_by_key(|j| (j.status.is_finished(), j.status != Status::Running, j.position, -j.created_at));
+    return jobs;
+}
+
+/// Unfinished jobs as they appear on this node's record in /api/nodex
+pub fn node_jobs() -> Vec<crate::ThalamusNodeJob> {
+    let state = scheduler().state.lock().unwrap();
+    return state.jobs.iter().filter(|j| !j.status.is_finished()).map(node_job).collect();
+}
+
+fn node_job(job: &Job) -> crate::ThalamusNodeJob {
+    crate::ThalamusNodeJob {
+        oid: job.oid.clone(),
+        job_identifier: job.key.clone(),
+        url: Some(format!("/api/jobs/{}", job.oid)),
+        status: Some(job.status.as_str().to_string()),
+        progress: None,
+        started_at: job.started_at.unwrap_or(job.created_at),
+    }
+}
+
+// Tells peers where a job is in its lifecycle
+fn announce(job: &Job) {
+    crate::p2p::publish_job(node_job(job), job.status.is_finished());
+}
+
+/// Oids of jobs that haven't finished
+pub fn active() -> HashSet<String> {
+    let state = scheduler().state.lock().unwrap();
+    return state.jobs.iter().filter(|j| !j.status.is_finished()).map(|j| j.oid.clone()).collect();
+}
+
+/// Queued and running jobs per service
+pub fn depth() -> BTreeMap<String, (usize, usize)> {
+    let state = scheduler().state.lock().unwrap();
+    let mut depth: BTreeMap<String, (usize, usize)> = BTreeMap::new();
+    for service in state.config.services.keys() {
+        depth.insert(service.to_string(), (0, 0));
+    }
+    for job in state.jobs.iter() {
+        let entry = depth.entry(job.service.to_string()).or_default();
+        match job.status {
+            Status::Queued => entry.0 += 1,
+            Status::Running => entry.1 += 1,
+            _ => {}
+        }
+    }
+    return depth;
+}
+
+/// Reads a finished job's output
+pub fn output(job: &Job) -> std::io::Result<Vec<u8>> {
+    match &job.output {
+        Some(path) => crate::trace::read_file(path.as_str()),
+        None => Err(std::io::Error::new(std::io::ErrorKind::NotFound, format!("job {} has no output", job.oid))),
+    }
+}
+
+/// Queues a job for an http request. With ?async=true the caller gets the job back at once
+/// (202) and follows it at /api/jobs/{oid}; otherwise the request waits for the result.
+/// ?priority=N overrides the service's default priority.
+pub fn respond(request: &Request, oid: String, spec: Spec) -> Response {
+    let priority = request.get_param("priority").and_then(|p| p.parse::<i64>().ok());
+    let job = submit(oid, spec, priority);
+    match request.get_param("async") {
+        Some(value) if value == "true" || value == "1" => return accepted(&job),
+        _ => {}
+    }
+    return match wait_for(job.oid.as_str(), request.remote_addr()) {
+        Some(job) => result(&job),
+        None => ErrorReply::internal(format!("job {} was lost", job.oid)),
+    };
+}
+
+/// 202 reply pointing at a queued job
+pub fn accepted(job: &Job) -> Response {
+    Response::json(job).with_status_code(202).with_additional_header("Location", format!("/api/jobs/{}", job.oid))
+}
+
+/// A finished job's output as an http reply
+pub fn result(job: &Job) -> Response {
+    match job.status {
+        Status::Completed => {
+            match job.artifact.as_deref().and_then(crate::thalamus::artifacts::get) {
+                Some(artifact) => crate::thalamus::artifacts::response(&artifact).with_additional_header("X-Job-Id", job.oid.clone()),
+                None => ErrorReply::gone(format!("output of job {} has expired", job.oid)),
+            }
+        },
+        Status::Failed => ErrorReply::internal(job.error.clone().unwrap_or(format!("job {} failed", job.oid))),
+        Status::Cancelled => ErrorReply::conflict(format!("job {} was cancelled", job.oid)),
+        _ => ErrorReply::conflict(format!("job {} is {}", job.oid, job.status.as_str())),
+    }
+}
+
+/// /api/jobs, /api/jobs/{oid} and /api/jobs/{oid}/result; DELETE /api/jobs/{oid} cancels
+pub fn handle(request: &Request, thalamus: Arc<Mutex<crate::ThalamusClient>>) -> Result<Response, crate::thalamus::http::Error> {
+    let url = request.url();
+    let parts: Vec<&str> = url.trim_start_matches("/api/jobs").split('/').filter(|p| !p.is_empty()).collect();
+    match parts.as_slice() {
+        [] => return Ok(Response::json(&list())),
+        [oid] if request.method() == "DELETE" => {
+            match cancel(oid) {
+                Some(job) if job.status == Status::Running => return Ok(Response::json(&job).with_status_code(202)),
+                Some(job) if job.status == Status::Cancelled => return Ok(Response::json(&job)),
+                Some(job) => return Ok(ErrorReply::conflict(format!("job {} already {}", job.oid, job.status.as_str()))),
+                None => {}
+            }
+            // Not a scheduler job; a benchmark stops once its current test finishes
+            return Ok(match crate::cancel_node_job(thalamus, oid) {
+                Some(job) => Response::json(&job).with_status_code(202),
+                None => Response::empty_404(),
+            });
+        },
+        [oid] => return Ok(match get(oid) {
+            Some(job) => Response::json(&job),
+            None => Response::empty_404(),
+        }),
+        [oid, "result"] => return Ok(match get(oid) {
+            Some(job) => result(&job),
+            None => Response::empty_404(),
+        }),
+        _ => return Ok(Response::empty_404()),
+    }
+}
+
+// Sets the queue position on a copy of the job
+fn with_position(jobs: &[Job], oid: &str) -> Option<Job> {
+    let index = jobs.iter().position(|j| j.oid == oid)?;
+    let mut job = jobs[index].clone();
+    if job.status == Status::Queued {
+        let ahead = jobs.iter().enumerate().filter(|(i, j)| {
+            j.status == Status::Queued && j.service == job.service && runs_before(jobs, *i, index)
+        }).count();
+        job.position = Some(ahead + 1);
+    }
+    return Some(job);
+}
+
+// Whether jobs[a] starts before jobs[b]: higher priority first, then submission order. The
+// queue keeps jobs in submission order, which breaks ties within created_at's one second.
+fn runs_before(jobs: &[Job], a: usize, b: usize) -> bool {
+    (-jobs[a].priority, jobs[a].created_at, a) < (-jobs[b].priority, jobs[b].created_at, b)
+}
+
+// Drops the oldest finished jobs beyond FINISHED_KEPT
+fn prune(jobs: &mut Vec<Job>) {
+    let mut finished: Vec<(i64, String)> = jobs.iter().filter(|j| j.status.is_finished()).map(|j| (j.finished_at.unwrap_or(0), j.oid.clone())).collect();
+    if finished.len() <= FINISHED_KEPT {
+        return;
+    }
+    finished.sort();
+    let dropped: Vec<String> = finished[..finished.len() - FINISHED_KEPT].iter().map(|(_, oid)| oid.clone()).collect();
+    jobs.retain(|j| !dropped.contains(&j.oid));
+}
+
+fn save(path: &str, jobs: &Vec<Job>) {
+    let json = match serde_json::to_string(jobs) {
+        Ok(json) => json,
+        Err(e) => return log::error!("Unable to serialize the job queue: {}", e),
+    };
+    match crate::storage::json::atomic_write(path, json.as_bytes()) {
+        Ok(_) => {},
+        Err(e) => log::error!("Unable to save {}: {}", path, e),
+    }
+}
+
+// Runs the model and moves its output to the artifact store
+fn run_job(job: &Job) -> Result<crate::thalamus::artifacts::Artifact, String> {
+    let (output, content_type) = execute(job)?;
+    return crate::thalamus::artifacts::put(output.as_str(), content_type.as_str()).map_err(|e| format!("unable to store output: {}", e));
+}
+
+// Runs the model, returning the output path and its content type
+fn execute(job: &Job) -> Result<(String, String), String> {
+    match &job.spec {
+        Spec::Whisper { model, language, format, input } => {
+            let text = crate::thalamus::services::whisper::whisper(input.clone(), *model, language.as_deref(), *format).map_err(|e| format!("{}", e))?;
+            let reply = crate::STTReply {
+                text: text,
+                time: job.created_at as f64,
+                response_type: None,
+            };
+            let output = path(job.oid.as_str(), "output.json");
+            let json = serde_json::to_vec(&reply).map_err(|e| format!("{}", e))?;
+            crate::trace::write_file(output.as_str(), &json).map_err(|e| format!("{}", e))?;
+            return Ok((output, format!("application/json")));
+        },
+        Spec::WhisperVwav { model, language, input } => {
+            let output = crate::thalamus::services::whisper::whisper_vwav(input.clone(), *model, language.as_deref()).map_err(|e| format!("{}", e))?;
+            return Ok((output, format!("video/mp4")));
+        },
+        Spec::Llama { model, prompt } => {
+            let text = crate::thalamus::tools::llama(model.as_str(), prompt.as_str()).map_err(|e| format!("{}", e))?;
+            let output = path(job.oid.as_str(), "output.txt");
+            crate::trace::write_file(output.as_str(), text.as_bytes()).map_err(|e| format!("{}", e))?;
+            return Ok((output, format!("text/plain; charset=utf-8")));
+        },
+        Spec::Tts { text, primary, fallback } => {
+            let wav = crate::thalamus::services::tts::get(text.clone(), primary.as_str(), fallback.as_str()).map_err(|e| format!("{}", e))?;
+            let output = path(job.oid.as_str(), "output.wav");
+            crate::trace::write_file(output.as_str(), &wav).map_err(|e| format!("{}", e))?;
+            return Ok((output, format!("audio/wav")));
+        },
+        Spec::Srgan { filename, input } => {
+            let output = path(job.oid.as_str(), format!("SRGAN_{}", filename).as_str());
+            crate::thalamus::tools::srgan(input.as_str(), output.as_str()).map_err(|e| format!("{}", e))?;
+            if !std::path::Path::new(output.as_str()).exists() {
+                return Err(format!("srgan produced no output"));
+            }
+            return Ok((output, crate::thalamus::tools::find_mimetype(filename)));
+        },
+        Spec::Yolov7 { input } => {
+            let json = crate::thalamus::services::image::yolo::yolov7(input.clone())?;
+            let reply: crate::thalamus::services::image::yolo::YoloV7Output = serde_json::from_str(&json).map_err(|e| format!("{}", e))?;
+            let output = path(job.oid.as_str(), "output.json");
+            let json = serde_json::to_vec(&reply).map_err(|e| format!("{}", e))?;
+            crate::trace::write_file(output.as_str(), &json).map_err(|e| format!("{}", e))?;
+            return Ok((output, format!("application/json")));
+        },
+        Spec::Nst { style, style_name, input } => {
+            let output = crate::thalamus::services::image::nst::run(style.as_str(), input.as_str(), job.oid.clone(), style_name.clone()).map_err(|e| format!("{}", e))?;
+            return Ok((output, format!("image/jpeg")));
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::thalamus::artifacts::Artifact;
+
+    // A scratch directory per test, removed on drop
+    struct Scratch(std::path::PathBuf);
+    impl Scratch {
+        fn new(name: &str) -> Scratch {
+            let dir = std::env::temp_dir().join(format!("thalamus-jobs-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            Scratch(dir)
+        }
+
+        fn path(&self, name: &str) -> String {
+            self.0.join(name).to_string_lossy().to_string()
+        }
+    }
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    // A queue persisted in the scratch directory, leaked so its workers can hold on to it
+    fn queue(scratch: &Scratch, config: SchedulerConfig, runner: Runner) -> &'static Queue {
+        Box::leak(Box::new(Queue::new(config, scratch.path("queue.json").as_str(), runner)))
+    }
+
+    fn config(services: &[(&str, usize)]) -> SchedulerConfig {
+        let mut config = SchedulerConfig { max_running: 0, services: BTreeMap::new() };
+        for (service, concurrency) in services {
+            config.services.insert(service.to_string(), QueueConfig { concurrency: *concurrency, priority: 0 });
+        }
+        config
+    }
+
+    fn llama(prompt: &str) -> Spec {
+        Spec::Llama { model: LlamaModel::Llama7B, prompt: prompt.to_string() }
+    }
+
+    fn status(queue: &Queue, oid: &str) -> Status {
+        queue.state.lock().unwrap().jobs.iter().find(|j| j.oid == oid).unwrap().status
+    }
+
+    // Holds its slot until the job is cancelled
+    fn hold(_: &Job) -> Result<Artifact, String> {
+        while !cancelled() {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        Err(format!("cancelled"))
+    }
+
+    // Panics on the prompt "panic", otherwise holds its slot
+    fn panics(job: &Job) -> Result<Artifact, String> {
+        match &job.spec {
+            Spec::Llama { prompt, .. } if prompt == "panic" => panic!("model crashed"),
+            _ => hold(job),
+        }
+    }
+
+    fn tts() -> Spec {
+        Spec::Tts { text: format!("hello"), primary: format!("a"), fallback: format!("b") }
+    }
+
+    // A job as it would sit in queue.json
+    fn job(oid: &str, spec: Spec, priority: i64, status: Status, created_at: i64) -> Job {
+        Job {
+            oid: oid.to_string(),
+            service: spec.service().to_string(),
+            key: spec.key(),
+            spec: spec,
+            priority: priority,
+            status: status,
+            position: None,
+            output: None,
+            content_type: None,
+            artifact: None,
+            url: None,
+            error: None,
+            request_id: None,
+            traceparent: None,
+            created_at: created_at,
+            started_at: None,
+            finished_at: None,
+        }
+    }
+
+    // Cancels every unfinished job and waits for its worker to let go
+    fn drain(queue: &'static Queue) {
+        let oids: Vec<String> = queue.state.lock().unwrap().jobs.iter().filter(|j| !j.status.is_finished()).map(|j| j.oid.clone()).collect();
+        for oid in oids.iter() {
+            queue.cancel(oid.as_str());
+        }
+        for oid in oids.iter() {
+            queue.wait(oid.as_str());
+        }
+    }
+
+    #[test]
+    fn higher_priority_runs_first_then_submission_order() {
+        let jobs = vec![
+            job("c", llama("c"), 0, Status::Queued, 100),
+            job("b", llama("b"), 0, Status::Queued, 100),
+            job("a", llama("a"), 0, Status::Queued, 101),
+            job("urgent", llama("urgent"), 5, Status::Queued, 102),
+        ];
+        assert!(runs_before(&jobs, 3, 0));
+        assert!(runs_before(&jobs, 0, 2));
+        // Same second: the queue's order, not the oid, decides
+        assert!(runs_before(&jobs, 0, 1));
+        assert!(!runs_before(&jobs, 1, 0));
+
+        let positions: Vec<Option<usize>> = jobs.iter().map(|j| with_position(&jobs, j.oid.as_str()).unwrap().position).collect();
+        assert_eq!(positions, vec![Some(2), Some(3), Some(4), Some(1)]);
+    }
+
+    #[test]
+    fn dispatch_starts_the_highest_priority_job_first() {
+        let scratch = Scratch::new("priority");
+        let queue = queue(&scratch, config(&[("llama", 1)]), hold);
+        queue.submit(format!("first"), llama("first"), None);
+        queue.submit(format!("later"), llama("later"), None);
+        let urgent = queue.submit(format!("urgent"), llama("urgent"), Some(5));
+        assert_eq!(urgent.position, Some(1));
+
+        queue.cancel("first");
+        queue.wait("first");
+        assert_eq!(status(queue, "urgent"), Status::Running);
+        assert_eq!(status(queue, "later"), Status::Queued);
+        drain(queue);
+    }
+
+    #[test]
+    fn dispatch_keeps_each_service_within_its_concurrency() {
+        let scratch = Scratch::new("concurrency");
+        let queue = queue(&scratch, config(&[("llama", 1), ("tts", 2)]), hold);
+        for oid in ["l1", "l2"] {
+            queue.submit(oid.to_string(), llama(oid), None);
+        }
+        for oid in ["t1", "t2", "t3"] {
+            queue.submit(oid.to_string(), tts(), None);
+        }
+
+        assert_eq!(status(queue, "l1"), Status::Running);
+        assert_eq!(status(queue, "l2"), Status::Queued);
+        assert_eq!(status(queue, "t1"), Status::Running);
+        assert_eq!(status(queue, "t2"), Status::Running);
+        assert_eq!(status(queue, "t3"), Status::Queued);
+
+        // A finished job hands its slot to the next one of its own service
+        queue.cancel("t1");
+        queue.wait("t1");
+        assert_eq!(status(queue, "t3"), Status::Running);
+        assert_eq!(status(queue, "l2"), Status::Queued);
+        drain(queue);
+    }
+
+    #[test]
+    fn dispatch_respects_the_node_wide_cap() {
+        let scratch = Scratch::new("cap");
+        let mut config = config(&[("llama", 1), ("tts", 4)]);
+        config.max_running = 2;
+        let queue = queue(&scratch, config, hold);
+        queue.submit(format!("l1"), llama("l1"), None);
+        for oid in ["t1", "t2", "t3"] {
+            queue.submit(oid.to_string(), tts(), None);
+        }
+
+        let running = queue.state.lock().unwrap().jobs.iter().filter(|j| j.status == Status::Running).count();
+        assert_eq!(running, 2);
+        assert_eq!(status(queue, "t2"), Status::Queued);
+        drain(queue);
+    }
+
+    #[test]
+    fn restore_requeues_unfinished_jobs_from_queue_json() {
+        let scratch = Scratch::new("restore");
+        let mut running = job("running", llama("running"), 0, Status::Running, 100);
+        running.started_at = Some(101);
+        let jobs = vec![
+            job("done", llama("done"), 0, Status::Completed, 90),
+            running,
+            job("queued", llama("queued"), 0, Status::Queued, 102),
+        ];
+        std::fs::write(scratch.path("queue.json"), serde_json::to_vec(&jobs).unwrap()).unwrap();
+
+        let queue = queue(&scratch, config(&[("llama", 1)]), hold);
+        queue.restore();
+        assert_eq!(status(queue, "done"), Status::Completed);
+        // Interrupted jobs run again, oldest first
+        assert_eq!(status(queue, "running"), Status::Running);
+        assert_eq!(status(queue, "queued"), Status::Queued);
+        assert_eq!(with_position(&queue.state.lock().unwrap().jobs, "queued").unwrap().position, Some(1));
+
+        // The restored queue is written back
+        let saved: Vec<Job> = serde_json::from_slice(&std::fs::read(scratch.path("queue.json")).unwrap()).unwrap();
+        assert_eq!(saved.len(), 3);
+        assert_eq!(saved.iter().find(|j| j.oid == "running").unwrap().status, Status::Running);
+        drain(queue);
+    }
+
+    #[test]
+    fn restore_starts_empty_on_a_corrupt_queue_json() {
+        let scratch = Scratch::new("restore-corrupt");
+        std::fs::write(scratch.path("queue.json"), "[{").unwrap();
+        let queue = queue(&scratch, config(&[("llama", 1)]), hold);
+        queue.restore();
+        assert!(queue.state.lock().unwrap().jobs.is_empty());
+    }
+
+    #[test]
+    fn cancelling_a_queued_job_drops_it_at_once() {
+        let scratch = Scratch::new("cancel-queued");
+        let queue = queue(&scratch, config(&[("llama", 1)]), hold);
+        queue.submit(format!("running"), llama("running"), None);
+        queue.submit(format!("queued"), llama("queued"), None);
+
+        let cancelled = queue.cancel("queued").unwrap();
+        assert_eq!(cancelled.status, Status::Cancelled);
+        assert!(cancelled.finished_at.is_some());
+        assert_eq!(status(queue, "running"), Status::Running);
+        assert!(queue.cancel("missing").is_none());
+        drain(queue);
+    }
+
+    #[test]
+    fn cancelling_a_running_job_stops_its_worker() {
+        let scratch = Scratch::new("cancel-running");
+        let queue = queue(&scratch, config(&[("llama", 1)]), hold);
+        queue.submit(format!("running"), llama("running"), None);
+        queue.submit(format!("next"), llama("next"), None);
+
+        // Still running until the worker notices
+        assert_eq!(queue.cancel("running").unwrap().status, Status::Running);
+        let job = queue.wait("running").unwrap();
+        assert_eq!(job.status, Status::Cancelled);
+        assert!(job.error.is_none());
+        assert_eq!(status(queue, "next"), Status::Running);
+        drain(queue);
+    }
+
+    #[test]
+    fn unstarted_jobs_fail_and_free_their_slot() {
+        let scratch = Scratch::new("unstarted");
+        let queue = queue(&scratch, config(&[("llama", 1)]), hold);
+
+        // As dispatch leaves a job whose worker thread couldn't be spawned
+        let mut state = queue.state.lock().unwrap();
+        state.jobs.push(job("unstarted", llama("unstarted"), 0, Status::Running, 100));
+        state.jobs.push(job("queued", llama("queued"), 0, Status::Queued, 101));
+        state.controls.insert(format!("unstarted"), Arc::new(Control { cancelled: AtomicBool::new(false), children: Mutex::new(Vec::new()) }));
+        std::mem::drop(state);
+
+        queue.fail_unstarted(vec![(format!("unstarted"), format!("unable to start: out of threads"))]);
+        let job = queue.wait("unstarted").unwrap();
+        assert_eq!(job.status, Status::Failed);
+        assert_eq!(job.error.as_deref(), Some("unable to start: out of threads"));
+        assert!(!queue.state.lock().unwrap().controls.contains_key("unstarted"));
+        assert_eq!(status(queue, "queued"), Status::Running);
+        drain(queue);
+    }
+
+    #[test]
+    fn panicking_job_fails_and_frees_its_slot() {
+        let scratch = Scratch::new("panic");
+        let queue = queue(&scratch, config(&[("llama", 1)]), panics);
+        queue.submit(format!("a"), llama("panic"), None);
+        queue.submit(format!("b"), llama("hello"), None);
+
+        let a = queue.wait("a").unwrap();
+        assert_eq!(a.status, Status::Failed);
+        assert_eq!(a.error.as_deref(), Some("worker panicked"));
+        assert!(!queue.state.lock().unwrap().controls.contains_key("a"));
+        assert_eq!(status(queue, "b"), Status::Running);
+
+        drain(queue);
+    }
+}
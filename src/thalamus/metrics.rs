@@ -0,0 +1,265 @@
+// ████████ ██   ██  █████  ██       █████  ███    ███ ██    ██ ███████ 
+//    ██    ██   ██ ██   ██ ██      ██   ██ ████  ████ ██    ██ ██      
+//    ██    ███████ ███████ ██      ███████ ██ ████ ██ ██    ██ ███████ 
+//    ██    ██   ██ ██   ██ ██      ██   ██ ██  ██  ██ ██    ██      ██ 
+//    ██    ██   ██ ██   ██ ███████ ██   ██ ██      ██  ██████  ███████                                                                             
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+
+// Prometheus metrics: /metrics
+// Counters and histograms are recorded as requests are served; peer counts, in-flight jobs
+// and OpenTTS availability are read when the endpoint is scraped.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+// Upper bounds of the duration histogram buckets, in seconds
+const BUCKETS: [f64; 12] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0];
+
+// Routes reported as-is, most specific first; anything else is "other"
+const ROUTES: [&str; 22] = [
+    "/api/thalamus/version",
+    "/api/thalamus/node",
+    "/api/thalamus/hardware",
+    "/api/services/image/srgan",
+    "/api/services/image/yolo/v7",
+    "/api/services/image/nst/styles",
+    "/api/services/image/nst/run",
+    "/api/services/image/ocnn",
+    "/api/services/image",
+    "/api/services/llama",
+    "/api/services/whisper/vwav",
+    "/api/services/whisper",
+    "/api/services/tts/voices",
+    "/api/services/tts",
+    "/api/nodex",
+    "/api/jobs",
+    "/api/artifacts",
+    "/metrics",
+    "/dashboard/app.js",
+    "/dashboard/style.css",
+    "/dashboard",
+    "/",
+];
+
+#[derive(Debug, Clone, Default)]
+struct Histogram {
+    buckets: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        for (i, bound) in BUCKETS.iter().enumerate() {
+            if seconds <= *bound {
+                self.buckets[i] += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+}
+
+// Metric name -> rendered label set -> value
+#[derive(Debug, Default)]
+struct Registry {
+    counters: BTreeMap<&'static str, BTreeMap<String, u64>>,
+    histograms: BTreeMap<&'static str, BTreeMap<String, Histogram>>,
+}
+
+static METRICS: OnceLock<Mutex<Registry>> = OnceLock::new();
+
+fn metrics() -> &'static Mutex<Registry> {
+    METRICS.get_or_init(|| Mutex::new(Registry::default()))
+}
+
+fn help(name: &str) -> &'static str {
+    match name {
+        "thalamus_http_requests_total" => "HTTP requests served, by route, method and status",
+        "thalamus_http_errors_total" => "HTTP requests answered with a 4xx or 5xx status",
+        "thalamus_http_request_duration_seconds" => "HTTP request latency",
+        "thalamus_service_requests_total" => "Model requests served over http or p2p, by service, model and outcome",
+        "thalamus_service_request_duration_seconds" => "Model request latency",
+        "thalamus_process_spawn_failures_total" => "Child processes that failed to start, by tool",
+        "thalamus_model_downloads_total" => "Model downloads by outcome",
+        "thalamus_hash_checks_total" => "Hash checks of downloaded models by outcome",
+        "thalamus_trace_spans_dropped_total" => "Finished spans dropped because the trace export queue was full",
+        _ => "",
+    }
+}
+
+fn labels(pairs: &[(&str, &str)]) -> String {
+    pairs.iter()
+        .map(|(k, v)| format!("{}=\"{}\"", k, v.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")))
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+fn inc(name: &'static str, labels: String) {
+    let mut registry = metrics().lock().unwrap();
+    *registry.counters.entry(name).or_default().entry(labels).or_default() += 1;
+}
+
+fn observe(name: &'static str, labels: String, elapsed: Duration) {
+    let mut registry = metrics().lock().unwrap();
+    registry.histograms.entry(name).or_default().entry(labels).or_default().observe(elapsed.as_secs_f64());
+}
+
+/// Collapses a request path into a route label without ids or unknown paths
+pub fn route(url: &str) -> String {
+    if url.starts_with("/api/nodex/") {
+        return match url.rsplit('/').next() {
+            Some(action) => format!("/api/nodex/{{pid}}/{}", action),
+            None => format!("other"),
+        };
+    }
+    if url.starts_with("/api/jobs/") {
+        return match url.trim_start_matches("/api/jobs/").split_once('/') {
+            Some((_oid, action)) => format!("/api/jobs/{{oid}}/{}", action),
+            None => format!("/api/jobs/{{oid}}"),
+        };
+    }
+    if url.starts_with("/api/artifacts/") {
+        return format!("/api/artifacts/{{id}}");
+    }
+    if url.starts_with("/api/mesh/") {
+        return match url.trim_start_matches("/api/mesh/").split_once('/') {
+            Some((_pid, path)) => match route(format!("/{}", path).as_str()).as_str() {
+                "other" => format!("other"),
+                forwarded => format!("/api/mesh/{{pid}}{}", forwarded),
+            },
+            None => format!("other"),
+        };
+    }
+    for known in ROUTES.iter() {
+        if url == *known || (*known != "/" && url.starts_with(known)) {
+            return known.to_string();
+        }
+    }
+    return format!("other");
+}
+
+/// Records a served HTTP request
+pub fn http_request(url: &str, method: &str, status: u16, elapsed: Duration) {
+    let route = route(url);
+    let status = format!("{}", status);
+    inc("thalamus_http_requests_total", labels(&[("route", route.as_str()), ("method", method), ("status", status.as_str())]));
+    if status.starts_with('4') || status.starts_with('5') {
+        inc("thalamus_http_errors_total", labels(&[("route", route.as_str()), ("status", status.as_str())]));
+    }
+    observe("thalamus_http_request_duration_seconds", labels(&[("route", route.as_str())]), elapsed);
+}
+
+/// Records a model request by its traffic key, e.g. "whisper:base" or "tts"
+pub fn service_request(key: &str, ok: bool, elapsed: Duration) {
+    let (service, model) = split_key(key);
+    let outcome = if ok { "ok" } else { "error" };
+    inc("thalamus_service_requests_total", labels(&[("service", service), ("model", model), ("outcome", outcome)]));
+    observe("thalamus_service_request_duration_seconds", labels(&[("service", service), ("model", model)]), elapsed);
+}
+
+/// Records a child process (whisper, llama, srgan, ffmpeg, ...) that couldn't be started
+pub fn spawn_failure(tool: &str) {
+    inc("thalamus_process_spawn_failures_total", labels(&[("tool", tool)]));
+}
+
+/// Records a model download: "ok" or "failed"
+pub fn download(outcome: &str) {
+    inc("thalamus_model_downloads_total", labels(&[("outcome", outcome)]));
+}
+
+/// Records a hash check: "passed", "failed", "skipped" when the size matches, or "unknown" when no hash is on record
+pub fn hash_check(outcome: &str) {
+    inc("thalamus_hash_checks_total", labels(&[("outcome", outcome)]));
+}
+
+/// Records a span dropped because the trace exporter fell behind
+pub fn trace_span_dropped() {
+    inc("thalamus_trace_spans_dropped_total", labels(&[]));
+}
+
+fn split_key(key: &str) -> (&str, &str) {
+    match key.split_once(':') {
+        Some((service, model)) => (service, model),
+        None => (key, ""),
+    }
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn opentts_available() -> bool {
+    let client = reqwest::blocking::Client::new();
+    match client.get("http://localhost:5500/api/voices").timeout(Duration::from_secs(2)).send() {
+        Ok(response) => response.status().is_success(),
+        Err(_) => false,
+    }
+}
+
+/// Renders every metric in the Prometheus text format
+pub fn render(thalamus: Arc<Mutex<crate::ThalamusClient>>) -> String {
+    let mut out = String::new();
+
+    let registry = metrics().lock().unwrap();
+    for (name, series) in registry.counters.iter() {
+        header(&mut out, name, help(name), "counter");
+        for (labels, value) in series.iter() {
+            let _ = writeln!(out, "{}{{{}}} {}", name, labels, value);
+        }
+    }
+    for (name, series) in registry.histograms.iter() {
+        header(&mut out, name, help(name), "histogram");
+        for (labels, histogram) in series.iter() {
+            for (i, bound) in BUCKETS.iter().enumerate() {
+                let _ = writeln!(out, "{}_bucket{{{},le=\"{}\"}} {}", name, labels, bound, histogram.buckets[i]);
+            }
+            let _ = writeln!(out, "{}_bucket{{{},le=\"+Inf\"}} {}", name, labels, histogram.count);
+            let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, histogram.sum);
+            let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, histogram.count);
+        }
+    }
+    std::mem::drop(registry);
+
+    header(&mut out, "thalamus_jobs_in_flight", "Model requests running or waiting on this node", "gauge");
+    for (key, stats) in crate::thalamus::traffic::snapshot() {
+        let (service, model) = split_key(key.as_str());
+        let _ = writeln!(out, "thalamus_jobs_in_flight{{{}}} {}", labels(&[("service", service), ("model", model)]), stats.queue_depth);
+    }
+
+    let thalamus_x = thalamus.lock().unwrap();
+    let known = thalamus_x.nodes.len();
+    let online = thalamus_x.nodes.iter().filter(|n| n.is_online).count();
+    std::mem::drop(thalamus_x);
+
+    let depth = crate::thalamus::jobs::depth();
+    header(&mut out, "thalamus_jobs_queued", "Jobs waiting in the scheduler, by service", "gauge");
+    for (service, (queued, _)) in depth.iter() {
+        let _ = writeln!(out, "thalamus_jobs_queued{{{}}} {}", labels(&[("service", service.as_str())]), queued);
+    }
+    header(&mut out, "thalamus_jobs_running", "Jobs running in the scheduler, by service", "gauge");
+    for (service, (_, running)) in depth.iter() {
+        let _ = writeln!(out, "thalamus_jobs_running{{{}}} {}", labels(&[("service", service.as_str())]), running);
+    }
+
+    let (artifacts, artifact_bytes) = crate::thalamus::artifacts::usage();
+    header(&mut out, "thalamus_artifacts", "Job outputs in the artifact store", "gauge");
+    let _ = writeln!(out, "thalamus_artifacts {}", artifacts);
+    header(&mut out, "thalamus_artifacts_bytes", "Size of the artifact store", "gauge");
+    let _ = writeln!(out, "thalamus_artifacts_bytes {}", artifact_bytes);
+
+    header(&mut out, "thalamus_peers_known", "Nodes in the mesh this node knows about", "gauge");
+    let _ = writeln!(out, "thalamus_peers_known {}", known);
+    header(&mut out, "thalamus_peers_online", "Known nodes currently online", "gauge");
+    let _ = writeln!(out, "thalamus_peers_online {}", online);
+
+    header(&mut out, "thalamus_opentts_available", "1 if the local OpenTTS server answers", "gauge");
+    let _ = writeln!(out, "thalamus_opentts_available {}", if opentts_available() { 1 } else { 0 });
+
+    return out;
+}
@@ -43,6 +43,10 @@ pub fn handle(request: &Request) -> Result<Response, crate::thalamus::http::Erro
         return Ok(crate::thalamus::services::image::srgan::handle(request)?);
     }
 
+    if request.url().contains("/api/services/image/yolo"){
+        return Ok(crate::thalamus::services::image::yolo::handle(request)?);
+    }
+
     if request.url().contains("/api/services/image/nst"){
         return Ok(crate::thalamus::services::image::nst::handle(request)?);
     }
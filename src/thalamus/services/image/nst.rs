@@ -24,7 +24,6 @@ use serde::{Serialize, Deserialize};
 use rouille::post_input;
 use rouille::Request;
 use rouille::Response;
-use std::thread;
 
 use titlecase::titlecase;
 
@@ -61,12 +60,15 @@ pub fn handle(request: &Request) -> Result<Response, crate::thalamus::http::Erro
         if input.image_id.contains("oid:") {
             let oid = input.image_id.replace("oid:", "");
             if Path::new(format!("/opt/thalamus/files/{}", oid).as_str()).exists(){
-                thread::Builder::new().name("nst_thread".to_string()).spawn(move || {
-                    match run(&selected_style, format!("/opt/thalamus/files/{}", oid).as_str(), oid, input.nst_style){
-                        Ok(_) => (),
-                        Err(e) => log::error!("{}", e),
-                    }
-                })?;
+                // Thousands of optimizer steps; always queue and let the caller follow the job
+                let job_oid = crate::thalamus::jobs::prepare()?;
+                let priority = request.get_param("priority").and_then(|p| p.parse::<i64>().ok());
+                let job = crate::thalamus::jobs::submit(job_oid, crate::thalamus::jobs::Spec::Nst {
+                    style: selected_style,
+                    style_name: input.nst_style,
+                    input: format!("/opt/thalamus/files/{}", oid),
+                }, priority);
+                return Ok(crate::thalamus::jobs::accepted(&job));
             }
         }
 
@@ -88,7 +90,8 @@ fn style_loss(m1: &Tensor, m2: &Tensor) -> Tensor {
     gram_matrix(m1).mse_loss(&gram_matrix(m2), tch::Reduction::Mean)
 }
 
-pub fn run(style_img: &str, content_img: &str, _oid: String, _style: String) -> Result<(), crate::thalamus::services::Error> {
+/// Runs in the job `oid`, saving every 1000th step in its directory; returns the final image
+pub fn run(style_img: &str, content_img: &str, oid: String, _style: String) -> Result<String, crate::thalamus::services::Error> {
 
     log::info!("NST");
     log::info!("style image: {:?}", style_img);
@@ -117,6 +120,10 @@ pub fn run(style_img: &str, content_img: &str, _oid: String, _style: String) ->
     let mut opt = nn::Adam::default().build(&vs, LEARNING_RATE)?;
 
     for step_idx in 1..(1 + TOTAL_STEPS) {
+        if crate::thalamus::jobs::cancelled() {
+            log::info!("NST cancelled at step {}", step_idx);
+            return Err(format!("cancelled at step {}", step_idx).into());
+        }
         let input_layers = net.forward_all_t(&input_var, false, Some(max_layer));
         let style_loss: Tensor =
             STYLE_INDEXES.iter().map(|&i| style_loss(&input_layers[i], &style_layers[i])).sum();
@@ -129,10 +136,11 @@ pub fn run(style_img: &str, content_img: &str, _oid: String, _style: String) ->
         // log::info!("{} {}", step_idx, f64::from(loss.clone(&loss)));
         if step_idx % 1000 == 0 {
             // log::info!("{} {}", step_idx, f64::from(loss));
-            imagenet::save_image(&input_var, &format!("/opt/thalamus/files/out{}.jpg", step_idx))?;
+            let step_path = crate::thalamus::jobs::path(oid.as_str(), format!("out{}.jpg", step_idx).as_str());
+            imagenet::save_image(&input_var, &step_path)?;
 
 
-            let mut file = File::open(format!("/opt/thalamus/files/out{}.jpg", step_idx))?;
+            let mut file = File::open(step_path.as_str())?;
             let mut buf = Vec::new();
             file.read_to_end(&mut buf)?;
 
@@ -146,7 +154,7 @@ pub fn run(style_img: &str, content_img: &str, _oid: String, _style: String) ->
         }
     }
 
-    Ok(())
+    Ok(crate::thalamus::jobs::path(oid.as_str(), format!("out{}.jpg", TOTAL_STEPS).as_str()))
 }
 
 
@@ -13,8 +13,6 @@
 use std::path::Path;
 use rouille::Request;
 use rouille::Response;
-use std::fs::File;
-use std::io::Write;
 // use std::io::Read;
 use rouille::post_input;
 use rouille::input::post::BufferedFile;
@@ -26,29 +24,20 @@ pub fn handle(request: &Request) -> Result<Response, crate::thalamus::http::Erro
             input_file: BufferedFile,
         })?;
 
-        let xyz = input.input_file.filename.unwrap();
+        // Keep only the file name so uploads stay inside the job directory
+        let xyz = match input.input_file.filename.as_ref().and_then(|f| Path::new(f).file_name()) {
+            Some(name) => name.to_string_lossy().to_string(),
+            None => format!("input.jpg"),
+        };
 
-        let mime_type = crate::thalamus::tools::find_mimetype(&xyz.clone());
+        let oid = crate::thalamus::jobs::prepare()?;
+        let tmp_file_path = crate::thalamus::jobs::path(oid.as_str(), xyz.as_str());
+        crate::trace::write_file(tmp_file_path.as_str(), &input.input_file.data)?;
 
-        let tmp_file_path = format!("/opt/thalamus/tmp/srgan/{}", xyz.clone());
-        let out_file_path = format!("/opt/thalamus/tmp/srgan/SRGAN_{}", xyz.clone());
-        let mut file = File::create(tmp_file_path.clone())?;
-        file.write_all(&input.input_file.data)?;
-
-        match crate::thalamus::tools::srgan(tmp_file_path.as_str(), out_file_path.clone().as_str()){
-            Ok(_) => {
-
-                let outfile = File::open(out_file_path.as_str()).unwrap();
-
-                let response = Response::from_file(mime_type, outfile);
-                return Ok(response);
-            },
-            Err(e) => {
-                log::error!("{:?}", e);
-            }
-        }
-
-        return Ok(Response::empty_404());
+        return Ok(crate::thalamus::jobs::respond(request, oid, crate::thalamus::jobs::Spec::Srgan {
+            filename: xyz,
+            input: tmp_file_path,
+        }));
 
 
     }
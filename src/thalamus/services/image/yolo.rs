@@ -11,19 +11,18 @@
 // Yolo9k CoreML https://github.com/seph14/Cinder-Yolo9k/tree/master
 
 // use std::fs;
-use std::fs::File;
-use std::io::{Write};
 use rouille::Request;
 use rouille::Response;
 use rouille::input::post::BufferedFile;
 use rouille::post_input;
 use std::path::Path;
-use std::time::{SystemTime, UNIX_EPOCH};
 use std::process::{Command, Stdio};
+use std::os::unix::process::CommandExt;
 use serde::{Serialize, Deserialize};
 
 
 pub fn yolov7(file_path: String) -> Result<String, String> {
+    let _span = crate::trace::enter("yolov7 process");
 
     #[cfg(target_os = "linux")]{
         let child = Command::new("/opt/thalamus/bin/yolov7")
@@ -32,12 +31,21 @@ pub fn yolov7(file_path: String) -> Result<String, String> {
         .arg("640")
         .arg("640")
         .stdout(Stdio::piped())
-        .spawn()
-        .expect("failed to execute child");
+        .process_group(0)
+        .spawn();
+        let child = match child {
+            Ok(child) => child,
+            Err(e) => {
+                crate::thalamus::metrics::spawn_failure("yolov7");
+                return Err(format!("yolo_v7_error: failed to start /opt/thalamus/bin/yolov7: {}", e));
+            }
+        };
+        let _watch = crate::thalamus::jobs::watch(&child);
     
-        let output = child
-            .wait_with_output()
-            .expect("failed to wait on child");
+        let output = match child.wait_with_output() {
+            Ok(output) => output,
+            Err(e) => return Err(format!("yolo_v7_error: failed to wait on yolov7: {}", e)),
+        };
         let yolo = String::from_utf8_lossy(&output.stdout).to_string().replace("\n", "");
     
         if yolo.to_lowercase().contains("error") || yolo.len() == 0 {
@@ -54,12 +62,21 @@ pub fn yolov7(file_path: String) -> Result<String, String> {
         let child = Command::new("/opt/thalamus/bin/yolov7")
         .arg(file_path)
         .stdout(Stdio::piped())
-        .spawn()
-        .expect("failed to execute child");
+        .process_group(0)
+        .spawn();
+        let child = match child {
+            Ok(child) => child,
+            Err(e) => {
+                crate::thalamus::metrics::spawn_failure("yolov7");
+                return Err(format!("yolo_v7_error: failed to start /opt/thalamus/bin/yolov7: {}", e));
+            }
+        };
+        let _watch = crate::thalamus::jobs::watch(&child);
     
-        let output = child
-            .wait_with_output()
-            .expect("failed to wait on child");
+        let output = match child.wait_with_output() {
+            Ok(output) => output,
+            Err(e) => return Err(format!("yolo_v7_error: failed to wait on yolov7: {}", e)),
+        };
         let yolo = String::from_utf8_lossy(&output.stdout).to_string().replace("\n", "");
     
         if yolo.to_lowercase().contains("error") || yolo.len() == 0 {
@@ -139,20 +156,18 @@ pub fn install() -> Result<(), crate::thalamus::setup::Error> {
 
 pub fn handle(request: &Request) -> Result<Response, crate::thalamus::http::Error> {
     
-    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+    if request.url() == "/api/services/image/yolo/v7" {
+        let input = post_input!(request, {
+            image_file: BufferedFile,
+        })?;
 
-    let input = post_input!(request, {
-        image_file: BufferedFile,
-    })?;
+        let oid = crate::thalamus::jobs::prepare()?;
+        let tmp_file_path = crate::thalamus::jobs::path(oid.as_str(), "input.jpg");
+        crate::trace::write_file(tmp_file_path.as_str(), &input.image_file.data)?;
 
-    let tmp_file_path = format!("/opt/thalamus/tmp/{}.jpg", timestamp.clone());
-    let mut file = File::create(tmp_file_path.clone())?;
-    file.write_all(&input.image_file.data)?;
-    
-    if request.url() == "/api/services/image/yolo/v7" {
-        let yolo = yolov7(tmp_file_path)?;
-        let reply: YoloV7Output = serde_json::from_str(&yolo)?;
-        return Ok(Response::json(&reply));
+        return Ok(crate::thalamus::jobs::respond(request, oid, crate::thalamus::jobs::Spec::Yolov7 {
+            input: tmp_file_path,
+        }));
     }
     
     return Ok(Response::empty_404());
@@ -19,9 +19,54 @@ use rouille::Response;
 
 
 use rouille::post_input;
+use serde::{Serialize, Deserialize};
 
 use std::path::Path;
 
+/// Llama models, sent as the `model` field
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LlamaModel {
+    #[serde(rename = "7B")]
+    Llama7B,
+    #[serde(rename = "13B")]
+    Llama13B,
+    #[serde(rename = "30B")]
+    Llama30B,
+    #[serde(rename = "65B")]
+    Llama65B,
+}
+impl LlamaModel {
+    pub const ALL: [LlamaModel; 4] = [LlamaModel::Llama7B, LlamaModel::Llama13B, LlamaModel::Llama30B, LlamaModel::Llama65B];
+
+    pub fn is_installed(&self) -> bool {
+        Path::new(format!("/opt/thalamus/models/llama/{}/ggml-model-q4_0.gguf", self.as_str()).as_str()).exists()
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LlamaModel::Llama7B => "7B",
+            LlamaModel::Llama13B => "13B",
+            LlamaModel::Llama30B => "30B",
+            LlamaModel::Llama65B => "65B",
+        }
+    }
+}
+impl std::fmt::Display for LlamaModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+impl std::str::FromStr for LlamaModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<LlamaModel, String> {
+        match LlamaModel::ALL.iter().find(|m| m.as_str() == s) {
+            Some(model) => Ok(*model),
+            None => Err(format!("unknown llama model '{}', expected one of 7B, 13B, 30B, 65B", s)),
+        }
+    }
+}
+
 // curl -d "prompt=tell me about abe lincoln&model=7B" -X POST http://172.16.0.15:8050/api/services/llama
 pub fn handle(request: &Request) -> Result<Response, crate::thalamus::http::Error> {
     
@@ -32,15 +77,25 @@ pub fn handle(request: &Request) -> Result<Response, crate::thalamus::http::Erro
             model: String, // 7B
         })?;
 
-        match crate::thalamus::tools::llama(input.model.as_str(), input.prompt.as_str()){
-            Ok(output) => {
-                return Ok(Response::text(output));
-            },
-            Err(e) => {
-                return Err(e.into());
-            }
+        let model = match input.model.parse::<LlamaModel>() {
+            Ok(model) => model,
+            Err(e) => return Ok(crate::thalamus::http::ErrorReply::bad_request(e)),
+        };
+        if !model.is_installed() {
+            return Ok(crate::thalamus::http::ErrorReply::not_capable(format!("llama model {} is not installed", model)));
+        }
+
+        match crate::thalamus::hardware::admission(format!("llama:{}", model).as_str()) {
+            Ok(_) => {},
+            Err(e) => return Ok(crate::thalamus::http::ErrorReply::not_capable(e)),
         }
 
+        let oid = crate::thalamus::jobs::prepare()?;
+        return Ok(crate::thalamus::jobs::respond(request, oid, crate::thalamus::jobs::Spec::Llama {
+            model: model,
+            prompt: input.prompt,
+        }));
+
 
     }
     
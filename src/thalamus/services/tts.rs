@@ -26,7 +26,12 @@ pub fn handle(request: &Request) -> Result<Response, crate::thalamus::http::Erro
         let input = request.get_param("text").unwrap();
         let primary = request.get_param("primary").unwrap();
         let fallback = request.get_param("fallback").unwrap();
-        return Ok(Response::from_data("audio/wav", crate::thalamus::services::tts::get(input, primary.as_str(), fallback.as_str()).unwrap()));
+        let oid = crate::thalamus::jobs::prepare()?;
+        return Ok(crate::thalamus::jobs::respond(request, oid, crate::thalamus::jobs::Spec::Tts {
+            text: input,
+            primary: primary,
+            fallback: fallback,
+        }));
     }
 
     if request.url() == "/api/services/tts/voices" {
@@ -14,69 +14,54 @@ use rouille::post_input;
 use serde::{Serialize, Deserialize};
 
 use std::path::Path;
-use std::fs::File;
 
-use std::time::{SystemTime, UNIX_EPOCH};
 
-use std::io::Write;
 
 
 
 
 // /opt/thalamus/bin/whisper -m /opt/thalamus/models/ggml-* -f ./output.wav -otxt
-pub fn whisper(file_path: String, method: &str) -> Result<String, crate::thalamus::services::Error> {
+pub fn whisper(file_path: String, model: WhisperModel, language: Option<&str>, format: WhisperFormat) -> Result<String, crate::thalamus::services::Error> {
 
     // Force all input to become wav@16khz
     match crate::thalamus::tools::wav_to_16000(file_path.clone()){
         Ok(_) => (),
         Err(e) => return Err(crate::thalamus::services::Error::from(e))
     };
+    stop_if_cancelled("ffmpeg")?;
 
     // Execute Whisper
-    match method {
-        "tiny" => log::warn!("{}", crate::thalamus::tools::whisper("tiny", file_path.as_str())?),
-        "base" => log::warn!("{}", crate::thalamus::tools::whisper("base", file_path.as_str())?),
-        "medium" => log::warn!("{}", crate::thalamus::tools::whisper("medium", file_path.as_str())?),
-        "large" => log::warn!("{}", crate::thalamus::tools::whisper("large", file_path.as_str())?),
-        &_ => log::warn!("{}", crate::thalamus::tools::whisper("tiny", file_path.as_str())?)
-    };
+    log::warn!("{}", crate::thalamus::tools::whisper(model.as_str(), file_path.as_str(), language, format.as_str())?);
+    stop_if_cancelled("whisper")?;
     
     // Copy the results to memory
-    let data = std::fs::read_to_string(format!("{}.16.wav.txt", file_path).as_str())?;
+    let data = String::from_utf8_lossy(&crate::trace::read_file(format!("{}.16.wav.{}", file_path, format.as_str()).as_str())?).to_string();
 
-    // Cleanup
-    // thread::spawn(move || {
-    //     thread::sleep(Duration::from_millis(60000));
-    //     crate::thalamus::tools::rm(format!("{}*", file_path).as_str()).unwrap();
-    // });
+    // Cleanup: the .16.wav and transcript sit in the job's directory, removed when the job finishes
 
     // Return the results
     return Ok(data);
 }
 
 
-pub fn whisper_vwav(file_path: String, method: &str) -> Result<String, crate::thalamus::services::Error> {
+pub fn whisper_vwav(file_path: String, model: WhisperModel, language: Option<&str>) -> Result<String, crate::thalamus::services::Error> {
 
     // Force all input to become wav@16khz
     match crate::thalamus::tools::wav_to_16000(file_path.clone()){
         Ok(_) => (),
         Err(e) => return Err(crate::thalamus::services::Error::from(e))
     };
+    stop_if_cancelled("ffmpeg")?;
 
 
 
     // Execute Whisper
-    match method {
-        "tiny" => log::warn!("{}", crate::thalamus::tools::whisper_owts("tiny", file_path.as_str())?),
-        "base" => log::warn!("{}", crate::thalamus::tools::whisper_owts("base", file_path.as_str())?),
-        "medium" => log::warn!("{}", crate::thalamus::tools::whisper_owts("medium", file_path.as_str())?),
-        "large" => log::warn!("{}", crate::thalamus::tools::whisper_owts("large", file_path.as_str())?),
-        &_ => log::warn!("{}", crate::thalamus::tools::whisper_owts("tiny", file_path.as_str())?)
-    };
+    log::warn!("{}", crate::thalamus::tools::whisper_owts(model.as_str(), file_path.as_str(), language)?);
+    stop_if_cancelled("whisper")?;
     
     // linux only patch
 
-    crate::thalamus::services::whisper::patch_whisper_wts(format!("{}.16.wav.wts", file_path.clone())).unwrap();
+    crate::thalamus::services::whisper::patch_whisper_wts(format!("{}.16.wav.wts", file_path.clone()))?;
     
     
     match crate::thalamus::tools::mark_as_executable(format!("{}.16.wav.wts", file_path.clone()).as_str()){
@@ -93,6 +78,7 @@ pub fn whisper_vwav(file_path: String, method: &str) -> Result<String, crate::th
             log::error!("{}", e);
         },
     }
+    stop_if_cancelled("the wts script")?;
 
 
   
@@ -100,6 +86,14 @@ pub fn whisper_vwav(file_path: String, method: &str) -> Result<String, crate::th
     return Ok(format!("{}.16.wav.mp4", file_path.clone()));
 }
 
+// A cancelled job's children were killed, so whatever they left behind is incomplete
+fn stop_if_cancelled(tool: &str) -> Result<(), crate::thalamus::services::Error> {
+    if crate::thalamus::jobs::cancelled() {
+        return Err(format!("cancelled after {}", tool).into());
+    }
+    return Ok(());
+}
+
 // Patch linux whisper WTS files
 pub fn patch_whisper_wts(file_path: String) -> Result<(), crate::thalamus::services::Error>{
     let mut data = std::fs::read_to_string(format!("{}", file_path).as_str())?;
@@ -255,6 +249,88 @@ pub struct STTReply {
     pub response_type: Option<String>,
 }
 
+/// Whisper models, sent as the `method` field
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum WhisperModel {
+    Tiny,
+    Base,
+    Medium,
+    Large,
+}
+impl WhisperModel {
+    pub const ALL: [WhisperModel; 4] = [WhisperModel::Tiny, WhisperModel::Base, WhisperModel::Medium, WhisperModel::Large];
+
+    pub fn is_installed(&self) -> bool {
+        Path::new(format!("/opt/thalamus/models/ggml-{}.bin", self.as_str()).as_str()).exists()
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WhisperModel::Tiny => "tiny",
+            WhisperModel::Base => "base",
+            WhisperModel::Medium => "medium",
+            WhisperModel::Large => "large",
+        }
+    }
+}
+impl std::fmt::Display for WhisperModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+impl std::str::FromStr for WhisperModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<WhisperModel, String> {
+        match WhisperModel::ALL.iter().find(|m| m.as_str() == s) {
+            Some(model) => Ok(*model),
+            None => Err(format!("unknown whisper model '{}', expected one of tiny, base, medium, large", s)),
+        }
+    }
+}
+
+/// Transcript formats, sent as the `format` field
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum WhisperFormat {
+    Txt,
+    Srt,
+    Vtt,
+}
+impl WhisperFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WhisperFormat::Txt => "txt",
+            WhisperFormat::Srt => "srt",
+            WhisperFormat::Vtt => "vtt",
+        }
+    }
+}
+impl Default for WhisperFormat {
+    fn default() -> WhisperFormat {
+        WhisperFormat::Txt
+    }
+}
+impl std::fmt::Display for WhisperFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+impl std::str::FromStr for WhisperFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<WhisperFormat, String> {
+        match s {
+            "txt" => Ok(WhisperFormat::Txt),
+            "srt" => Ok(WhisperFormat::Srt),
+            "vtt" => Ok(WhisperFormat::Vtt),
+            _ => Err(format!("unknown whisper format '{}', expected one of txt, srt, vtt", s)),
+        }
+    }
+}
+
+
 
 
 pub fn handle(request: &Request) -> Result<Response, crate::thalamus::http::Error> {
@@ -264,52 +340,77 @@ pub fn handle(request: &Request) -> Result<Response, crate::thalamus::http::Erro
     
     if request.url() == "/api/services/whisper" {
 
-        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
-
         let input = post_input!(request, {
             speech: BufferedFile,
-            method: String
+            method: String,
+            language: Option<String>,
+            format: Option<String>
         })?;
 
-        let tmp_file_path = format!("/opt/thalamus/tmp/{}.wav", timestamp.clone());
-        let mut file = File::create(tmp_file_path.clone())?;
-        file.write_all(&input.speech.data)?;
-
-        let stt = whisper(tmp_file_path, input.method.as_str())?;
-
-        let reply = STTReply{
-            text: stt,
-            time: timestamp as f64,
-            response_type: None
+        let model = match input.method.parse::<WhisperModel>() {
+            Ok(model) => model,
+            Err(e) => return Ok(crate::thalamus::http::ErrorReply::bad_request(e)),
+        };
+        let format = match input.format {
+            Some(format) => match format.parse::<WhisperFormat>() {
+                Ok(format) => format,
+                Err(e) => return Ok(crate::thalamus::http::ErrorReply::bad_request(e)),
+            },
+            None => WhisperFormat::Txt,
         };
+        if !model.is_installed() {
+            return Ok(crate::thalamus::http::ErrorReply::not_capable(format!("whisper model {} is not installed", model)));
+        }
+
+        match crate::thalamus::hardware::admission(format!("whisper:{}", model).as_str()) {
+            Ok(_) => {},
+            Err(e) => return Ok(crate::thalamus::http::ErrorReply::not_capable(e)),
+        }
 
-        log::info!("{}", reply.text.clone());
+        let oid = crate::thalamus::jobs::prepare()?;
+        let input_path = crate::thalamus::jobs::path(oid.as_str(), "input.wav");
+        crate::trace::write_file(input_path.as_str(), &input.speech.data)?;
 
-        return Ok(Response::json(&reply));
+        return Ok(crate::thalamus::jobs::respond(request, oid, crate::thalamus::jobs::Spec::Whisper {
+            model: model,
+            language: input.language,
+            format: format,
+            input: input_path,
+        }));
       
     }
 
 
     if request.url() == "/api/services/whisper/vwav"{
 
-        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
-
         let input = post_input!(request, {
             speech: BufferedFile,
-            method: String
+            method: String,
+            language: Option<String>
         })?;
 
-        let tmp_file_path = format!("/opt/thalamus/tmp/{}.wav", timestamp.clone());
-        let mut file = File::create(tmp_file_path.clone())?;
-        file.write_all(&input.speech.data)?;
+        let model = match input.method.parse::<WhisperModel>() {
+            Ok(model) => model,
+            Err(e) => return Ok(crate::thalamus::http::ErrorReply::bad_request(e)),
+        };
+        if !model.is_installed() {
+            return Ok(crate::thalamus::http::ErrorReply::not_capable(format!("whisper model {} is not installed", model)));
+        }
 
-        let output_path = whisper_vwav(tmp_file_path, input.method.as_str())?;
+        match crate::thalamus::hardware::admission(format!("whisper:{}", model).as_str()) {
+            Ok(_) => {},
+            Err(e) => return Ok(crate::thalamus::http::ErrorReply::not_capable(e)),
+        }
 
-        let outfile = File::open(output_path.as_str()).unwrap();
+        let oid = crate::thalamus::jobs::prepare()?;
+        let input_path = crate::thalamus::jobs::path(oid.as_str(), "input.wav");
+        crate::trace::write_file(input_path.as_str(), &input.speech.data)?;
 
-        let response = Response::from_file("video/mp4", outfile);
-        return Ok(response);
-       
+        return Ok(crate::thalamus::jobs::respond(request, oid, crate::thalamus::jobs::Spec::WhisperVwav {
+            model: model,
+            language: input.language,
+            input: input_path,
+        }));
                 
     }
 
@@ -446,10 +446,48 @@ pub fn update_linux_service_file(args: crate::Args){
     data.push_str("After=systemd-user-sessions.service\n");
     data.push_str("After=network-online.target\n\n");
     data.push_str("[Service]\n");
+    let mut rendezvous = String::new();
+    for point in args.rendezvous.iter() {
+        rendezvous.push_str(format!(" --rendezvous {}", point).as_str());
+    }
+    for peer in args.bootstrap.iter() {
+        rendezvous.push_str(format!(" --bootstrap {}", peer).as_str());
+    }
+    if args.relay {
+        rendezvous.push_str(" --relay");
+    }
+    if args.benchmark_interval != 24 {
+        rendezvous.push_str(format!(" --benchmark-interval {}", args.benchmark_interval).as_str());
+    }
+    if args.storage.as_str() != "json" {
+        rendezvous.push_str(format!(" --storage {}", args.storage).as_str());
+    }
+    if args.log_level.as_str() != "info" {
+        rendezvous.push_str(format!(" --log-level {}", args.log_level).as_str());
+    }
+    if args.log_format.as_str() != "logfmt" {
+        rendezvous.push_str(format!(" --log-format {}", args.log_format).as_str());
+    }
+    if args.log_max_size != 50 {
+        rendezvous.push_str(format!(" --log-max-size {}", args.log_max_size).as_str());
+    }
+    if args.log_keep != 10 {
+        rendezvous.push_str(format!(" --log-keep {}", args.log_keep).as_str());
+    }
+    if args.artifact_ttl != 24 {
+        rendezvous.push_str(format!(" --artifact-ttl {}", args.artifact_ttl).as_str());
+    }
+    if args.artifact_max_size != 2048 {
+        rendezvous.push_str(format!(" --artifact-max-size {}", args.artifact_max_size).as_str());
+    }
+    match &args.otlp_endpoint {
+        Some(endpoint) => rendezvous.push_str(format!(" --otlp-endpoint {}", endpoint).as_str()),
+        None => {}
+    }
     if args.encrypt{
-        data.push_str(format!("ExecStart=/usr/bin/env LIBTORCH=/opt/thalamus/libtorch LD_LIBRARY_PATH=/opt/thalamus/libtorch/lib: /opt/thalamus/bin/thalamus --lang {} --max-threads {} --http-port {} --p2p-port {} --encrypt --key {}\n", args.lang, args.max_threads, args.www_port, args.p2p_port, args.key).as_str());
+        data.push_str(format!("ExecStart=/usr/bin/env LIBTORCH=/opt/thalamus/libtorch LD_LIBRARY_PATH=/opt/thalamus/libtorch/lib: /opt/thalamus/bin/thalamus --lang {} --max-threads {} --http-port {} --p2p-port {} --encrypt --key {}{}\n", args.lang, args.max_threads, args.www_port, args.p2p_port, args.key, rendezvous).as_str());
     } else {
-        data.push_str(format!("ExecStart=/usr/bin/env LIBTORCH=/opt/thalamus/libtorch LD_LIBRARY_PATH=/opt/thalamus/libtorch/lib: /opt/thalamus/bin/thalamus --lang {} --max-threads {} --http-port {} --p2p-port {} --key {}\n", args.lang, args.max_threads, args.www_port, args.p2p_port, args.key).as_str());
+        data.push_str(format!("ExecStart=/usr/bin/env LIBTORCH=/opt/thalamus/libtorch LD_LIBRARY_PATH=/opt/thalamus/libtorch/lib: /opt/thalamus/bin/thalamus --lang {} --max-threads {} --http-port {} --p2p-port {} --key {}{}\n", args.lang, args.max_threads, args.www_port, args.p2p_port, args.key, rendezvous).as_str());
     }
     data.push_str("TimeoutSec=30\n");
     data.push_str("Restart=on-failure\n");
@@ -12,6 +12,7 @@
 use std::fs::File;
 use std::io::Write;
 use std::process::{Command, Stdio};
+use std::os::unix::process::CommandExt;
 use error_chain::error_chain;
 use sha2::{Sha256, Digest};
 use std::{io, fs};
@@ -28,6 +29,17 @@ error_chain! {
 }
 
 
+// A child that failed to start becomes an error, counted against `tool` in the metrics
+fn spawned(child: io::Result<std::process::Child>, tool: &str) -> Result<std::process::Child> {
+    match child {
+        Ok(child) => return Ok(child),
+        Err(e) => {
+            crate::thalamus::metrics::spawn_failure(tool);
+            return Err(e.into());
+        }
+    }
+}
+
 pub fn hash_check(file_path: &str) -> Result<String>{
     let f = File::open(file_path)?;
 
@@ -59,13 +71,12 @@ pub fn dbash(command: &str) -> Result<String>{
     .arg("-c")
     .arg(command)
     .stdout(Stdio::piped())
-    .spawn()
-    .expect("failed to execute child");
+    .spawn();
+    let child = spawned(child, "sudo")?;
 
 
     let output = child
-    .wait_with_output()
-    .expect("failed to wait on child");
+    .wait_with_output()?;
 
     return Ok(String::from_utf8_lossy(&output.stdout).to_string());    
 }
@@ -75,13 +86,12 @@ pub fn bash(command: &str) -> Result<String>{
     .arg("-c")
     .arg(command)
     .stdout(Stdio::piped())
-    .spawn()
-    .expect("failed to execute child");
+    .spawn();
+    let child = spawned(child, "bash")?;
 
 
     let output = child
-    .wait_with_output()
-    .expect("failed to wait on child");
+    .wait_with_output()?;
 
     return Ok(String::from_utf8_lossy(&output.stdout).to_string());    
 }
@@ -95,13 +105,12 @@ pub fn brew_install(package: &str) -> Result<String>{
     .arg("install")
     .arg(package)
     .stdout(Stdio::piped())
-    .spawn()
-    .expect("failed to execute child");
+    .spawn();
+    let child = spawned(child, "sudo")?;
 
 
     let output = child
-    .wait_with_output()
-    .expect("failed to wait on child");
+    .wait_with_output()?;
 
     return Ok(String::from_utf8_lossy(&output.stdout).to_string());    
 }
@@ -114,13 +123,12 @@ pub fn untar(file_path: &str, directory: &str) -> Result<String>{
     .arg("--directory")
     .arg(directory)
     .stdout(Stdio::piped())
-    .spawn()
-    .expect("failed to execute child");
+    .spawn();
+    let child = spawned(child, "tar")?;
 
 
     let output = child
-    .wait_with_output()
-    .expect("failed to wait on child");
+    .wait_with_output()?;
 
     return Ok(String::from_utf8_lossy(&output.stdout).to_string());    
 }
@@ -132,13 +140,12 @@ pub fn apt_install(package: &str) -> Result<String>{
     .arg(package)
     .arg("-y")
     .stdout(Stdio::piped())
-    .spawn()
-    .expect("failed to execute child");
+    .spawn();
+    let child = spawned(child, "apt")?;
 
 
     let output = child
-    .wait_with_output()
-    .expect("failed to wait on child");
+    .wait_with_output()?;
 
     return Ok(String::from_utf8_lossy(&output.stdout).to_string());    
 }
@@ -150,13 +157,12 @@ pub fn dnf_install(package: &str) -> Result<String>{
     .arg(package)
     .arg("-y")
     .stdout(Stdio::piped())
-    .spawn()
-    .expect("failed to execute child");
+    .spawn();
+    let child = spawned(child, "dnf")?;
 
 
     let output = child
-    .wait_with_output()
-    .expect("failed to wait on child");
+    .wait_with_output()?;
 
     return Ok(String::from_utf8_lossy(&output.stdout).to_string());    
 }
@@ -169,13 +175,12 @@ pub fn brew_uninstall(package: &str) -> Result<String>{
     .arg("uninstall")
     .arg(package)
     .stdout(Stdio::piped())
-    .spawn()
-    .expect("failed to execute child");
+    .spawn();
+    let child = spawned(child, "sudo")?;
 
 
     let output = child
-    .wait_with_output()
-    .expect("failed to wait on child");
+    .wait_with_output()?;
 
     return Ok(String::from_utf8_lossy(&output.stdout).to_string());    
 }
@@ -186,13 +191,12 @@ pub fn ln(path: &str, link: &str) -> Result<String>{
     .arg(path)
     .arg(link)
     .stdout(Stdio::piped())
-    .spawn()
-    .expect("failed to execute child");
+    .spawn();
+    let child = spawned(child, "ln")?;
 
 
     let output = child
-    .wait_with_output()
-    .expect("failed to wait on child");
+    .wait_with_output()?;
 
     return Ok(String::from_utf8_lossy(&output.stdout).to_string());    
 }
@@ -202,13 +206,12 @@ pub fn mv(source: &str, destination: &str) -> Result<String>{
     .arg(source)
     .arg(destination)
     .stdout(Stdio::piped())
-    .spawn()
-    .expect("failed to execute child");
+    .spawn();
+    let child = spawned(child, "mv")?;
 
 
     let output = child
-    .wait_with_output()
-    .expect("failed to wait on child");
+    .wait_with_output()?;
 
     return Ok(String::from_utf8_lossy(&output.stdout).to_string());    
 }
@@ -218,13 +221,12 @@ pub fn cp(source: &str, destination: &str) -> Result<String>{
     .arg(source)
     .arg(destination)
     .stdout(Stdio::piped())
-    .spawn()
-    .expect("failed to execute child");
+    .spawn();
+    let child = spawned(child, "cp")?;
 
 
     let output = child
-    .wait_with_output()
-    .expect("failed to wait on child");
+    .wait_with_output()?;
 
     return Ok(String::from_utf8_lossy(&output.stdout).to_string());    
 }
@@ -235,13 +237,12 @@ pub fn launchd_bootstrap(destination: &str) -> Result<String>{
     .arg("system")
     .arg(destination)
     .stdout(Stdio::piped())
-    .spawn()
-    .expect("failed to execute child");
+    .spawn();
+    let child = spawned(child, "launchctl")?;
 
 
     let output = child
-    .wait_with_output()
-    .expect("failed to wait on child");
+    .wait_with_output()?;
 
     return Ok(String::from_utf8_lossy(&output.stdout).to_string());    
 }
@@ -252,13 +253,12 @@ pub fn launchd_bootout(destination: &str) -> Result<String>{
     .arg("system")
     .arg(destination)
     .stdout(Stdio::piped())
-    .spawn()
-    .expect("failed to execute child");
+    .spawn();
+    let child = spawned(child, "launchctl")?;
 
 
     let output = child
-    .wait_with_output()
-    .expect("failed to wait on child");
+    .wait_with_output()?;
 
     return Ok(String::from_utf8_lossy(&output.stdout).to_string());    
 }
@@ -268,13 +268,12 @@ pub fn launchd_enable(destination: &str) -> Result<String>{
     .arg("enable")
     .arg(destination)
     .stdout(Stdio::piped())
-    .spawn()
-    .expect("failed to execute child");
+    .spawn();
+    let child = spawned(child, "launchctl")?;
 
 
     let output = child
-    .wait_with_output()
-    .expect("failed to wait on child");
+    .wait_with_output()?;
 
     return Ok(String::from_utf8_lossy(&output.stdout).to_string());    
 }
@@ -285,13 +284,12 @@ pub fn launchd_kickstart(destination: &str) -> Result<String>{
     .arg("-kp")
     .arg(destination)
     .stdout(Stdio::piped())
-    .spawn()
-    .expect("failed to execute child");
+    .spawn();
+    let child = spawned(child, "launchctl")?;
 
 
     let output = child
-    .wait_with_output()
-    .expect("failed to wait on child");
+    .wait_with_output()?;
 
     return Ok(String::from_utf8_lossy(&output.stdout).to_string());    
 }
@@ -300,13 +298,12 @@ pub fn systemctl_reload() -> Result<String>{
     let child = Command::new("/bin/systemctl")
     .arg("daemon-reload")
     .stdout(Stdio::piped())
-    .spawn()
-    .expect("failed to execute child");
+    .spawn();
+    let child = spawned(child, "systemctl")?;
 
 
     let output = child
-    .wait_with_output()
-    .expect("failed to wait on child");
+    .wait_with_output()?;
 
     return Ok(String::from_utf8_lossy(&output.stdout).to_string());    
 }
@@ -316,13 +313,12 @@ pub fn systemctl_start(service_name: &str) -> Result<String>{
     .arg("start")
     .arg(service_name)
     .stdout(Stdio::piped())
-    .spawn()
-    .expect("failed to execute child");
+    .spawn();
+    let child = spawned(child, "systemctl")?;
 
 
     let output = child
-    .wait_with_output()
-    .expect("failed to wait on child");
+    .wait_with_output()?;
 
     return Ok(String::from_utf8_lossy(&output.stdout).to_string());    
 }
@@ -332,13 +328,12 @@ pub fn systemctl_stop(service_name: &str) -> Result<String>{
     .arg("stop")
     .arg(service_name)
     .stdout(Stdio::piped())
-    .spawn()
-    .expect("failed to execute child");
+    .spawn();
+    let child = spawned(child, "systemctl")?;
 
 
     let output = child
-    .wait_with_output()
-    .expect("failed to wait on child");
+    .wait_with_output()?;
 
     return Ok(String::from_utf8_lossy(&output.stdout).to_string());    
 }
@@ -348,13 +343,12 @@ pub fn systemctl_enable(service_name: &str) -> Result<String>{
     .arg("enable")
     .arg(service_name)
     .stdout(Stdio::piped())
-    .spawn()
-    .expect("failed to execute child");
+    .spawn();
+    let child = spawned(child, "systemctl")?;
 
 
     let output = child
-    .wait_with_output()
-    .expect("failed to wait on child");
+    .wait_with_output()?;
 
     return Ok(String::from_utf8_lossy(&output.stdout).to_string());    
 }
@@ -363,13 +357,12 @@ pub fn rm(path: &str) -> Result<String>{
     let child = Command::new("/bin/rm")
     .arg(path)
     .stdout(Stdio::piped())
-    .spawn()
-    .expect("failed to execute child");
+    .spawn();
+    let child = spawned(child, "rm")?;
 
 
     let output = child
-    .wait_with_output()
-    .expect("failed to wait on child");
+    .wait_with_output()?;
 
     return Ok(String::from_utf8_lossy(&output.stdout).to_string());    
 }
@@ -379,13 +372,12 @@ pub fn rmd(path: &str) -> Result<String>{
     .arg("-rf")
     .arg(path)
     .stdout(Stdio::piped())
-    .spawn()
-    .expect("failed to execute child");
+    .spawn();
+    let child = spawned(child, "rm")?;
 
 
     let output = child
-    .wait_with_output()
-    .expect("failed to wait on child");
+    .wait_with_output()?;
 
     return Ok(String::from_utf8_lossy(&output.stdout).to_string());    
 }
@@ -393,47 +385,63 @@ pub fn rmd(path: &str) -> Result<String>{
 
 
 
-pub fn whisper(model: &str, file_path: &str) -> Result<String>{
+pub fn whisper(model: &str, file_path: &str, language: Option<&str>, format: &str) -> Result<String>{
+    let mut span = crate::trace::enter("whisper process");
+    span.attr("model", model);
+    span.attr("format", format);
     
     
     let child = Command::new("/opt/thalamus/bin/whisper")
     .arg("-m")
     .arg(format!("/opt/thalamus/models/ggml-{}.bin", model))
+    .arg("-l")
+    .arg(language.unwrap_or("en"))
     .arg("-f")
     .arg(format!("{}.16.wav", file_path))
-    .arg("-otxt")
+    .arg(format!("-o{}", format))
     .stdout(Stdio::piped())
-    .spawn()
-    .expect("failed to execute child");
+    .stderr(Stdio::piped())
+    .process_group(0)
+    .spawn();
+    let child = spawned(child, "whisper")?;
+    let _watch = crate::thalamus::jobs::watch(&child);
 
 
     let output = child
-    .wait_with_output()
-    .expect("failed to wait on child");
+    .wait_with_output()?;
+    crate::logging::child_output("whisper", &output.stderr);
 
     return Ok(String::from_utf8_lossy(&output.stdout).to_string());    
     
 }
 
-pub fn whisper_owts(model: &str, file_path: &str) -> Result<String>{
+pub fn whisper_owts(model: &str, file_path: &str, language: Option<&str>) -> Result<String>{
+    let mut span = crate::trace::enter("whisper process");
+    span.attr("model", model);
+    span.attr("format", "owts");
     
     
     let child = Command::new("/opt/thalamus/bin/whisper")
     .arg("-m")
     .arg(format!("/opt/thalamus/models/ggml-{}.bin", model))
+    .arg("-l")
+    .arg(language.unwrap_or("en"))
     .arg("-f")
     .arg(format!("{}.16.wav", file_path))
     .arg("-fp")
     .arg("/opt/thalamus/fonts/courier.ttf")
     .arg("-owts")
     .stdout(Stdio::piped())
-    .spawn()
-    .expect("failed to execute child");
+    .stderr(Stdio::piped())
+    .process_group(0)
+    .spawn();
+    let child = spawned(child, "whisper")?;
+    let _watch = crate::thalamus::jobs::watch(&child);
 
 
     let output = child
-    .wait_with_output()
-    .expect("failed to wait on child");
+    .wait_with_output()?;
+    crate::logging::child_output("whisper", &output.stderr);
 
     return Ok(String::from_utf8_lossy(&output.stdout).to_string());    
     
@@ -448,13 +456,12 @@ pub fn mkdir(apath: &str) -> Result<String>{
     let child = Command::new("/bin/mkdir")
     .arg(apath)
     .stdout(Stdio::piped())
-    .spawn()
-    .expect("failed to execute child");
+    .spawn();
+    let child = spawned(child, "mkdir")?;
 
 
     let output = child
-    .wait_with_output()
-    .expect("failed to wait on child");
+    .wait_with_output()?;
 
     return Ok(String::from_utf8_lossy(&output.stdout).to_string());    
 }
@@ -465,13 +472,12 @@ pub fn fix_permissions(apath: &str) -> Result<String>{
     .arg("777")
     .arg(apath)
     .stdout(Stdio::piped())
-    .spawn()
-    .expect("failed to execute child");
+    .spawn();
+    let child = spawned(child, "chmod")?;
 
 
     let output = child
-    .wait_with_output()
-    .expect("failed to wait on child");
+    .wait_with_output()?;
 
     return Ok(String::from_utf8_lossy(&output.stdout).to_string()); 
 }
@@ -481,13 +487,12 @@ pub fn mark_as_executable(apath: &str) -> Result<String>{
     .arg("+x")
     .arg(apath)
     .stdout(Stdio::piped())
-    .spawn()
-    .expect("failed to execute child");
+    .spawn();
+    let child = spawned(child, "chmod")?;
 
 
     let output = child
-    .wait_with_output()
-    .expect("failed to wait on child");
+    .wait_with_output()?;
 
     return Ok(String::from_utf8_lossy(&output.stdout).to_string()); 
 }
@@ -496,29 +501,34 @@ pub fn sh(script: &str) -> Result<String>{
     let child = Command::new("/bin/sh")
     .arg(script)
     .stdout(Stdio::piped())
-    .spawn()
-    .expect("failed to execute child");
+    .process_group(0)
+    .spawn();
+    let child = spawned(child, "sh")?;
+    let _watch = crate::thalamus::jobs::watch(&child);
 
 
     let output = child
-    .wait_with_output()
-    .expect("failed to wait on child");
+    .wait_with_output()?;
 
     return Ok(String::from_utf8_lossy(&output.stdout).to_string()); 
 }
 
 pub fn srgan(input: &str, output: &str) -> Result<String>{
+    let _span = crate::trace::enter("srgan process");
     let child = Command::new("/opt/thalamus/bin/srgan")
     .arg(input)
     .arg(output)
     .stdout(Stdio::piped())
-    .spawn()
-    .expect("failed to execute child");
+    .stderr(Stdio::piped())
+    .process_group(0)
+    .spawn();
+    let child = spawned(child, "srgan")?;
+    let _watch = crate::thalamus::jobs::watch(&child);
 
 
     let output = child
-    .wait_with_output()
-    .expect("failed to wait on child");
+    .wait_with_output()?;
+    crate::logging::child_output("srgan", &output.stderr);
 
     return Ok(String::from_utf8_lossy(&output.stdout).to_string()); 
 }
@@ -529,14 +539,19 @@ pub fn safe_download(file_path: &str, online_path: &str, hash: Option<&str>, exp
         match crate::thalamus::tools::wget(file_path, online_path){
             Ok(success) => {
                 if success {
+                    crate::thalamus::metrics::download("ok");
                     log::info!("{} downloaded successfully", file_path);
                 } else {
+                    crate::thalamus::metrics::download("failed");
                     log::warn!("{} failed to download...trying again", file_path);
                     safe_download(file_path, online_path, hash, expected_file_size);
                 }
             
             },
-            Err(_) => return log::error!("failed to download {} from {}", file_path, online_path),
+            Err(_) => {
+                crate::thalamus::metrics::download("failed");
+                return log::error!("failed to download {} from {}", file_path, online_path);
+            },
         }
     } else {
         // hash check
@@ -545,6 +560,7 @@ pub fn safe_download(file_path: &str, online_path: &str, hash: Option<&str>, exp
         match expected_file_size{
             Some(x_file_size) => {
                 if x_file_size == crate::thalamus::tools::get_file_size(file_path).unwrap(){
+                    crate::thalamus::metrics::hash_check("skipped");
                     log::info!("{} file size matches expectations...skiping hash check", file_path);
                     needs_hashing = false;
                 } else {
@@ -561,14 +577,17 @@ pub fn safe_download(file_path: &str, online_path: &str, hash: Option<&str>, exp
             match hash {
                 Some(xhash) => {
                     if xhash == crate::thalamus::tools::hash_check(file_path).unwrap().as_str(){
+                        crate::thalamus::metrics::hash_check("passed");
                         log::info!("{} is already downloaded and passes the hash check", file_path);
                     } else {
+                        crate::thalamus::metrics::hash_check("failed");
                         log::warn!("{} is already downloaded and fails the hash check", file_path);
                         safe_download(file_path, online_path, hash, expected_file_size);
                     }
     
                 },
                 None => {
+                    crate::thalamus::metrics::hash_check("unknown");
                     log::info!("{} is already downloaded....no known hash....downloaded hash is: {}", file_path, crate::thalamus::tools::hash_check(file_path).unwrap());
                 },
             }
@@ -585,13 +604,12 @@ pub fn wget(file_path: &str, url: &str) -> Result<bool>{
     .arg(file_path)
     .arg(url)
     .stdout(Stdio::piped())
-    .spawn()
-    .expect("failed to execute child");
+    .spawn();
+    let child = spawned(child, "wget")?;
 
 
     let _output = child
-    .wait_with_output()
-    .expect("failed to wait on child");
+    .wait_with_output()?;
 
     return Ok(true);
 }
@@ -602,18 +620,18 @@ pub fn download(file_path: &str, url: &str) -> Result<bool>{
     .arg(file_path)
     .arg(url)
     .stdout(Stdio::piped())
-    .spawn()
-    .expect("failed to execute child");
+    .spawn();
+    let child = spawned(child, "wget")?;
 
 
     let _output = child
-    .wait_with_output()
-    .expect("failed to wait on child");
+    .wait_with_output()?;
 
     return Ok(true);
 }
 
 pub fn wav_to_16000(input: String) -> Result<String>{
+    let _span = crate::trace::enter("ffmpeg convert");
     let child = Command::new("/opt/thalamus/bin/ffmpeg")
     .arg("-y")
     .arg("-i")
@@ -626,13 +644,16 @@ pub fn wav_to_16000(input: String) -> Result<String>{
     .arg("pcm_s16le")
     .arg(format!("{}.16.wav", input))
     .stdout(Stdio::piped())
-    .spawn()
-    .expect("failed to execute child");
+    .stderr(Stdio::piped())
+    .process_group(0)
+    .spawn();
+    let child = spawned(child, "ffmpeg")?;
+    let _watch = crate::thalamus::jobs::watch(&child);
 
 
     let output = child
-    .wait_with_output()
-    .expect("failed to wait on child");
+    .wait_with_output()?;
+    crate::logging::child_output("ffmpeg", &output.stderr);
 
     return Ok(String::from_utf8_lossy(&output.stdout).to_string());
 }
@@ -651,19 +672,24 @@ pub fn touch(path: String) -> Result<()>{
 
 
 pub fn llama(model: &str, prompt: &str) -> Result<String>{
+    let mut span = crate::trace::enter("llama process");
+    span.attr("model", model);
     let child = Command::new("/opt/thalamus/bin/llama")
     .arg("-m")
     .arg(format!("/opt/thalamus/models/llama/{}/ggml-model-q4_0.gguf", model))
     .arg("-p")
     .arg(format!("\"{}\"", prompt))
     .stdout(Stdio::piped())
-    .spawn()
-    .expect("failed to execute child");
+    .stderr(Stdio::piped())
+    .process_group(0)
+    .spawn();
+    let child = spawned(child, "llama")?;
+    let _watch = crate::thalamus::jobs::watch(&child);
 
 
     let output = child
-    .wait_with_output()
-    .expect("failed to wait on child");
+    .wait_with_output()?;
+    crate::logging::child_output("llama", &output.stderr);
 
     return Ok(String::from_utf8_lossy(&output.stdout).to_string());    
     
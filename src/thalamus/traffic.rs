@@ -0,0 +1,138 @@
+// ████████ ██   ██  █████  ██       █████  ███    ███ ██    ██ ███████ 
+//    ██    ██   ██ ██   ██ ██      ██   ██ ████  ████ ██    ██ ██      
+//    ██    ███████ ███████ ██      ███████ ██ ████ ██ ██    ██ ███████ 
+//    ██    ██   ██ ██   ██ ██      ██   ██ ██  ██  ██ ██    ██      ██ 
+//    ██    ██   ██ ██   ██ ███████ ██   ██ ██      ██  ██████  ███████                                                                             
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+
+// Rolling statistics from served requests
+// Every request this node serves, over http or forwarded through p2p, is tracked per service
+// and model (e.g. "whisper:base", "llama:7B", "tts") and published on the node record.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Serialize, Deserialize};
+
+// Weight of the newest sample in the latency and error rate averages
+const LATENCY_ALPHA: f64 = 0.2;
+const ERROR_ALPHA: f64 = 0.1;
+
+// Time constant of the decaying completion counter, in seconds
+const THROUGHPUT_TAU: f64 = 60.0;
+
+/// Rolling stats for one service and model
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct RollingStats {
+    /// EWMA of request latency in milliseconds
+    pub latency_ms: Option<f64>,
+    /// Completed requests per minute, decaying when idle
+    pub throughput: f64,
+    /// EWMA of the failed fraction of requests
+    pub error_rate: f64,
+    /// Requests running or waiting right now
+    pub queue_depth: u32,
+    pub requests: u64,
+    pub errors: u64,
+    pub updated_at: i64,
+    #[serde(skip)]
+    decayed_at: Option<Instant>,
+}
+impl RollingStats {
+    // Decays the completion counter up to now
+    fn decay(&mut self, now: Instant) {
+        match self.decayed_at {
+            Some(then) => {
+                let dt = now.duration_since(then).as_secs_f64();
+                self.throughput *= (-dt / THROUGHPUT_TAU).exp();
+            },
+            None => {}
+        }
+        self.decayed_at = Some(now);
+    }
+
+    fn record(&mut self, latency_ms: f64, ok: bool) {
+        let now = Instant::now();
+        self.decay(now);
+        // A counter decaying with time constant tau, read per minute
+        self.throughput += 60.0 / THROUGHPUT_TAU;
+        self.latency_ms = match self.latency_ms {
+            Some(average) => Some(average + LATENCY_ALPHA * (latency_ms - average)),
+            None => Some(latency_ms),
+        };
+        let failed = if ok { 0.0 } else { 1.0 };
+        self.error_rate += ERROR_ALPHA * (failed - self.error_rate);
+        self.requests += 1;
+        if !ok {
+            self.errors += 1;
+        }
+        self.updated_at = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0);
+    }
+}
+
+static TRAFFIC: OnceLock<Mutex<HashMap<String, RollingStats>>> = OnceLock::new();
+
+fn traffic() -> &'static Mutex<HashMap<String, RollingStats>> {
+    TRAFFIC.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// An in-flight request; counts as failed unless done() is called
+pub struct Tracker {
+    key: String,
+    started: Instant,
+    ok: bool,
+    cancelled: bool,
+}
+impl Tracker {
+    pub fn done(mut self) {
+        self.ok = true;
+    }
+
+    /// The caller gave up; leaves the queue without counting for or against the node
+    pub fn cancelled(mut self) {
+        self.cancelled = true;
+    }
+}
+impl Drop for Tracker {
+    fn drop(&mut self) {
+        let elapsed = self.started.elapsed();
+        let mut traffic = traffic().lock().unwrap();
+        let stats = traffic.entry(self.key.clone()).or_default();
+        stats.queue_depth = stats.queue_depth.saturating_sub(1);
+        if self.cancelled {
+            return;
+        }
+        stats.record(elapsed.as_secs_f64() * 1000.0, self.ok);
+        std::mem::drop(traffic);
+        crate::thalamus::metrics::service_request(self.key.as_str(), self.ok, elapsed);
+    }
+}
+
+/// Starts tracking a request for a service and model key
+pub fn begin(key: String) -> Tracker {
+    let mut traffic = traffic().lock().unwrap();
+    traffic.entry(key.clone()).or_default().queue_depth += 1;
+    std::mem::drop(traffic);
+    Tracker {
+        key: key,
+        started: Instant::now(),
+        ok: false,
+        cancelled: false,
+    }
+}
+
+/// Current stats for every key served so far
+pub fn snapshot() -> BTreeMap<String, RollingStats> {
+    let now = Instant::now();
+    let mut traffic = traffic().lock().unwrap();
+    let mut snapshot = BTreeMap::new();
+    for (key, stats) in traffic.iter_mut() {
+        stats.decay(now);
+        snapshot.insert(key.clone(), stats.clone());
+    }
+    return snapshot;
+}
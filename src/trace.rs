@@ -0,0 +1,322 @@
+// ████████ ██   ██  █████  ██       █████  ███    ███ ██    ██ ███████ 
+//    ██    ██   ██ ██   ██ ██      ██   ██ ████  ████ ██    ██ ██      
+//    ██    ███████ ███████ ██      ███████ ██ ████ ██ ██    ██ ███████ 
+//    ██    ██   ██ ██   ██ ██      ██   ██ ██  ██  ██ ██    ██      ██ 
+//    ██    ██   ██ ██   ██ ███████ ██   ██ ██      ██  ██████  ███████                                                                             
+// Copyright 2021-2023 The Open Sam Foundation (OSF)
+// Developed by Caleb Mitchell Smith (PixelCoda)
+// Licensed under GPLv3....see LICENSE file.
+
+
+// Distributed tracing
+// W3C traceparent headers are accepted on http and p2p requests and sent on every call to
+// another node, so one trace follows a job across the mesh. Spans are exported over
+// OTLP/HTTP (json) when --otlp-endpoint is set; without it ids are still propagated.
+
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use rand::Rng;
+use serde_json::{json, Value};
+
+// Spans sent per export call, and how long a partial batch may wait
+const BATCH_SIZE: usize = 512;
+const BATCH_DELAY: Duration = Duration::from_secs(5);
+
+// Finished spans waiting for the exporter; when a slow collector lets it fill, new spans are dropped
+const QUEUE_SIZE: usize = 4096;
+
+/// The part of a span that crosses process boundaries
+#[derive(Debug, Clone, PartialEq)]
+pub struct Context {
+    pub trace_id: String,
+    pub span_id: String,
+    pub sampled: bool,
+}
+impl Context {
+    /// Parses a W3C traceparent header, e.g. 00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01
+    pub fn parse(traceparent: &str) -> Option<Context> {
+        let parts: Vec<&str> = traceparent.trim().split('-').collect();
+        if parts.len() < 4 || parts[0].len() != 2 || parts[0] == "ff" {
+            return None;
+        }
+        // Version 00 has exactly four fields; later versions may append more
+        if parts[0] == "00" && parts.len() != 4 {
+            return None;
+        }
+        let (trace_id, span_id, flags) = (parts[1], parts[2], parts[3]);
+        if !is_hex(trace_id, 32) || !is_hex(span_id, 16) || !is_hex(flags, 2) {
+            return None;
+        }
+        if trace_id.chars().all(|c| c == '0') || span_id.chars().all(|c| c == '0') {
+            return None;
+        }
+        let flags = u8::from_str_radix(flags, 16).ok()?;
+        Some(Context {
+            trace_id: trace_id.to_lowercase(),
+            span_id: span_id.to_lowercase(),
+            sampled: flags & 1 == 1,
+        })
+    }
+
+    pub fn traceparent(&self) -> String {
+        format!("00-{}-{}-{}", self.trace_id, self.span_id, if self.sampled { "01" } else { "00" })
+    }
+}
+
+fn is_hex(value: &str, len: usize) -> bool {
+    value.len() == len && value.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn random_id(bytes: usize) -> String {
+    let mut rng = rand::thread_rng();
+    (0..bytes).map(|_| format!("{:02x}", rng.gen::<u8>())).collect()
+}
+
+fn now_nanos() -> u128 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0)
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<Context>> = RefCell::new(None);
+}
+
+/// The span active on this thread, if any
+pub fn current() -> Option<Context> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// traceparent header for a call made from this thread
+pub fn traceparent() -> Option<String> {
+    current().map(|context| context.traceparent())
+}
+
+/// OTLP span kinds
+#[derive(Debug, Clone, Copy)]
+pub enum Kind {
+    Internal = 1,
+    Server = 2,
+    Client = 3,
+}
+
+/// A timed operation; recorded when dropped
+pub struct Span {
+    pub context: Context,
+    parent_span_id: Option<String>,
+    name: String,
+    kind: Kind,
+    start: u128,
+    started: Instant,
+    attributes: Vec<(String, String)>,
+    error: Option<String>,
+    // Set when this span is the thread's current span; restored on drop
+    previous: Option<Option<Context>>,
+}
+impl Span {
+    fn new(name: String, kind: Kind, parent: Option<Context>) -> Span {
+        let (trace_id, parent_span_id, sampled) = match parent {
+            Some(parent) => (parent.trace_id, Some(parent.span_id), parent.sampled),
+            None => (random_id(16), None, true),
+        };
+        Span {
+            context: Context { trace_id: trace_id, span_id: random_id(8), sampled: sampled },
+            parent_span_id: parent_span_id,
+            name: name,
+            kind: kind,
+            start: now_nanos(),
+            started: Instant::now(),
+            attributes: Vec::new(),
+            error: None,
+            previous: None,
+        }
+    }
+
+    // Makes this span the parent of spans started on this thread until it is dropped
+    fn enter(mut self) -> Span {
+        let context = self.context.clone();
+        self.previous = Some(CURRENT.with(|current| current.borrow_mut().replace(context)));
+        self
+    }
+
+    pub fn attr<V: ToString>(&mut self, key: &str, value: V) {
+        self.attributes.push((key.to_string(), value.to_string()));
+    }
+
+    /// Marks the span as failed
+    pub fn error<E: ToString>(&mut self, error: E) {
+        self.error = Some(error.to_string());
+    }
+
+    pub fn traceparent(&self) -> String {
+        self.context.traceparent()
+    }
+}
+impl Drop for Span {
+    fn drop(&mut self) {
+        match self.previous.take() {
+            Some(previous) => CURRENT.with(|current| *current.borrow_mut() = previous),
+            None => {}
+        }
+        if !self.context.sampled {
+            return;
+        }
+        let exporter = match EXPORTER.get() {
+            Some(exporter) => exporter,
+            None => return,
+        };
+        let end = self.start + self.started.elapsed().as_nanos();
+        let mut span = json!({
+            "traceId": self.context.trace_id,
+            "spanId": self.context.span_id,
+            "name": self.name,
+            "kind": self.kind as i32,
+            "startTimeUnixNano": format!("{}", self.start),
+            "endTimeUnixNano": format!("{}", end),
+            "attributes": attributes(&self.attributes),
+            "status": match &self.error {
+                Some(error) => json!({ "code": 2, "message": error }),
+                None => json!({ "code": 1 }),
+            },
+        });
+        match &self.parent_span_id {
+            Some(parent) => { span["parentSpanId"] = Value::String(parent.clone()); },
+            None => {}
+        }
+        match exporter.try_send(span) {
+            Ok(_) => {},
+            Err(TrySendError::Full(_)) => {
+                DROPPED.fetch_add(1, Ordering::Relaxed);
+                crate::thalamus::metrics::trace_span_dropped();
+            },
+            Err(TrySendError::Disconnected(_)) => {},
+        }
+    }
+}
+
+/// Starts a span for an inbound request, continuing the caller's trace when a valid
+/// traceparent is given, and makes it current on this thread
+pub fn accept(traceparent: Option<&str>, name: String) -> Span {
+    let parent = traceparent.and_then(Context::parse);
+    Span::new(name, Kind::Server, parent).enter()
+}
+
+/// Starts a child of the current span and makes it current on this thread
+pub fn enter(name: &str) -> Span {
+    Span::new(name.to_string(), Kind::Internal, current()).enter()
+}
+
+/// Starts a span for a call to another node. It is not made current, so it can be held
+/// across awaits; send span.traceparent() with the call.
+pub fn client(name: String, pid: &str) -> Span {
+    let mut span = Span::new(name, Kind::Client, current());
+    span.attr("peer.pid", pid);
+    span
+}
+
+/// Writes a file inside a span
+pub fn write_file(path: &str, data: &[u8]) -> std::io::Result<()> {
+    let mut span = enter("file write");
+    span.attr("file.path", path);
+    span.attr("file.bytes", data.len());
+    let result = std::fs::write(path, data);
+    match &result {
+        Ok(_) => {},
+        Err(e) => span.error(e),
+    }
+    return result;
+}
+
+/// Reads a file inside a span
+pub fn read_file(path: &str) -> std::io::Result<Vec<u8>> {
+    let mut span = enter("file read");
+    span.attr("file.path", path);
+    let result = std::fs::read(path);
+    match &result {
+        Ok(data) => span.attr("file.bytes", data.len()),
+        Err(e) => span.error(e),
+    }
+    return result;
+}
+
+fn attributes(pairs: &[(String, String)]) -> Value {
+    Value::Array(pairs.iter().map(|(key, value)| json!({ "key": key, "value": { "stringValue": value } })).collect())
+}
+
+static EXPORTER: OnceLock<SyncSender<Value>> = OnceLock::new();
+
+// Spans dropped because the export queue was full
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Starts exporting spans to an OTLP/HTTP collector, e.g. http://localhost:4318
+pub fn init(endpoint: &str) {
+    let url = format!("{}/v1/traces", endpoint.trim_end_matches('/'));
+    let (sender, receiver) = mpsc::sync_channel(QUEUE_SIZE);
+    if EXPORTER.set(sender).is_err() {
+        return;
+    }
+    let _exporter = std::thread::Builder::new().name("otlp_exporter".to_string()).spawn(move || export(url, receiver));
+    log::warn!("Exporting traces to {}", endpoint);
+}
+
+fn export(url: String, receiver: Receiver<Value>) {
+    let client = match reqwest::blocking::Client::builder().timeout(Duration::from_secs(10)).build() {
+        Ok(client) => client,
+        Err(e) => return log::error!("Unable to start the trace exporter: {}", e),
+    };
+
+    let mut instance = std::fs::read_to_string("/opt/thalamus/pid").unwrap_or_default();
+    instance = instance.trim().to_string();
+    let resource = json!({
+        "attributes": attributes(&[
+            (format!("service.name"), format!("thalamus")),
+            (format!("service.version"), format!("{}", env!("CARGO_PKG_VERSION"))),
+            (format!("service.instance.id"), instance),
+        ]),
+    });
+
+    let mut batch: Vec<Value> = Vec::new();
+    let mut deadline = Instant::now() + BATCH_DELAY;
+    let mut reported_dropped = 0;
+    loop {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        let disconnected = match receiver.recv_timeout(timeout) {
+            Ok(span) => {
+                batch.push(span);
+                false
+            },
+            Err(RecvTimeoutError::Timeout) => false,
+            Err(RecvTimeoutError::Disconnected) => true,
+        };
+
+        if batch.len() >= BATCH_SIZE || Instant::now() >= deadline || disconnected {
+            while !batch.is_empty() {
+                let spans: Vec<Value> = batch.drain(..batch.len().min(BATCH_SIZE)).collect();
+                let body = json!({
+                    "resourceSpans": [{
+                        "resource": resource,
+                        "scopeSpans": [{ "scope": { "name": "thalamus" }, "spans": spans }],
+                    }],
+                });
+                match client.post(url.as_str()).json(&body).send() {
+                    Ok(response) if response.status().is_success() => {},
+                    Ok(response) => log::debug!("Trace collector rejected {} spans: {}", spans.len(), response.status()),
+                    Err(e) => log::debug!("Unable to reach trace collector: {}", e),
+                }
+            }
+            deadline = Instant::now() + BATCH_DELAY;
+
+            let dropped = DROPPED.load(Ordering::Relaxed);
+            if dropped > reported_dropped {
+                log::warn!("Trace export queue full, dropped {} spans", dropped - reported_dropped);
+                reported_dropped = dropped;
+            }
+        }
+
+        if disconnected {
+            return;
+        }
+    }
+}